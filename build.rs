@@ -0,0 +1,7 @@
+fn main() {
+    // The updater picks the release asset built for the same target triple
+    println!(
+        "cargo:rustc-env=TARGET={}",
+        std::env::var("TARGET").unwrap()
+    );
+}
@@ -1,3 +1,5 @@
+use crate::helper;
+use crate::os;
 use crate::ui;
 use cursive::event::Key;
 use cursive::Cursive;
@@ -18,10 +20,38 @@ impl App {
         self.cursive.add_global_callback(Key::Esc, Cursive::quit);
     }
 
-    pub fn dispatch(&mut self) {
+    pub fn dispatch(mut self) {
         self.set_global_callback();
 
         ui::main(&mut self.cursive);
-        self.cursive.run();
+        ui::run_startup_checks(&mut self.cursive);
+        loop {
+            self.cursive.run();
+
+            match ui::take_escalation() {
+                ui::Escalation::None => return,
+                ui::Escalation::Relaunch => {
+                    // Give the terminal back before the elevated instance takes it
+                    drop(self.cursive);
+                    match os::run_as_admin() {
+                        Ok(code) => std::process::exit(code),
+                        Err(err) => {
+                            eprintln!("{}", err);
+                            std::process::exit(1);
+                        }
+                    }
+                }
+                ui::Escalation::StartHelper => {
+                    // sudo may ask for a password on the terminal
+                    drop(self.cursive);
+                    println!("Starting the helper changing the hosts file as root");
+                    let started = helper::start();
+
+                    self = App::new();
+                    self.set_global_callback();
+                    ui::resume(&mut self.cursive, started);
+                }
+            }
+        }
     }
 }
@@ -0,0 +1,243 @@
+use crate::error::ErrorKind;
+use crate::os::{open_options, set_mode, write_file};
+use crate::parser::Hosts;
+use crate::target;
+use crate::{signature, state};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::io::{BufRead as _, BufReader, Write as _};
+use std::path::Path;
+
+const LOG_FILE: &str = "audit.log";
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum Operation {
+    Install,
+    Uninstall,
+    /// The original hosts file put back from its backup
+    Restore,
+    ListUpdate,
+    CustomImport,
+}
+
+impl Operation {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Operation::Install => "install",
+            Operation::Uninstall => "uninstall",
+            Operation::Restore => "restore",
+            Operation::ListUpdate => "list-update",
+            Operation::CustomImport => "custom-import",
+        }
+    }
+}
+
+/// A single line of the audit log
+#[derive(Serialize, Deserialize)]
+pub struct Entry {
+    pub timestamp: String,
+    pub user: String,
+    pub operation: Operation,
+    pub source: String,
+    pub added: usize,
+    pub removed: usize,
+    pub checksum_before: Option<String>,
+    pub checksum_after: Option<String>,
+}
+
+/// The state of the file of the install target at some point of time
+pub struct Snapshot {
+    checksum: Option<String>,
+    entries: HashSet<(String, String)>,
+}
+
+impl Snapshot {
+    /// Take a snapshot of the file of the install target, the system hosts file by default.
+    /// A missing or unparseable file results in an empty snapshot.
+    pub fn take() -> Snapshot {
+        let target = target::configured();
+        let content = match fs::read_to_string(target.path()) {
+            Ok(content) => content,
+            Err(_) => {
+                return Snapshot {
+                    checksum: None,
+                    entries: HashSet::new(),
+                }
+            }
+        };
+        let entries = match target.parse(&content) {
+            Ok(hosts) => flatten(&hosts),
+            Err(_) => HashSet::new(),
+        };
+
+        Snapshot {
+            checksum: Some(signature::sha256(content.as_bytes())),
+            entries,
+        }
+    }
+}
+
+fn flatten(hosts: &Hosts) -> HashSet<(String, String)> {
+    let mut entries = HashSet::new();
+    for (ip, hostnames) in hosts {
+        for hostname in hostnames {
+            entries.insert((ip.to_owned(), hostname.to_owned()));
+        }
+    }
+    entries
+}
+
+fn invoking_user() -> String {
+    // When running through sudo, the interesting user is the one who called sudo
+    ["SUDO_USER", "USER", "USERNAME"]
+        .iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|user| !user.is_empty())
+        .unwrap_or_else(|| String::from("unknown"))
+}
+
+/// Record a change that has been made already, a failure only adds a warning to `summary`
+pub fn record_with_warning(
+    summary: &str,
+    operation: Operation,
+    source: &str,
+    before: Snapshot,
+) -> String {
+    match record(operation, source, before) {
+        Ok(_) => summary.to_owned(),
+        Err(err) => format!(
+            "{}\n\nWarning: the change could not be written to the audit log\n{}",
+            summary, err
+        ),
+    }
+}
+
+/// Append the change between `before` and the current file of the install target to the audit log
+pub fn record(operation: Operation, source: &str, before: Snapshot) -> Result<(), ErrorKind> {
+    let after = Snapshot::take();
+    let entry = Entry {
+        timestamp: chrono::Local::now().to_rfc3339(),
+        user: invoking_user(),
+        operation,
+        source: source.to_owned(),
+        added: after.entries.difference(&before.entries).count(),
+        removed: before.entries.difference(&after.entries).count(),
+        checksum_before: before.checksum,
+        checksum_after: after.checksum,
+    };
+
+    let line = match serde_json::to_string(&entry) {
+        Ok(line) => line,
+        Err(err) => return Err(ErrorKind::SerdeJSONError(err)),
+    };
+
+    state::ensure()?;
+    let mut file = match open_options(state::FILE_MODE)
+        .create(true)
+        .append(true)
+        .open(state::path(LOG_FILE))
+    {
+        Ok(file) => file,
+        Err(err) => return Err(ErrorKind::IOError(err)),
+    };
+    // The mode only applies when the log is created, a log created with another mode is
+    // fixed so that the other users can read it from the Log screen
+    set_mode(&file, state::FILE_MODE)?;
+    match writeln!(file, "{}", line) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(ErrorKind::IOError(err)),
+    }
+}
+
+/// Whether the file of the install target changed since the last change recorded in the audit log.
+/// Without any recorded change there is nothing to compare with.
+pub fn is_hosts_file_modified() -> Result<bool, ErrorKind> {
+    match entries()?.pop() {
+        Some(last) => Ok(last.checksum_after != Snapshot::take().checksum),
+        None => Ok(false),
+    }
+}
+
+/// Read every entry of the audit log, oldest first
+pub fn entries() -> Result<Vec<Entry>, ErrorKind> {
+    let file = match fs::File::open(state::path(LOG_FILE)) {
+        Ok(file) => file,
+        Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(ErrorKind::IOError(err)),
+    };
+
+    let mut entries = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(err) => return Err(ErrorKind::IOError(err)),
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<Entry>(&line) {
+            Ok(entry) => entries.push(entry),
+            Err(err) => return Err(ErrorKind::SerdeJSONError(err)),
+        }
+    }
+    Ok(entries)
+}
+
+/// Write the audit log as JSON lines
+pub fn export<W: std::io::Write>(writer: &mut W) -> Result<(), ErrorKind> {
+    for entry in entries()? {
+        let line = match serde_json::to_string(&entry) {
+            Ok(line) => line,
+            Err(err) => return Err(ErrorKind::SerdeJSONError(err)),
+        };
+        if let Err(err) = writeln!(writer, "{}", line) {
+            return Err(ErrorKind::IOError(err));
+        }
+    }
+    Ok(())
+}
+
+/// Write the audit log to `path`, refusing to follow a symbolic link planted there
+pub fn export_to_file(path: &str) -> Result<(), ErrorKind> {
+    let mut content = Vec::new();
+    export(&mut content)?;
+    write_file(Path::new(path), &content, state::FILE_MODE)
+}
+
+impl Entry {
+    /// Human readable form of the entry, used by the TUI and the CLI
+    pub fn describe(&self) -> String {
+        format!(
+            "{} {} by {} from {} (+{} -{})",
+            self.timestamp,
+            self.operation.as_str(),
+            self.user,
+            self.source,
+            self.added,
+            self.removed
+        )
+    }
+}
+
+#[cfg(all(test, any(target_os = "linux", target_os = "macos")))]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn exports_refuse_symbolic_links() {
+        let directory = TempDir::new("audit-export");
+        let victim = directory.path.join("victim");
+        fs::write(&victim, "untouched").unwrap();
+        let link = directory.path.join("audit.jsonl");
+        std::os::unix::fs::symlink(&victim, &link).unwrap();
+
+        match export_to_file(&link.display().to_string()) {
+            Err(ErrorKind::String(_)) => {}
+            result => panic!("unexpected result {:?}", result.map(|_| ())),
+        }
+        assert_eq!(fs::read_to_string(&victim).unwrap(), "untouched");
+    }
+}
@@ -0,0 +1,166 @@
+use crate::config::Config;
+use crate::list;
+use crate::progress::Progress;
+use crate::state;
+use crate::updater::Updater;
+use crate::version::Version;
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+const STATUS_FILE: &str = "update-check.json";
+
+/// What the last automatic update check found
+#[derive(Serialize, Deserialize)]
+pub struct Status {
+    /// Unix timestamp of the check
+    checked_at: i64,
+    /// Version of the application available to update to
+    application: Option<String>,
+    /// Whether that version fixes something critical
+    #[serde(default)]
+    critical: bool,
+    /// The oldest version of the application the project still supports
+    #[serde(default)]
+    minimum_supported_version: Option<String>,
+    /// Checksum of the latest hosts list
+    hosts_list_checksum: Option<String>,
+}
+
+impl Status {
+    fn load() -> Option<Status> {
+        let content = fs::read_to_string(state::cache_path(STATUS_FILE)).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    fn save(&self) {
+        // Without the status the next start just checks again
+        if let (Ok(_), Ok(content)) = (state::ensure_cache(), serde_json::to_string(self)) {
+            state::write(&state::cache_path(STATUS_FILE), content.as_bytes()).ok();
+        }
+    }
+
+    fn is_due(&self, interval: u64) -> bool {
+        let elapsed = chrono::Utc::now().timestamp() - self.checked_at;
+        elapsed < 0 || elapsed as u64 >= interval * 60 * 60
+    }
+
+    /// The version to update to, as long as it hasn't been installed since the check
+    pub fn application_update(&self) -> Option<&str> {
+        let version = self.application.as_deref()?;
+        match Version::parse(version) {
+            Ok(parsed_version) if parsed_version != Version::current() => Some(version),
+            _ => None,
+        }
+    }
+
+    /// Whether the version to update to fixes something critical
+    pub fn is_critical(&self) -> bool {
+        self.critical && self.application_update().is_some()
+    }
+
+    /// The oldest supported version, when the running one is older
+    pub fn unsupported(&self) -> Option<&str> {
+        let version = self.minimum_supported_version.as_deref()?;
+        match Version::parse(version) {
+            Ok(parsed_version) if Version::current() < parsed_version => Some(version),
+            _ => None,
+        }
+    }
+
+    /// Whether the hosts list in use differs from the latest one
+    pub fn hosts_list_update(&self) -> bool {
+        match &self.hosts_list_checksum {
+            Some(checksum) => *checksum != list::current_checksum(),
+            None => false,
+        }
+    }
+}
+
+/// The result of the last check, checking again when it is older than the configured interval.
+/// `None` when the automatic check is disabled or nothing could be checked.
+pub fn run() -> Option<Status> {
+    let config = Config::load().unwrap_or_default();
+    if config.update_check_interval == 0 {
+        return None;
+    }
+    if let Some(status) = Status::load() {
+        if !status.is_due(config.update_check_interval) {
+            return Some(status);
+        }
+    }
+
+    let mut updater_instance = Updater::new();
+    let application = match updater_instance.get_latest_info() {
+        Ok(latest) if updater_instance.is_updatable() => {
+            Ok(Some((latest.version.to_string(), latest.critical)))
+        }
+        Ok(_) => Ok(None),
+        Err(err) => Err(err),
+    };
+    let hosts_list_checksum =
+        list::fetch(&config, &Progress::none()).map(|fetched| fetched.info.checksum);
+    // Most likely offline, try again on the next start
+    if application.is_err() && hosts_list_checksum.is_err() {
+        return None;
+    }
+
+    let (application, critical) = match application {
+        Ok(Some((version, critical))) => (Some(version), critical),
+        _ => (None, false),
+    };
+    let status = Status {
+        checked_at: chrono::Utc::now().timestamp(),
+        application,
+        critical,
+        minimum_supported_version: updater_instance
+            .minimum_supported_version()
+            .map(Version::to_string),
+        hosts_list_checksum: hosts_list_checksum.ok(),
+    };
+    status.save();
+    Some(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(application: Option<&str>, critical: bool, minimum: Option<&str>) -> Status {
+        Status {
+            checked_at: 0,
+            application: application.map(str::to_owned),
+            critical,
+            minimum_supported_version: minimum.map(str::to_owned),
+            hosts_list_checksum: None,
+        }
+    }
+
+    #[test]
+    fn critical_updates_are_reported_until_installed() {
+        assert!(status(Some("999.0.0"), true, None).is_critical());
+        assert!(!status(Some("999.0.0"), false, None).is_critical());
+        let current = Version::current().to_string();
+        assert!(!status(Some(&current), true, None).is_critical());
+    }
+
+    #[test]
+    fn older_versions_are_unsupported() {
+        assert_eq!(
+            status(None, false, Some("999.0.0")).unsupported(),
+            Some("999.0.0")
+        );
+        assert_eq!(
+            status(None, false, Some("201901010000")).unsupported(),
+            None
+        );
+        assert_eq!(status(None, false, None).unsupported(), None);
+    }
+
+    #[test]
+    fn statuses_of_previous_releases_are_read() {
+        let status: Status =
+            serde_json::from_str(r#"{"checked_at":1,"application":"999.0.0"}"#).unwrap();
+        assert!(!status.is_critical());
+        assert_eq!(status.unsupported(), None);
+    }
+}
@@ -0,0 +1,522 @@
+use crate::audit;
+use crate::config::Config;
+use crate::dns;
+use crate::doctor;
+use crate::doh;
+use crate::error::ErrorKind;
+use crate::export;
+use crate::helper;
+use crate::import::{self, Format, DEFAULT_TARGET};
+use crate::list;
+use crate::os::is_has_admin_access;
+use crate::parser;
+use crate::progress::Progress;
+use crate::rollback;
+use crate::schedule;
+use crate::subscription::{self, SUBSCRIPTIONS_SOURCE};
+use crate::target;
+use crate::transport;
+use crate::updater::is_installed;
+use crate::updater::Updater;
+use crate::verify;
+use crate::{CURRENT_VERSION, TARGET};
+use std::net::SocketAddr;
+use std::path::Path;
+
+const USAGE: &str = "Usage: bebasin [COMMAND]
+
+Run without any command to open the interactive menu.
+
+Commands:
+    dns [--listen ADDRESS] [--upstream ADDRESS]
+                            Answer DNS queries from the hosts list and the subscriptions,
+                            forwarding the other names to the upstream server
+    doctor                  Look for reasons the hosts file could be ignored
+    doh [disable]           List the browsers bypassing the hosts file with DNS over HTTPS,
+                            or turn it off for them with browser policies
+    export sections         List the sections of the hosts list
+    export FORMAT [--section NAME]... [--output PATH]
+                            Export the sections of the hosts list, all by default, and the
+                            subscriptions to dnsmasq, unbound, rpz, coredns or pihole
+    helper                  Change the hosts file on behalf of the interactive menu,
+                            reading requests from the standard input
+    import [--format FORMAT] [--target IP] LOCATION
+                            Convert a blocklist from an URL or a local path to hosts entries,
+                            FORMAT is one of hosts, domains, adblock, dnsmasq, unbound or rpz
+    log [--json]            Show every change made to the hosts file
+    refresh                 Update the hosts list without any interaction
+    rollback [VERSION]      List the previous versions, or go back to one of them
+    schedule install [HOURS]
+                            Refresh the hosts list in the background, every 24 hours by default
+    schedule remove         Stop refreshing the hosts list in the background
+    subscription [list]     List the custom hosts lists merged with the Bebasin one
+    subscription add NAME LOCATION
+                            Subscribe to a custom hosts list from an URL or a local path
+    subscription remove NAME
+                            Unsubscribe from a custom hosts list
+    subscription refresh    Download the custom hosts lists again
+    target [list]           List where the hosts list can be installed
+    target set TARGET       Install to hosts-file, dnsmasq, unbound or network-manager,
+                            Bebasin has to be uninstalled first
+    target reload           Restart the DNS server of the target and flush the DNS caches
+    update [--from ARCHIVE] Update the application, or install a downloaded release archive
+    verify                  Check that the hosts file is in effect
+    version                 Show the version of Bebasin
+    help                    Show this message";
+
+fn parse_address(address: &str) -> Result<SocketAddr, ErrorKind> {
+    match address.parse::<SocketAddr>() {
+        Ok(address) => Ok(address),
+        Err(_) => Err(ErrorKind::String(format!(
+            "Invalid address '{}', expected an IP and a port",
+            address
+        ))),
+    }
+}
+
+fn dns(args: &[String]) -> Result<(), ErrorKind> {
+    let config = Config::load()?;
+    let mut listen = config.dns_listen;
+    let mut upstream = config.dns_upstream;
+    let mut index = 0;
+    while index < args.len() {
+        match (args[index].as_str(), args.get(index + 1)) {
+            ("--listen", Some(address)) => listen = address.to_owned(),
+            ("--upstream", Some(address)) => upstream = address.to_owned(),
+            (arg, _) => {
+                return Err(ErrorKind::String(format!(
+                    "Unknown argument '{}'\n\n{}",
+                    arg, USAGE
+                )))
+            }
+        }
+        // An option and its value
+        index += 2;
+    }
+    let listen = parse_address(&listen)?;
+    let upstream = parse_address(&upstream)?;
+
+    let server = dns::Server::new(&export::mapping(&[])?, upstream);
+    let listeners = dns::Listeners::bind(listen)?;
+    println!(
+        "Answering DNS queries on {}, forwarding to {}",
+        listeners.local_addr()?,
+        upstream
+    );
+    dns::serve(server, listeners)
+}
+
+fn doctor() -> Result<(), ErrorKind> {
+    let report = doctor::run();
+    println!("{}", report.describe());
+    if report.has_failures() {
+        Err(ErrorKind::String(String::from(
+            "Some problems keep the hosts file from working",
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+fn doh(args: &[String]) -> Result<(), ErrorKind> {
+    let is_disabling = match args.first().map(String::as_str) {
+        Some("disable") => true,
+        Some(arg) => {
+            return Err(ErrorKind::String(format!(
+                "Unknown argument '{}'\n\n{}",
+                arg, USAGE
+            )))
+        }
+        None => false,
+    };
+
+    let root = Path::new(doh::SYSTEM_ROOT);
+    let findings = doh::scan(root);
+    if findings.is_empty() {
+        println!("No browser uses DNS over HTTPS");
+        return Ok(());
+    }
+    if is_disabling {
+        for path in doh::write_policies(root, &findings)? {
+            println!("Written {}", path.display());
+        }
+        println!("Restart the browsers to apply the policies");
+    } else {
+        println!("These browsers bypass the hosts file with DNS over HTTPS:");
+        for finding in findings {
+            println!("{}", finding.describe());
+        }
+    }
+    Ok(())
+}
+
+fn export(args: &[String]) -> Result<(), ErrorKind> {
+    let format = match args.first().map(String::as_str) {
+        Some("sections") => {
+            for name in export::section_names()? {
+                println!("{}", name);
+            }
+            return Ok(());
+        }
+        Some(name) => match export::Format::from_name(name) {
+            Some(format) => format,
+            None => return Err(ErrorKind::String(format!("Unknown format '{}'", name))),
+        },
+        None => {
+            return Err(ErrorKind::String(format!(
+                "export needs a format\n\n{}",
+                USAGE
+            )))
+        }
+    };
+
+    let mut sections = Vec::new();
+    let mut output = None;
+    let mut index = 1;
+    while index < args.len() {
+        match (args[index].as_str(), args.get(index + 1)) {
+            ("--section", Some(name)) => sections.push(name.to_owned()),
+            ("--output", Some(path)) => output = Some(path),
+            (arg, _) => {
+                return Err(ErrorKind::String(format!(
+                    "Unknown argument '{}'\n\n{}",
+                    arg, USAGE
+                )))
+            }
+        }
+        // An option and its value
+        index += 2;
+    }
+
+    let hosts = export::mapping(&sections)?;
+    match output {
+        Some(path) => {
+            export::export_to_file(path, &hosts, format)?;
+            println!("Exported to {}", path);
+        }
+        None => print!("{}", export::export(&hosts, format)),
+    }
+    Ok(())
+}
+
+fn import(args: &[String]) -> Result<(), ErrorKind> {
+    let mut format = None;
+    let mut target = String::from(DEFAULT_TARGET);
+    let mut location = None;
+    let mut index = 0;
+    while index < args.len() {
+        match (args[index].as_str(), args.get(index + 1)) {
+            ("--format", Some(name)) => match Format::from_name(name) {
+                Some(name) => format = Some(name),
+                None => return Err(ErrorKind::String(format!("Unknown format '{}'", name))),
+            },
+            ("--target", Some(ip)) => target = ip.to_owned(),
+            (arg, _) if !arg.starts_with("--") && location.is_none() => {
+                location = Some(arg);
+                index += 1;
+                continue;
+            }
+            (arg, _) => {
+                return Err(ErrorKind::String(format!(
+                    "Unknown argument '{}'\n\n{}",
+                    arg, USAGE
+                )))
+            }
+        }
+        // An option and its value
+        index += 2;
+    }
+    let location = match location {
+        Some(location) => location,
+        None => {
+            return Err(ErrorKind::String(format!(
+                "import needs the location of a blocklist\n\n{}",
+                USAGE
+            )))
+        }
+    };
+
+    let config = Config::load()?;
+    let transport = transport::from_config(&config);
+    let content = list::read(transport.as_ref(), location, &Progress::none())?;
+    let format = format.unwrap_or_else(|| Format::detect(&content));
+    let imported = import::import(&content, format, &target, &Progress::none())?;
+    // The entries go to the standard output, the report doesn't get in the way of a redirection
+    print!("{}", parser::to_string(&imported.hosts, ""));
+    for line in &imported.unrepresentable {
+        eprintln!("{}", line.describe());
+    }
+    Ok(())
+}
+
+fn log(args: &[String]) -> Result<(), ErrorKind> {
+    if args.iter().any(|arg| arg == "--json") {
+        let stdout = std::io::stdout();
+        return audit::export(&mut stdout.lock());
+    }
+
+    match audit::entries() {
+        Ok(entries) => {
+            for entry in entries {
+                println!("{}", entry.describe());
+            }
+            Ok(())
+        }
+        Err(err) => Err(err),
+    }
+}
+
+fn rollback(args: &[String]) -> Result<(), ErrorKind> {
+    match args.first() {
+        Some(version) => {
+            let config = Config::load()?;
+            rollback::restore(version, config.keep_binaries)?;
+            println!("Rolled back to version {}", version);
+            Ok(())
+        }
+        None => {
+            let kept = rollback::kept()?;
+            if kept.is_empty() {
+                println!("There is no previous version to roll back to");
+            }
+            for kept in kept {
+                println!("{}", kept.version);
+            }
+            Ok(())
+        }
+    }
+}
+
+fn refresh() -> Result<(), ErrorKind> {
+    let result = schedule::refresh()?;
+    println!("{}", result);
+    Ok(())
+}
+
+fn schedule(args: &[String]) -> Result<(), ErrorKind> {
+    match args.first().map(String::as_str) {
+        Some("install") => {
+            let interval = match args.get(1) {
+                Some(hours) => match hours.parse::<u64>() {
+                    Ok(hours) => hours,
+                    Err(_) => {
+                        return Err(ErrorKind::String(format!(
+                            "Invalid number of hours '{}'",
+                            hours
+                        )))
+                    }
+                },
+                None => schedule::DEFAULT_INTERVAL,
+            };
+            let installed = schedule::install(interval)?;
+            println!(
+                "The hosts list will be refreshed every {} hours by {}",
+                interval, installed
+            );
+            Ok(())
+        }
+        Some("remove") => {
+            schedule::remove()?;
+            println!("The hosts list won't be refreshed in the background anymore");
+            Ok(())
+        }
+        _ => Err(ErrorKind::String(format!(
+            "schedule needs either install or remove\n\n{}",
+            USAGE
+        ))),
+    }
+}
+
+fn subscription(args: &[String]) -> Result<(), ErrorKind> {
+    let mut config = Config::load()?;
+    match (args.first().map(String::as_str), args.get(1), args.get(2)) {
+        (None, _, _) | (Some("list"), _, _) => {
+            if config.subscriptions.is_empty() {
+                println!("There is no subscription");
+            }
+            let statuses = subscription::statuses();
+            for subscription in &config.subscriptions {
+                println!(
+                    "{} {}\n    {}",
+                    subscription.name,
+                    subscription.location,
+                    statuses
+                        .get(&subscription.name)
+                        .cloned()
+                        .unwrap_or_default()
+                        .describe()
+                        .replace('\n', "\n    ")
+                );
+            }
+            Ok(())
+        }
+        (Some("add"), Some(name), Some(location)) => {
+            subscription::add(&mut config, name, location)?;
+            println!(
+                "Subscribed to {}, run 'bebasin subscription refresh' to download it",
+                location
+            );
+            Ok(())
+        }
+        (Some("remove"), Some(name), _) => {
+            if subscription::remove(&mut config, name)? {
+                list::reapply(SUBSCRIPTIONS_SOURCE)?;
+            }
+            println!("Unsubscribed from {}", name);
+            Ok(())
+        }
+        (Some("refresh"), _, _) => {
+            let refreshed = subscription::refresh(&config, &Progress::none())?;
+            println!("{}", refreshed.describe());
+            if refreshed.is_changed && is_installed() {
+                list::reapply(SUBSCRIPTIONS_SOURCE)?;
+                println!("{}", target::configured().reload());
+            }
+            Ok(())
+        }
+        _ => Err(ErrorKind::String(format!(
+            "subscription needs either list, add NAME LOCATION, remove NAME or refresh\n\n{}",
+            USAGE
+        ))),
+    }
+}
+
+fn target(args: &[String]) -> Result<(), ErrorKind> {
+    match (args.first().map(String::as_str), args.get(1)) {
+        (None, _) | (Some("list"), _) => {
+            let configured = target::configured().kind();
+            for kind in target::Kind::ALL.iter() {
+                let marker = if *kind == configured { "*" } else { " " };
+                let availability = if kind.is_available() {
+                    ""
+                } else {
+                    " (unavailable)"
+                };
+                println!(
+                    "{} {}{}",
+                    marker,
+                    target::from_kind(*kind).status(),
+                    availability
+                );
+            }
+            Ok(())
+        }
+        (Some("set"), Some(name)) => {
+            let kind = match target::Kind::from_name(name) {
+                Some(kind) => kind,
+                None => return Err(ErrorKind::String(format!("Unknown target '{}'", name))),
+            };
+            target::select(kind)?;
+            println!(
+                "The hosts list will be installed to {}",
+                target::from_kind(kind).path().display()
+            );
+            Ok(())
+        }
+        (Some("reload"), _) => {
+            println!("{}", target::configured().reload());
+            Ok(())
+        }
+        _ => Err(ErrorKind::String(format!(
+            "target needs either list, set TARGET or reload\n\n{}",
+            USAGE
+        ))),
+    }
+}
+
+fn update(args: &[String]) -> Result<(), ErrorKind> {
+    let mut updater_instance = Updater::new();
+    match args.first().map(String::as_str) {
+        Some("--from") => match args.get(1) {
+            Some(path) => {
+                updater_instance.update_from_archive(Path::new(path), &Progress::none())?;
+                println!("The application has been updated from {}", path);
+                Ok(())
+            }
+            None => Err(ErrorKind::String(format!(
+                "--from needs the path of a release archive\n\n{}",
+                USAGE
+            ))),
+        },
+        Some(arg) => Err(ErrorKind::String(format!(
+            "Unknown argument '{}'\n\n{}",
+            arg, USAGE
+        ))),
+        None => {
+            let latest = updater_instance.get_latest_info()?;
+            if !updater_instance.is_updatable() {
+                println!("You have been using the latest version");
+                return Ok(());
+            }
+            println!("Updating to version {}", latest.version);
+            updater_instance.update(&Progress::none())?;
+            println!("The application has been updated");
+            Ok(())
+        }
+    }
+}
+
+fn verify() -> Result<(), ErrorKind> {
+    println!("{}", verify::verify(&Progress::none())?.describe());
+    Ok(())
+}
+
+/// Commands changing the system rather than only reading it
+fn needs_admin(args: &[String]) -> bool {
+    match args[0].as_str() {
+        "helper" | "refresh" | "schedule" | "update" => true,
+        "doh" | "rollback" => args.len() > 1,
+        "subscription" | "target" => args.len() > 1 && args[1] != "list",
+        _ => false,
+    }
+}
+
+/// Run a non-interactive command, returns the process exit code
+pub fn run(args: &[String]) -> i32 {
+    // Fail before anything is downloaded or changed halfway
+    if needs_admin(args) && !is_has_admin_access() {
+        eprintln!(
+            "'bebasin {}' needs administrator rights,\n\
+            run it again with sudo, or from an administrator prompt on Windows",
+            args.join(" ")
+        );
+        return 1;
+    }
+
+    let result = match args[0].as_str() {
+        "dns" => dns(&args[1..]),
+        "doctor" => doctor(),
+        "doh" => doh(&args[1..]),
+        "export" => export(&args[1..]),
+        "helper" => helper::serve(),
+        "import" => import(&args[1..]),
+        "log" => log(&args[1..]),
+        "refresh" => refresh(),
+        "rollback" => rollback(&args[1..]),
+        "schedule" => schedule(&args[1..]),
+        "subscription" => subscription(&args[1..]),
+        "target" => target(&args[1..]),
+        "update" => update(&args[1..]),
+        "verify" => verify(),
+        "version" | "--version" | "-V" => {
+            println!("bebasin {} ({})", CURRENT_VERSION, TARGET);
+            Ok(())
+        }
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        command => Err(ErrorKind::String(format!(
+            "Unknown command '{}'\n\n{}",
+            command, USAGE
+        ))),
+    };
+
+    match result {
+        Ok(_) => 0,
+        Err(err) => {
+            eprintln!("{}", err);
+            1
+        }
+    }
+}
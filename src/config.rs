@@ -0,0 +1,114 @@
+use crate::error::ErrorKind;
+use crate::state;
+use crate::target::Kind;
+use crate::HOSTS_LIST_URL;
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+const CONFIG_FILE: &str = "config.json";
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Channel {
+    Stable,
+    Beta,
+    Nightly,
+}
+
+/// A custom hosts list kept up to date along with the Bebasin one
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Subscription {
+    pub name: String,
+    /// Either an URL or a local path
+    pub location: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Where the hosts list is fetched from, either an URL or a local path
+    pub hosts_list_url: String,
+    /// Install releases and hosts lists without a valid signature, only meant for development
+    pub allow_unsigned: bool,
+    /// Seconds to wait for a connection, or for a stalled transfer, before giving up
+    pub timeout: u64,
+    /// How many times a failed request is retried
+    pub retries: u32,
+    /// Serve every request from this directory instead of the network
+    pub local_mirror: Option<String>,
+    /// Base URLs laid out like the local mirror, tried in order when a request fails
+    pub mirrors: Vec<String>,
+    /// HTTP(S) or SOCKS proxy, the standard proxy environment variables are used when missing.
+    /// Every user reads the configuration, credentials are better left to the environment.
+    pub proxy: Option<String>,
+    /// Certificate bundle to verify the HTTPS servers against, instead of the system one
+    pub ca_bundle: Option<String>,
+    /// How many previous executables are kept to roll back to, none at all with 0
+    pub keep_binaries: usize,
+    /// Which releases the updater follows
+    pub channel: Channel,
+    /// Stay on this version, the updater won't go past it
+    pub pin: Option<String>,
+    /// Hours between two automatic update checks, 0 disables them
+    pub update_check_interval: u64,
+    /// Domains a custom hosts list may only redirect after an explicit confirmation,
+    /// their subdomains included
+    pub protected_hostnames: Vec<String>,
+    /// Custom hosts lists merged with the Bebasin one
+    pub subscriptions: Vec<Subscription>,
+    /// Where the hosts list gets installed
+    pub install_target: Kind,
+    /// Address the built-in DNS resolver listens on
+    pub dns_listen: String,
+    /// DNS server the resolver forwards the names outside of the mapping to
+    pub dns_upstream: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            hosts_list_url: String::from(HOSTS_LIST_URL),
+            allow_unsigned: false,
+            timeout: 30,
+            retries: 2,
+            local_mirror: None,
+            mirrors: Vec::new(),
+            proxy: None,
+            ca_bundle: None,
+            keep_binaries: 3,
+            channel: Channel::Stable,
+            pin: None,
+            update_check_interval: 24,
+            protected_hostnames: Vec::new(),
+            subscriptions: Vec::new(),
+            install_target: Kind::HostsFile,
+            dns_listen: String::from("127.0.0.1:53"),
+            dns_upstream: String::from("1.1.1.1:53"),
+        }
+    }
+}
+
+impl Config {
+    /// Load the configuration from the state directory, a missing file means the default one
+    pub fn load() -> Result<Config, ErrorKind> {
+        match fs::read_to_string(state::path(CONFIG_FILE)) {
+            Ok(content) => match serde_json::from_str::<Config>(&content) {
+                Ok(config) => Ok(config),
+                Err(err) => Err(ErrorKind::SerdeJSONError(err)),
+            },
+            Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(err) => Err(ErrorKind::IOError(err)),
+        }
+    }
+
+    /// Write the configuration to the state directory, readable by every user so the
+    /// unprivileged side can load it, only the owner can change it
+    pub fn save(&self) -> Result<(), ErrorKind> {
+        let content = match serde_json::to_string_pretty(self) {
+            Ok(content) => content,
+            Err(err) => return Err(ErrorKind::SerdeJSONError(err)),
+        };
+        state::ensure()?;
+        state::write(&state::path(CONFIG_FILE), content.as_bytes())
+    }
+}
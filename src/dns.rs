@@ -0,0 +1,562 @@
+use crate::error::ErrorKind;
+use crate::parser::Hosts;
+use std::collections::HashMap;
+use std::io::{Read as _, Write as _};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const HEADER_LENGTH: usize = 12;
+/// Large enough for any UDP message, EDNS allows more than the historical 512 bytes
+const MAX_MESSAGE_LENGTH: usize = 65535;
+const MAX_NAME_LENGTH: usize = 255;
+/// Answers of the mapping aren't expected to change often
+const LOCAL_TTL: u32 = 300;
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(5);
+/// A client keeping a TCP connection open without sending anything is dropped
+const TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Threads answering the UDP queries, forwarding keeps one busy until the upstream answers
+const UDP_WORKERS: usize = 16;
+/// Queries waiting for a worker, the next ones are dropped and their clients ask again
+const UDP_QUEUE_LENGTH: usize = 256;
+/// TCP clients served at the same time, the next connections are closed right away
+const MAX_TCP_CLIENTS: usize = 32;
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+const RCODE_FORMERR: u8 = 1;
+const RCODE_SERVFAIL: u8 = 2;
+
+/// The question of a query, the only part a stub resolver has to understand
+struct Question {
+    name: String,
+    kind: u16,
+    class: u16,
+    /// Where the question ends in the query
+    end: usize,
+}
+
+/// Read the first question of `query`, `None` when the query is malformed
+fn parse_question(query: &[u8]) -> Option<Question> {
+    let question_count = u16::from_be_bytes([*query.get(4)?, *query.get(5)?]);
+    if question_count != 1 {
+        return None;
+    }
+
+    let mut labels = Vec::new();
+    let mut offset = HEADER_LENGTH;
+    loop {
+        let length = *query.get(offset)? as usize;
+        offset += 1;
+        if length == 0 {
+            break;
+        }
+        // Compression pointers and extended labels have nothing to do in a question
+        if length > 63 {
+            return None;
+        }
+        let label = query.get(offset..offset + length)?;
+        labels.push(String::from_utf8_lossy(label).to_lowercase());
+        offset += length;
+    }
+    let name = labels.join(".");
+    if name.len() > MAX_NAME_LENGTH {
+        return None;
+    }
+
+    let fields = query.get(offset..offset + 4)?;
+    Some(Question {
+        name,
+        kind: u16::from_be_bytes([fields[0], fields[1]]),
+        class: u16::from_be_bytes([fields[2], fields[3]]),
+        end: offset + 4,
+    })
+}
+
+/// A response carrying the header and the question of `query`, without any record yet
+fn response_header(query: &[u8], question_end: usize, rcode: u8) -> Vec<u8> {
+    let mut response = query[..question_end].to_vec();
+    // Response, authoritative, recursion available, the opcode and recursion desired are kept
+    response[2] = 0x80 | (query[2] & 0x79) | 0x04;
+    response[3] = 0x80 | rcode;
+    // One question, no answer, no authority, no additional record
+    response[4..HEADER_LENGTH].copy_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+    response
+}
+
+/// An error answer to a query whose question couldn't be read
+fn error_response(query: &[u8], rcode: u8) -> Option<Vec<u8>> {
+    let header = query.get(..HEADER_LENGTH)?;
+    let mut response = header.to_vec();
+    response[2] = 0x80 | (query[2] & 0x79);
+    response[3] = 0x80 | rcode;
+    response[4..HEADER_LENGTH].copy_from_slice(&[0; 8]);
+    Some(response)
+}
+
+/// Answers queries for the names of the mapping, everything else goes to the upstream server
+pub struct Server {
+    mapping: HashMap<String, Vec<IpAddr>>,
+    upstream: SocketAddr,
+    /// How long the upstream server has to answer
+    timeout: Duration,
+}
+
+impl Server {
+    /// The hostnames lose their trailing dot and their case, like the names of the queries
+    pub fn new(hosts: &Hosts, upstream: SocketAddr) -> Server {
+        let mut mapping: HashMap<String, Vec<IpAddr>> = HashMap::new();
+        for (ip, hostnames) in hosts {
+            let ip = match ip.parse::<IpAddr>() {
+                Ok(ip) => ip,
+                Err(_) => continue,
+            };
+            for hostname in hostnames {
+                let addresses = mapping
+                    .entry(hostname.trim_end_matches('.').to_lowercase())
+                    .or_default();
+                if !addresses.contains(&ip) {
+                    addresses.push(ip);
+                }
+            }
+        }
+        Server {
+            mapping,
+            upstream,
+            timeout: UPSTREAM_TIMEOUT,
+        }
+    }
+
+    /// The answer from the mapping, `None` when the name isn't part of it
+    fn answer_locally(&self, query: &[u8], question: &Question) -> Option<Vec<u8>> {
+        let addresses = self.mapping.get(&question.name)?;
+        let mut response = response_header(query, question.end, 0);
+
+        // A mapped name only has the addresses of the mapping, any other type gets no record
+        let mut answers = 0u16;
+        if question.class == CLASS_IN {
+            for address in addresses {
+                let (kind, data) = match address {
+                    IpAddr::V4(address) if question.kind == TYPE_A => {
+                        (TYPE_A, address.octets().to_vec())
+                    }
+                    IpAddr::V6(address) if question.kind == TYPE_AAAA => {
+                        (TYPE_AAAA, address.octets().to_vec())
+                    }
+                    _ => continue,
+                };
+                // The name points to the question, right after the header
+                response.extend_from_slice(&[0xc0, HEADER_LENGTH as u8]);
+                response.extend_from_slice(&kind.to_be_bytes());
+                response.extend_from_slice(&CLASS_IN.to_be_bytes());
+                response.extend_from_slice(&LOCAL_TTL.to_be_bytes());
+                response.extend_from_slice(&(data.len() as u16).to_be_bytes());
+                response.extend_from_slice(&data);
+                answers += 1;
+            }
+        }
+        response[6..8].copy_from_slice(&answers.to_be_bytes());
+        Some(response)
+    }
+
+    fn forward_udp(&self, query: &[u8]) -> std::io::Result<Vec<u8>> {
+        let local: SocketAddr = if self.upstream.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.set_read_timeout(Some(self.timeout))?;
+        socket.connect(self.upstream)?;
+        socket.send(query)?;
+
+        let mut buffer = vec![0; MAX_MESSAGE_LENGTH];
+        loop {
+            let length = socket.recv(&mut buffer)?;
+            // Anything else than the answer to this query is ignored
+            if length >= 2 && buffer[..2] == query[..2] {
+                buffer.truncate(length);
+                return Ok(buffer);
+            }
+        }
+    }
+
+    fn forward_tcp(&self, query: &[u8]) -> std::io::Result<Vec<u8>> {
+        let mut stream = TcpStream::connect_timeout(&self.upstream, self.timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        write_tcp_message(&mut stream, query)?;
+        read_tcp_message(&mut stream)
+    }
+
+    /// The response to `query`, `None` when it isn't even a DNS message
+    pub fn answer(&self, query: &[u8], is_tcp: bool) -> Option<Vec<u8>> {
+        let question = match parse_question(query) {
+            Some(question) => question,
+            None => return error_response(query, RCODE_FORMERR),
+        };
+        if let Some(response) = self.answer_locally(query, &question) {
+            return Some(response);
+        }
+
+        let forwarded = if is_tcp {
+            self.forward_tcp(query)
+        } else {
+            self.forward_udp(query)
+        };
+        match forwarded {
+            Ok(response) => Some(response),
+            Err(_) => Some(response_header(query, question.end, RCODE_SERVFAIL)),
+        }
+    }
+}
+
+/// TCP messages are prefixed with their length
+fn read_tcp_message(stream: &mut TcpStream) -> std::io::Result<Vec<u8>> {
+    let mut length = [0; 2];
+    stream.read_exact(&mut length)?;
+    let mut message = vec![0; u16::from_be_bytes(length) as usize];
+    stream.read_exact(&mut message)?;
+    Ok(message)
+}
+
+fn write_tcp_message(stream: &mut TcpStream, message: &[u8]) -> std::io::Result<()> {
+    let mut framed = (message.len() as u16).to_be_bytes().to_vec();
+    framed.extend_from_slice(message);
+    stream.write_all(&framed)
+}
+
+fn serve_tcp_client(server: &Server, mut stream: TcpStream) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(TCP_IDLE_TIMEOUT))?;
+    // A client may send several queries over the same connection
+    loop {
+        let query = read_tcp_message(&mut stream)?;
+        if let Some(response) = server.answer(&query, true) {
+            write_tcp_message(&mut stream, &response)?;
+        }
+    }
+}
+
+/// Taken by a TCP client while it is served
+struct ClientSlot(Arc<AtomicUsize>);
+
+impl ClientSlot {
+    fn take(clients: &Arc<AtomicUsize>) -> Option<ClientSlot> {
+        if clients.fetch_add(1, Ordering::SeqCst) >= MAX_TCP_CLIENTS {
+            clients.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+        Some(ClientSlot(clients.clone()))
+    }
+}
+
+impl Drop for ClientSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn serve_tcp(server: Arc<Server>, listener: TcpListener) {
+    let clients = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+        // Dropping the stream closes the connection
+        let slot = match ClientSlot::take(&clients) {
+            Some(slot) => slot,
+            None => continue,
+        };
+        let server = server.clone();
+        thread::spawn(move || {
+            serve_tcp_client(&server, stream).ok();
+            drop(slot);
+        });
+    }
+}
+
+fn serve_udp(server: Arc<Server>, socket: UdpSocket) -> Result<(), ErrorKind> {
+    // Forwarding waits for the upstream server, the other queries go to the other workers
+    let (sender, receiver) = mpsc::sync_channel::<(Vec<u8>, SocketAddr)>(UDP_QUEUE_LENGTH);
+    let receiver = Arc::new(Mutex::new(receiver));
+    for _ in 0..UDP_WORKERS {
+        let socket = match socket.try_clone() {
+            Ok(socket) => socket,
+            Err(err) => return Err(ErrorKind::IOError(err)),
+        };
+        let (server, receiver) = (server.clone(), receiver.clone());
+        thread::spawn(move || loop {
+            let received = match receiver.lock() {
+                Ok(receiver) => receiver.recv(),
+                Err(_) => return,
+            };
+            let (query, client) = match received {
+                Ok(received) => received,
+                Err(_) => return,
+            };
+            if let Some(response) = server.answer(&query, false) {
+                socket.send_to(&response, client).ok();
+            }
+        });
+    }
+
+    let mut buffer = vec![0; MAX_MESSAGE_LENGTH];
+    loop {
+        let (length, client) = match socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            // Windows reports an unreachable client of a previous answer on the next receive
+            Err(ref err) if err.kind() == std::io::ErrorKind::ConnectionReset => continue,
+            Err(err) => return Err(ErrorKind::IOError(err)),
+        };
+        // Every worker is busy, the client asks again after a while
+        sender.try_send((buffer[..length].to_vec(), client)).ok();
+    }
+}
+
+/// The sockets of the resolver, bound before serving so that the failures show up right away
+pub struct Listeners {
+    socket: UdpSocket,
+    listener: TcpListener,
+}
+
+impl Listeners {
+    /// Listen on `listen` over UDP and TCP, port 0 picks the same free port for both
+    pub fn bind(listen: SocketAddr) -> Result<Listeners, ErrorKind> {
+        let socket = match UdpSocket::bind(listen) {
+            Ok(socket) => socket,
+            Err(err) => return Err(ErrorKind::IOError(err)),
+        };
+        let address = match socket.local_addr() {
+            Ok(address) => address,
+            Err(err) => return Err(ErrorKind::IOError(err)),
+        };
+        match TcpListener::bind(address) {
+            Ok(listener) => Ok(Listeners { socket, listener }),
+            Err(err) => Err(ErrorKind::IOError(err)),
+        }
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, ErrorKind> {
+        match self.socket.local_addr() {
+            Ok(address) => Ok(address),
+            Err(err) => Err(ErrorKind::IOError(err)),
+        }
+    }
+}
+
+/// Answer DNS queries over UDP and TCP until the process is stopped
+pub fn serve(server: Server, listeners: Listeners) -> Result<(), ErrorKind> {
+    let server = Arc::new(server);
+    let tcp_server = server.clone();
+    let listener = listeners.listener;
+    thread::spawn(move || serve_tcp(tcp_server, listener));
+    serve_udp(server, listeners.socket)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UPSTREAM_ADDRESS: [u8; 4] = [9, 9, 9, 9];
+
+    fn make_query(id: u16, name: &str, kind: u16) -> Vec<u8> {
+        // Recursion desired, one question
+        let mut query = id.to_be_bytes().to_vec();
+        query.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+        for label in name.split('.') {
+            query.push(label.len() as u8);
+            query.extend_from_slice(label.as_bytes());
+        }
+        query.push(0);
+        query.extend_from_slice(&kind.to_be_bytes());
+        query.extend_from_slice(&CLASS_IN.to_be_bytes());
+        query
+    }
+
+    /// The response code and the data of the answers of `response`
+    fn answers(response: &[u8], query: &[u8]) -> (u8, Vec<Vec<u8>>) {
+        assert_eq!(response[..2], query[..2], "the response has another ID");
+        assert!(response[2] & 0x80 != 0, "not a response");
+        let count = u16::from_be_bytes([response[6], response[7]]);
+        let mut offset = parse_question(response).unwrap().end;
+        let mut data = Vec::new();
+        for _ in 0..count {
+            // A pointer to the name, the type, the class and the TTL
+            offset += 10;
+            let length = u16::from_be_bytes([response[offset], response[offset + 1]]) as usize;
+            offset += 2;
+            data.push(response[offset..offset + length].to_vec());
+            offset += length;
+        }
+        (response[3] & 0x0f, data)
+    }
+
+    /// An upstream server answering every A query with the same address, over UDP and TCP
+    fn fake_upstream() -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap();
+        let listener = TcpListener::bind(address).unwrap();
+        let answer = |query: &[u8]| {
+            let question = parse_question(query).unwrap();
+            let mut response = response_header(query, question.end, 0);
+            response[6..8].copy_from_slice(&1u16.to_be_bytes());
+            response.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4]);
+            response.extend_from_slice(&UPSTREAM_ADDRESS);
+            response
+        };
+        thread::spawn(move || {
+            let mut buffer = [0; 512];
+            while let Ok((length, client)) = socket.recv_from(&mut buffer) {
+                socket.send_to(&answer(&buffer[..length]), client).ok();
+            }
+        });
+        thread::spawn(move || {
+            for mut stream in listener.incoming().filter_map(Result::ok) {
+                if let Ok(query) = read_tcp_message(&mut stream) {
+                    write_tcp_message(&mut stream, &answer(&query)).ok();
+                }
+            }
+        });
+        address
+    }
+
+    fn start(server: Server) -> SocketAddr {
+        let listeners = Listeners::bind(([127, 0, 0, 1], 0).into()).unwrap();
+        let address = listeners.local_addr().unwrap();
+        thread::spawn(move || serve(server, listeners));
+        address
+    }
+
+    fn mapping(upstream: SocketAddr) -> Server {
+        let hosts = crate::parser::parse_from_str(
+            "0.0.0.0 ads.example.com Tracker.Example.com\n\
+             10.0.0.1 dual.example.com\n\
+             fd00::1 dual.example.com\n",
+        )
+        .unwrap();
+        Server::new(&hosts, upstream)
+    }
+
+    fn ask_udp(address: SocketAddr, query: &[u8]) -> Vec<u8> {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        socket.send_to(query, address).unwrap();
+        let mut buffer = vec![0; MAX_MESSAGE_LENGTH];
+        let length = socket.recv(&mut buffer).unwrap();
+        buffer.truncate(length);
+        buffer
+    }
+
+    fn ask_tcp(address: SocketAddr, query: &[u8]) -> Vec<u8> {
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        write_tcp_message(&mut stream, query).unwrap();
+        read_tcp_message(&mut stream).unwrap()
+    }
+
+    #[test]
+    fn answers_mapped_names() {
+        let address = start(mapping(fake_upstream()));
+
+        let query = make_query(1, "ads.example.com", TYPE_A);
+        assert_eq!(
+            answers(&ask_udp(address, &query), &query),
+            (0, vec![vec![0, 0, 0, 0]])
+        );
+        // The names of the queries and of the mapping are matched whatever their case
+        let query = make_query(2, "TRACKER.example.COM", TYPE_A);
+        assert_eq!(
+            answers(&ask_tcp(address, &query), &query),
+            (0, vec![vec![0, 0, 0, 0]])
+        );
+    }
+
+    #[test]
+    fn answers_each_address_family() {
+        let address = start(mapping(fake_upstream()));
+
+        let query_a = make_query(3, "dual.example.com", TYPE_A);
+        assert_eq!(
+            answers(&ask_udp(address, &query_a), &query_a),
+            (0, vec![vec![10, 0, 0, 1]])
+        );
+        let query_aaaa = make_query(4, "dual.example.com", TYPE_AAAA);
+        let expected: IpAddr = "fd00::1".parse().unwrap();
+        let expected = match expected {
+            IpAddr::V6(expected) => expected.octets().to_vec(),
+            IpAddr::V4(_) => unreachable!(),
+        };
+        assert_eq!(
+            answers(&ask_udp(address, &query_aaaa), &query_aaaa),
+            (0, vec![expected])
+        );
+    }
+
+    #[test]
+    fn mapped_names_have_no_other_records() {
+        let address = start(mapping(fake_upstream()));
+
+        // The upstream address of a blocked name would bypass the mapping
+        let query_aaaa = make_query(5, "ads.example.com", TYPE_AAAA);
+        assert_eq!(
+            answers(&ask_udp(address, &query_aaaa), &query_aaaa),
+            (0, Vec::new())
+        );
+        let query_https = make_query(6, "ads.example.com", 65);
+        assert_eq!(
+            answers(&ask_udp(address, &query_https), &query_https),
+            (0, Vec::new())
+        );
+    }
+
+    #[test]
+    fn forwards_other_names() {
+        let address = start(mapping(fake_upstream()));
+
+        let query = make_query(7, "example.org", TYPE_A);
+        let expected = (0, vec![UPSTREAM_ADDRESS.to_vec()]);
+        assert_eq!(answers(&ask_udp(address, &query), &query), expected);
+        assert_eq!(answers(&ask_tcp(address, &query), &query), expected);
+    }
+
+    #[test]
+    fn fails_when_the_upstream_server_is_silent() {
+        // Bound, so nothing refuses the queries, but never answering
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server = Server {
+            timeout: Duration::from_millis(200),
+            ..mapping(silent.local_addr().unwrap())
+        };
+        let address = start(server);
+
+        let query = make_query(8, "example.org", TYPE_A);
+        assert_eq!(
+            answers(&ask_udp(address, &query), &query),
+            (RCODE_SERVFAIL, Vec::new())
+        );
+    }
+
+    #[test]
+    fn refuses_malformed_queries() {
+        let address = start(mapping(fake_upstream()));
+
+        // Two questions
+        let mut query = make_query(9, "example.org", TYPE_A);
+        query[5] = 2;
+        let response = ask_udp(address, &query);
+        assert_eq!(response[..2], query[..2]);
+        assert_eq!(response[3] & 0x0f, RCODE_FORMERR);
+
+        // A question cut in the middle of a label
+        let query = query[..16].to_vec();
+        let response = ask_udp(address, &query);
+        assert_eq!(response[3] & 0x0f, RCODE_FORMERR);
+    }
+}
@@ -0,0 +1,486 @@
+use crate::doh;
+use crate::list;
+use crate::os::{is_has_admin_access, HOSTS_BACKUP_PATH, HOSTS_PATH};
+use crate::parser::parse_from_str;
+use crate::target::{self, Kind, Target};
+use crate::HOSTS_HEADER;
+use std::fs;
+
+/// A list is considered stale after this many days
+const LIST_MAX_AGE_DAYS: i64 = 30;
+
+#[derive(PartialEq)]
+enum Status {
+    Ok,
+    Warning,
+    Failure,
+}
+
+struct Check {
+    name: &'static str,
+    status: Status,
+    detail: String,
+    fix: Option<String>,
+}
+
+impl Check {
+    fn ok<S: Into<String>>(name: &'static str, detail: S) -> Check {
+        Check {
+            name,
+            status: Status::Ok,
+            detail: detail.into(),
+            fix: None,
+        }
+    }
+
+    fn warning<S: Into<String>, F: Into<String>>(name: &'static str, detail: S, fix: F) -> Check {
+        Check {
+            name,
+            status: Status::Warning,
+            detail: detail.into(),
+            fix: Some(fix.into()),
+        }
+    }
+
+    fn failure<S: Into<String>, F: Into<String>>(name: &'static str, detail: S, fix: F) -> Check {
+        Check {
+            name,
+            status: Status::Failure,
+            detail: detail.into(),
+            fix: Some(fix.into()),
+        }
+    }
+}
+
+/// Everything that may keep the hosts file from being used
+pub struct Report {
+    checks: Vec<Check>,
+}
+
+impl Report {
+    pub fn has_failures(&self) -> bool {
+        self.checks
+            .iter()
+            .any(|check| check.status == Status::Failure)
+    }
+
+    pub fn describe(&self) -> String {
+        let mut description = Vec::new();
+        for check in &self.checks {
+            let status = match check.status {
+                Status::Ok => "[ OK ]",
+                Status::Warning => "[WARN]",
+                Status::Failure => "[FAIL]",
+            };
+            description.push(format!("{} {}: {}", status, check.name, check.detail));
+            if let Some(fix) = &check.fix {
+                description.push(format!("       Fix: {}", fix));
+            }
+        }
+        description.join("\n")
+    }
+}
+
+fn check_privileges() -> Check {
+    if is_has_admin_access() {
+        Check::ok("Privileges", "running with administrator rights")
+    } else {
+        // Nothing is wrong with the system, only this run can't change it
+        Check::warning(
+            "Privileges",
+            "running without administrator rights, the hosts file can't be changed",
+            "run Bebasin with sudo, or as administrator on Windows",
+        )
+    }
+}
+
+fn check_hosts_file() -> Vec<Check> {
+    let mut checks = Vec::new();
+
+    let content = match fs::read_to_string(HOSTS_PATH) {
+        Ok(content) => content,
+        Err(err) => {
+            checks.push(Check::failure(
+                "Hosts file",
+                format!("{} can't be read: {}", HOSTS_PATH, err),
+                "make sure the hosts file exists and is readable",
+            ));
+            return checks;
+        }
+    };
+    checks.push(match parse_from_str(&content) {
+        Ok(hosts) => Check::ok("Hosts file", format!("{} entries", hosts.len())),
+        Err(err) => Check::failure(
+            "Hosts file",
+            format!("{} can't be parsed: {}", HOSTS_PATH, err),
+            "fix or remove the invalid line",
+        ),
+    });
+
+    let is_read_only = fs::metadata(HOSTS_PATH)
+        .map(|metadata| metadata.permissions().readonly())
+        .unwrap_or(false);
+    if is_read_only {
+        checks.push(Check::failure(
+            "Hosts file permissions",
+            "the hosts file is read-only",
+            "allow the owner to write the hosts file",
+        ));
+    } else if is_has_admin_access() {
+        // Opening it for appending doesn't change anything
+        checks.push(match fs::OpenOptions::new().append(true).open(HOSTS_PATH) {
+            Ok(_) => Check::ok("Hosts file permissions", "the hosts file is writable"),
+            Err(err) => Check::failure(
+                "Hosts file permissions",
+                format!("the hosts file can't be written: {}", err),
+                "check whether a security software protects the hosts file",
+            ),
+        });
+    }
+
+    if let Some(check) = check_immutable() {
+        checks.push(check);
+    }
+    checks
+}
+
+#[cfg(target_os = "linux")]
+fn check_immutable() -> Option<Check> {
+    let output = std::process::Command::new("lsattr")
+        .arg("-d")
+        .arg(HOSTS_PATH)
+        .output()
+        .ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let attributes = stdout.split_whitespace().next()?;
+    if attributes.contains('i') {
+        Some(Check::failure(
+            "Immutable attribute",
+            "the hosts file is immutable, nothing can change it",
+            format!("sudo chattr -i {}", HOSTS_PATH),
+        ))
+    } else {
+        Some(Check::ok(
+            "Immutable attribute",
+            "the hosts file isn't immutable",
+        ))
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn check_immutable() -> Option<Check> {
+    let output = std::process::Command::new("stat")
+        .args(&["-f", "%Sf", HOSTS_PATH])
+        .output()
+        .ok()?;
+    let flags = String::from_utf8_lossy(&output.stdout);
+    if flags.contains("uchg") || flags.contains("schg") {
+        Some(Check::failure(
+            "Immutable attribute",
+            "the hosts file is locked, nothing can change it",
+            format!("sudo chflags nouchg,noschg {}", HOSTS_PATH),
+        ))
+    } else {
+        Some(Check::ok(
+            "Immutable attribute",
+            "the hosts file isn't locked",
+        ))
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn check_immutable() -> Option<Check> {
+    // The read-only attribute is all there is, it is checked with the permissions
+    None
+}
+
+/// A drop-in has no backup, the rest of the configuration of the DNS server is left alone
+fn check_drop_in(target: &dyn Target) -> Check {
+    let path = target.path().display();
+    if !target.is_installed() {
+        return Check::ok("Drop-in", format!("Bebasin isn't installed to {}", path));
+    }
+
+    let content = match fs::read_to_string(target.path()) {
+        Ok(content) => content,
+        Err(err) => {
+            return Check::failure(
+                "Drop-in",
+                format!("{} can't be read: {}", path, err),
+                "make sure the drop-in is readable",
+            )
+        }
+    };
+    match target.parse(&content) {
+        Ok(hosts) => Check::ok(
+            "Drop-in",
+            format!("{} maps {} addresses", path, hosts.len()),
+        ),
+        Err(err) => Check::failure(
+            "Drop-in",
+            format!("{} can't be parsed: {}", path, err),
+            "install Bebasin again",
+        ),
+    }
+}
+
+fn check_backup() -> Check {
+    let target = target::configured();
+    if target.kind() != Kind::HostsFile {
+        return check_drop_in(target.as_ref());
+    }
+    if !target.is_installed() {
+        return Check::ok(
+            "Backup",
+            "Bebasin isn't installed, there is nothing to back up",
+        );
+    }
+
+    let content = match fs::read_to_string(HOSTS_BACKUP_PATH) {
+        Ok(content) => content,
+        Err(err) => {
+            return Check::failure(
+                "Backup",
+                format!("{} can't be read: {}", HOSTS_BACKUP_PATH, err),
+                "make sure the backup is readable",
+            )
+        }
+    };
+    if content.starts_with(HOSTS_HEADER) {
+        return Check::failure(
+            "Backup",
+            "the backup contains the Bebasin hosts instead of the original hosts file",
+            "restore the original hosts file by hand, then remove the backup",
+        );
+    }
+    if let Err(err) = target.parse(&content) {
+        return Check::failure(
+            "Backup",
+            format!("the backup can't be parsed: {}", err),
+            "fix the invalid line of the backup",
+        );
+    }
+
+    match fs::read_to_string(HOSTS_PATH) {
+        Ok(hosts) if !hosts.starts_with(HOSTS_HEADER) => Check::warning(
+            "Backup",
+            "the hosts file has been replaced since Bebasin has been installed",
+            "install Bebasin again",
+        ),
+        _ => Check::ok("Backup", "the backup of the original hosts file is intact"),
+    }
+}
+
+fn check_list() -> Check {
+    let info = match list::installed_info() {
+        Some(info) => info,
+        None => {
+            return Check::warning(
+                "Hosts list",
+                "the hosts list embedded in this release is used",
+                "update the hosts list",
+            )
+        }
+    };
+    if !list::is_cache_intact() {
+        return Check::failure(
+            "Hosts list",
+            "the cached hosts list doesn't match its checksum",
+            "update the hosts list",
+        );
+    }
+
+    let version = info.version.unwrap_or_else(|| String::from("unknown"));
+    match chrono::DateTime::parse_from_rfc3339(&info.fetched_at) {
+        Ok(fetched_at) => {
+            let age = chrono::Local::now().signed_duration_since(fetched_at);
+            if age.num_days() > LIST_MAX_AGE_DAYS {
+                Check::warning(
+                    "Hosts list",
+                    format!(
+                        "version {} has been fetched {} days ago",
+                        version,
+                        age.num_days()
+                    ),
+                    "update the hosts list",
+                )
+            } else {
+                Check::ok(
+                    "Hosts list",
+                    format!("version {} from {}", version, info.source),
+                )
+            }
+        }
+        Err(_) => Check::warning(
+            "Hosts list",
+            format!("version {}, the fetch time is unknown", version),
+            "update the hosts list",
+        ),
+    }
+}
+
+/// nsswitch.conf decides whether the hosts file is consulted before DNS
+#[cfg(target_os = "linux")]
+fn check_nsswitch() -> Check {
+    let content = match fs::read_to_string("/etc/nsswitch.conf") {
+        Ok(content) => content,
+        // glibc falls back to "dns files" without it
+        Err(_) => {
+            return Check::failure(
+                "nsswitch.conf",
+                "/etc/nsswitch.conf is missing, DNS is consulted before the hosts file",
+                "create /etc/nsswitch.conf with the line \"hosts: files dns\"",
+            )
+        }
+    };
+
+    let sources = content.lines().find_map(|line| {
+        let line = line.split('#').next()?.trim();
+        let sources = line.strip_prefix("hosts:")?;
+        Some(
+            sources
+                .split_whitespace()
+                .filter(|source| !source.starts_with('['))
+                .map(str::to_owned)
+                .collect::<Vec<_>>(),
+        )
+    });
+    let sources = match sources {
+        Some(sources) => sources,
+        None => {
+            return Check::failure(
+                "nsswitch.conf",
+                "there is no hosts line, DNS is consulted before the hosts file",
+                "add the line \"hosts: files dns\" to /etc/nsswitch.conf",
+            )
+        }
+    };
+
+    let position = |name: &str| sources.iter().position(|source| source == name);
+    let files = match position("files") {
+        Some(files) => files,
+        None => {
+            return Check::failure(
+                "nsswitch.conf",
+                format!(
+                    "the hosts file isn't consulted (hosts: {})",
+                    sources.join(" ")
+                ),
+                "add files first to the hosts line of /etc/nsswitch.conf",
+            )
+        }
+    };
+    // systemd-resolved reads the hosts file itself, unless told otherwise
+    match position("dns") {
+        Some(dns) if dns < files => Check::failure(
+            "nsswitch.conf",
+            format!(
+                "DNS is consulted before the hosts file (hosts: {})",
+                sources.join(" ")
+            ),
+            "move files before dns in the hosts line of /etc/nsswitch.conf",
+        ),
+        _ => Check::ok("nsswitch.conf", format!("hosts: {}", sources.join(" "))),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn read_etc_hosts_disabled() -> bool {
+    let mut paths = vec![std::path::PathBuf::from("/etc/systemd/resolved.conf")];
+    if let Ok(entries) = fs::read_dir("/etc/systemd/resolved.conf.d") {
+        paths.extend(
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path()),
+        );
+    }
+    paths.iter().any(|path| {
+        fs::read_to_string(path)
+            .map(|content| {
+                content.lines().any(|line| {
+                    let line = line.trim().replace(' ', "");
+                    line.eq_ignore_ascii_case("ReadEtcHosts=no")
+                        || line.eq_ignore_ascii_case("ReadEtcHosts=false")
+                })
+            })
+            .unwrap_or(false)
+    })
+}
+
+#[cfg(target_os = "linux")]
+fn check_resolver() -> Check {
+    let content = fs::read_to_string("/etc/resolv.conf").unwrap_or_default();
+    let nameservers = content
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("nameserver"))
+        .map(|nameserver| nameserver.trim().to_owned())
+        .collect::<Vec<_>>();
+    if nameservers.is_empty() {
+        return Check::warning(
+            "resolv.conf",
+            "there is no nameserver in /etc/resolv.conf",
+            "configure a DNS server for your network",
+        );
+    }
+
+    let is_resolved_running = !crate::flush::find_processes("systemd-resolve").is_empty();
+    let is_resolved_stub = nameservers
+        .iter()
+        .any(|nameserver| nameserver == "127.0.0.53");
+    if is_resolved_stub && !is_resolved_running {
+        return Check::failure(
+            "systemd-resolved",
+            "/etc/resolv.conf points to systemd-resolved, which isn't running",
+            "sudo systemctl enable --now systemd-resolved",
+        );
+    }
+    if is_resolved_running && read_etc_hosts_disabled() {
+        return Check::failure(
+            "systemd-resolved",
+            "systemd-resolved is told to ignore the hosts file",
+            "remove ReadEtcHosts=no from the systemd-resolved configuration",
+        );
+    }
+
+    Check::ok(
+        "resolv.conf",
+        format!("nameserver {}", nameservers.join(", ")),
+    )
+}
+
+/// Browsers resolving through DNS over HTTPS never look at the hosts file
+#[cfg(target_os = "linux")]
+fn check_doh() -> Check {
+    let findings = doh::scan(std::path::Path::new(doh::SYSTEM_ROOT));
+    if findings.is_empty() {
+        return Check::ok("Browsers", "no browser uses DNS over HTTPS");
+    }
+    Check::warning(
+        "Browsers",
+        format!(
+            "these browsers bypass the hosts file with DNS over HTTPS: {}",
+            findings
+                .iter()
+                .map(doh::Finding::describe)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        "sudo bebasin doh disable",
+    )
+}
+
+/// Look for every known reason the hosts file could be ignored
+pub fn run() -> Report {
+    let mut checks = vec![check_privileges()];
+    checks.extend(check_hosts_file());
+    #[cfg(target_os = "linux")]
+    {
+        checks.push(check_nsswitch());
+        checks.push(check_resolver());
+        checks.push(check_doh());
+    }
+    checks.push(check_backup());
+    checks.push(check_list());
+
+    Report { checks }
+}
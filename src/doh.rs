@@ -0,0 +1,424 @@
+use crate::error::ErrorKind;
+use crate::os::write_file;
+use serde_json::{json, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Every other path is relative to it, so the detection can run against a copy of the
+/// home directories as well. Only the Linux layout is known.
+pub const SYSTEM_ROOT: &str = "/";
+const FIREFOX_PROFILES_PATHS: [&str; 2] =
+    [".mozilla/firefox", "snap/firefox/common/.mozilla/firefox"];
+const FIREFOX_POLICIES_PATH: &str = "etc/firefox/policies/policies.json";
+// The TRR modes that resolve through DNS over HTTPS, 5 means disabled on purpose
+const FIREFOX_DOH_MODES: [&str; 2] = ["2", "3"];
+const CHROMIUM_POLICY_NAME: &str = "bebasin.json";
+
+struct Chromium {
+    name: &'static str,
+    config_path: &'static str,
+    policies_path: &'static str,
+}
+
+const CHROMIUM_BROWSERS: [Chromium; 5] = [
+    Chromium {
+        name: "Google Chrome",
+        config_path: ".config/google-chrome",
+        policies_path: "etc/opt/chrome/policies/managed",
+    },
+    Chromium {
+        name: "Chromium",
+        config_path: ".config/chromium",
+        policies_path: "etc/chromium/policies/managed",
+    },
+    Chromium {
+        name: "Chromium",
+        config_path: "snap/chromium/common/chromium",
+        policies_path: "etc/chromium/policies/managed",
+    },
+    Chromium {
+        name: "Brave",
+        config_path: ".config/BraveSoftware/Brave-Browser",
+        policies_path: "etc/brave/policies/managed",
+    },
+    Chromium {
+        name: "Microsoft Edge",
+        config_path: ".config/microsoft-edge",
+        policies_path: "etc/opt/edge/policies/managed",
+    },
+];
+
+/// A browser profile resolving through DNS over HTTPS, which skips the hosts file
+pub struct Finding {
+    pub browser: &'static str,
+    pub profile: PathBuf,
+    pub mode: String,
+    policy: Policy,
+}
+
+enum Policy {
+    Firefox,
+    /// The directory of the managed policies
+    Chromium(&'static str),
+}
+
+impl Policy {
+    fn path(&self, root: &Path) -> PathBuf {
+        match self {
+            Policy::Firefox => root.join(FIREFOX_POLICIES_PATH),
+            Policy::Chromium(policies_path) => root.join(policies_path).join(CHROMIUM_POLICY_NAME),
+        }
+    }
+
+    /// Whether a policy, written by Bebasin or not, already turns DNS over HTTPS off
+    fn is_applied(&self, root: &Path) -> bool {
+        match self {
+            Policy::Firefox => read_json(&self.path(root))
+                .map(|value| value["policies"]["DNSOverHTTPS"]["Enabled"] == json!(false))
+                .unwrap_or(false),
+            Policy::Chromium(policies_path) => match fs::read_dir(root.join(policies_path)) {
+                Ok(entries) => entries
+                    .filter_map(|entry| entry.ok())
+                    .filter_map(|entry| read_json(&entry.path()))
+                    .any(|value| value["DnsOverHttpsMode"] == json!("off")),
+                Err(_) => false,
+            },
+        }
+    }
+}
+
+fn read_json(path: &Path) -> Option<Value> {
+    let content = fs::read_to_string(path).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+impl Finding {
+    pub fn describe(&self) -> String {
+        format!(
+            "{} ({}), mode {}",
+            self.browser,
+            self.profile.display(),
+            self.mode
+        )
+    }
+}
+
+fn home_directories(root: &Path) -> Vec<PathBuf> {
+    let mut homes = vec![root.join("root")];
+    if let Ok(entries) = fs::read_dir(root.join("home")) {
+        homes.extend(
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| path.is_dir()),
+        );
+    }
+    homes
+}
+
+/// The value of `network.trr.mode` in a prefs.js or user.js file, the last one wins
+fn firefox_trr_mode(path: &Path) -> Option<String> {
+    let content = fs::read_to_string(path).ok()?;
+    content.lines().rev().find_map(|line| {
+        let value = line
+            .trim()
+            .strip_prefix("user_pref(\"network.trr.mode\",")?
+            .trim_end_matches(';')
+            .trim_end_matches(')');
+        Some(value.trim().to_owned())
+    })
+}
+
+fn scan_firefox(home: &Path, findings: &mut Vec<Finding>) {
+    for profiles_path in FIREFOX_PROFILES_PATHS.iter() {
+        let profiles = match fs::read_dir(home.join(profiles_path)) {
+            Ok(profiles) => profiles,
+            Err(_) => continue,
+        };
+        for profile in profiles
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+        {
+            // user.js is applied over prefs.js on every start
+            let mode = firefox_trr_mode(&profile.join("user.js"))
+                .or_else(|| firefox_trr_mode(&profile.join("prefs.js")));
+            if let Some(mode) = mode {
+                if FIREFOX_DOH_MODES.contains(&mode.as_str()) {
+                    findings.push(Finding {
+                        browser: "Firefox",
+                        profile,
+                        mode,
+                        policy: Policy::Firefox,
+                    });
+                }
+            }
+        }
+    }
+}
+
+fn scan_chromium(home: &Path, findings: &mut Vec<Finding>) {
+    for browser in CHROMIUM_BROWSERS.iter() {
+        let profile = home.join(browser.config_path);
+        let local_state = match read_json(&profile.join("Local State")) {
+            Some(local_state) => local_state,
+            None => continue,
+        };
+        // "automatic" only upgrades to DNS over HTTPS when the system resolver supports it
+        if let Some(mode) = local_state["dns_over_https"]["mode"].as_str() {
+            if mode == "secure" || mode == "automatic" {
+                findings.push(Finding {
+                    browser: browser.name,
+                    profile,
+                    mode: mode.to_owned(),
+                    policy: Policy::Chromium(browser.policies_path),
+                });
+            }
+        }
+    }
+}
+
+/// Look for the browsers of every user that resolve through DNS over HTTPS,
+/// unless a policy turns it off
+pub fn scan(root: &Path) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    for home in home_directories(root) {
+        scan_firefox(&home, &mut findings);
+        scan_chromium(&home, &mut findings);
+    }
+    findings.retain(|finding| !finding.policy.is_applied(root));
+    findings
+}
+
+fn write_json(path: &Path, value: &Value) -> Result<(), ErrorKind> {
+    if let Some(parent) = path.parent() {
+        if let Err(err) = fs::create_dir_all(parent) {
+            return Err(ErrorKind::IOError(err));
+        }
+    }
+    let content = match serde_json::to_string_pretty(value) {
+        Ok(content) => content,
+        Err(err) => return Err(ErrorKind::SerdeJSONError(err)),
+    };
+    // Browsers read policies as the user running them
+    write_file(path, content.as_bytes(), 0o644)
+}
+
+/// Write the enterprise policies that turn DNS over HTTPS off for the browsers of `findings`,
+/// returns the written files
+pub fn write_policies(root: &Path, findings: &[Finding]) -> Result<Vec<PathBuf>, ErrorKind> {
+    let mut written = Vec::new();
+    for finding in findings {
+        let path = finding.policy.path(root);
+        if written.contains(&path) {
+            continue;
+        }
+        match finding.policy {
+            Policy::Firefox => {
+                // Other policies may already be there
+                let mut value = read_json(&path)
+                    .filter(Value::is_object)
+                    .unwrap_or_else(|| json!({}));
+                if !value["policies"].is_object() {
+                    value["policies"] = json!({});
+                }
+                value["policies"]["DNSOverHTTPS"] = json!({
+                    "Enabled": false,
+                    "Locked": true,
+                });
+                write_json(&path, &value)?;
+            }
+            Policy::Chromium(_) => write_json(&path, &json!({ "DnsOverHttpsMode": "off" }))?,
+        }
+        written.push(path);
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn write(root: &Path, path: &str, content: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    fn trr_mode(mode: u32) -> String {
+        format!(
+            "user_pref(\"network.trr.uri\", \"https://dns.example\");\n\
+            user_pref(\"network.trr.mode\", {});\n",
+            mode
+        )
+    }
+
+    fn chromium_mode(mode: &str) -> String {
+        json!({ "dns_over_https": { "mode": mode } }).to_string()
+    }
+
+    /// The profiles of the findings, relative to the root, with their mode
+    fn found(root: &Path, findings: &[Finding]) -> Vec<(String, String)> {
+        let mut found = findings
+            .iter()
+            .map(|finding| {
+                let profile = finding.profile.strip_prefix(root).unwrap();
+                (profile.display().to_string(), finding.mode.clone())
+            })
+            .collect::<Vec<_>>();
+        found.sort();
+        found
+    }
+
+    #[test]
+    fn finds_firefox_profiles_using_doh() {
+        let root = TempDir::new("doh-firefox");
+        let profiles = "home/alice/.mozilla/firefox";
+        write(
+            &root.path,
+            &format!("{}/fallback/prefs.js", profiles),
+            &trr_mode(2),
+        );
+        write(
+            &root.path,
+            &format!("{}/only/user.js", profiles),
+            &trr_mode(3),
+        );
+        write(
+            &root.path,
+            &format!("{}/off/prefs.js", profiles),
+            &trr_mode(5),
+        );
+        // user.js wins over prefs.js
+        write(
+            &root.path,
+            &format!("{}/reset/prefs.js", profiles),
+            &trr_mode(3),
+        );
+        write(
+            &root.path,
+            &format!("{}/reset/user.js", profiles),
+            &trr_mode(5),
+        );
+        write(
+            &root.path,
+            "root/snap/firefox/common/.mozilla/firefox/snap/prefs.js",
+            &trr_mode(3),
+        );
+
+        assert_eq!(
+            found(&root.path, &scan(&root.path)),
+            vec![
+                (format!("{}/fallback", profiles), String::from("2")),
+                (format!("{}/only", profiles), String::from("3")),
+                (
+                    String::from("root/snap/firefox/common/.mozilla/firefox/snap"),
+                    String::from("3")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_chromium_browsers_using_doh() {
+        let root = TempDir::new("doh-chromium");
+        write(
+            &root.path,
+            "home/alice/.config/google-chrome/Local State",
+            &chromium_mode("secure"),
+        );
+        write(
+            &root.path,
+            "root/.config/chromium/Local State",
+            &chromium_mode("automatic"),
+        );
+        write(
+            &root.path,
+            "home/bob/.config/BraveSoftware/Brave-Browser/Local State",
+            &chromium_mode("off"),
+        );
+
+        assert_eq!(
+            found(&root.path, &scan(&root.path)),
+            vec![
+                (
+                    String::from("home/alice/.config/google-chrome"),
+                    String::from("secure")
+                ),
+                (
+                    String::from("root/.config/chromium"),
+                    String::from("automatic")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn respects_applied_policies() {
+        let root = TempDir::new("doh-policies");
+        write(
+            &root.path,
+            "home/alice/.mozilla/firefox/default/prefs.js",
+            &trr_mode(2),
+        );
+        write(
+            &root.path,
+            "home/alice/.config/google-chrome/Local State",
+            &chromium_mode("secure"),
+        );
+        write(
+            &root.path,
+            "home/alice/.config/chromium/Local State",
+            &chromium_mode("secure"),
+        );
+        // Policies written by an administrator before, under their own names
+        write(
+            &root.path,
+            FIREFOX_POLICIES_PATH,
+            &json!({ "policies": { "DNSOverHTTPS": { "Enabled": false } } }).to_string(),
+        );
+        write(
+            &root.path,
+            "etc/opt/chrome/policies/managed/company.json",
+            &json!({ "DnsOverHttpsMode": "off" }).to_string(),
+        );
+
+        assert_eq!(
+            found(&root.path, &scan(&root.path)),
+            vec![(
+                String::from("home/alice/.config/chromium"),
+                String::from("secure")
+            )]
+        );
+    }
+
+    #[test]
+    fn written_policies_turn_doh_off() {
+        let root = TempDir::new("doh-write");
+        write(
+            &root.path,
+            "home/alice/.mozilla/firefox/default/prefs.js",
+            &trr_mode(3),
+        );
+        write(
+            &root.path,
+            "home/alice/.config/microsoft-edge/Local State",
+            &chromium_mode("secure"),
+        );
+        write(
+            &root.path,
+            FIREFOX_POLICIES_PATH,
+            &json!({ "policies": { "DisableTelemetry": true } }).to_string(),
+        );
+
+        let findings = scan(&root.path);
+        assert_eq!(findings.len(), 2);
+        let written = write_policies(&root.path, &findings).unwrap();
+        assert_eq!(written.len(), 2);
+        assert!(scan(&root.path).is_empty());
+
+        // The policies that were there already are kept
+        let policies = read_json(&root.path.join(FIREFOX_POLICIES_PATH)).unwrap();
+        assert_eq!(policies["policies"]["DisableTelemetry"], json!(true));
+    }
+}
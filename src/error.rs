@@ -2,13 +2,15 @@ use std::fmt;
 
 #[derive(Debug)]
 pub enum ErrorKind {
-    Error(Box<dyn std::error::Error>),
+    Error(Box<dyn std::error::Error + Send + Sync>),
     IOError(std::io::Error),
     PestRuleError(pest::error::Error<crate::parser::Rule>),
     SerdeJSONError(serde_json::Error),
-    #[cfg(any(target_os = "linux", target_os = "macos"))]
-    NixError(nix::Error),
     ZipError(zip::result::ZipError),
+    CurlError(curl::Error),
+    HTTPError(u32, String),
+    SignatureError(minisign_verify::Error),
+    Cancelled,
     String(String),
 }
 
@@ -18,10 +20,12 @@ impl fmt::Display for ErrorKind {
             ErrorKind::Error(err) => err.to_string(),
             ErrorKind::IOError(err) => err.to_string(),
             ErrorKind::PestRuleError(err) => err.to_string(),
-            #[cfg(any(target_os = "linux", target_os = "macos"))]
-            ErrorKind::NixError(err) => err.to_string(),
             ErrorKind::SerdeJSONError(err) => err.to_string(),
             ErrorKind::ZipError(err) => err.to_string(),
+            ErrorKind::CurlError(err) => err.to_string(),
+            ErrorKind::HTTPError(status, url) => format!("HTTP error {} from {}", status, url),
+            ErrorKind::SignatureError(err) => err.to_string(),
+            ErrorKind::Cancelled => String::from("The operation has been cancelled"),
             ErrorKind::String(err) => err.to_owned(),
         };
 
@@ -0,0 +1,326 @@
+use crate::error::ErrorKind;
+use crate::helpers::AppendableMap;
+use crate::list;
+use crate::os::write_file;
+use crate::parser::Hosts;
+use crate::state;
+use crate::subscription;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::net::IpAddr;
+use std::path::Path;
+
+/// Owner of the zone the RPZ export declares, any name works for a policy zone
+const RPZ_NAME_SERVER: &str = "localhost.";
+const RPZ_TTL: u32 = 300;
+
+/// The DNS servers the hosts list can be exported to, for the networks sharing a resolver
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+    /// `address=/domain/ip` lines
+    Dnsmasq,
+    /// `local-data` records of the server clause
+    Unbound,
+    /// BIND response policy zone
+    Rpz,
+    /// File read by the hosts plugin
+    CoreDns,
+    /// `custom.list` of the local DNS records
+    PiHole,
+}
+
+impl Format {
+    pub const ALL: [Format; 5] = [
+        Format::Dnsmasq,
+        Format::Unbound,
+        Format::Rpz,
+        Format::CoreDns,
+        Format::PiHole,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Format::Dnsmasq => "dnsmasq",
+            Format::Unbound => "unbound",
+            Format::Rpz => "rpz",
+            Format::CoreDns => "coredns",
+            Format::PiHole => "pihole",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Format> {
+        Format::ALL
+            .iter()
+            .copied()
+            .find(|format| format.as_str() == name)
+    }
+}
+
+/// The names of the sections of the hosts list in use
+pub fn section_names() -> Result<Vec<String>, ErrorKind> {
+    let mut names = Vec::new();
+    for section in list::sections(&list::current())? {
+        if !names.contains(&section.name) {
+            names.push(section.name);
+        }
+    }
+    Ok(names)
+}
+
+/// The selected sections of the hosts list in use, every section when none is selected,
+/// along with the entries of the subscriptions
+pub fn mapping(selected: &[String]) -> Result<Hosts, ErrorKind> {
+    let sections = list::sections(&list::current())?;
+    if let Some(name) = selected
+        .iter()
+        .find(|name| !sections.iter().any(|section| &section.name == *name))
+    {
+        return Err(ErrorKind::String(format!(
+            "The hosts list has no section named '{}'",
+            name
+        )));
+    }
+
+    let mut hosts: Hosts = HashMap::new();
+    for section in sections {
+        if selected.is_empty() || selected.contains(&section.name) {
+            hosts.append(section.hosts).ok();
+        }
+    }
+    hosts.append(subscription::cached_hosts()?).ok();
+    Ok(hosts)
+}
+
+/// The addresses of every hostname, sorted so that an unchanged mapping gives the same file.
+/// The hostnames lose their trailing dot, the servers read every name as absolute already.
+fn records(hosts: &Hosts) -> BTreeMap<String, BTreeSet<&str>> {
+    let mut records: BTreeMap<String, BTreeSet<&str>> = BTreeMap::new();
+    for (ip, hostnames) in hosts {
+        for hostname in hostnames {
+            let hostname = hostname.trim_end_matches('.').to_lowercase();
+            if !hostname.is_empty() {
+                records.entry(hostname).or_default().insert(ip.as_str());
+            }
+        }
+    }
+    records
+}
+
+fn record_type(ip: &str) -> &'static str {
+    match ip.parse::<IpAddr>() {
+        Ok(IpAddr::V6(_)) => "AAAA",
+        _ => "A",
+    }
+}
+
+fn dnsmasq(hosts: &Hosts) -> String {
+    // dnsmasq answers for the subdomains too, like the blocklists written for it expect
+    let mut content = String::from("# Generated by Bebasin, include it with conf-file=\n");
+    for (hostname, ips) in records(hosts) {
+        for ip in ips {
+            content.push_str(&format!("address=/{}/{}\n", hostname, ip));
+        }
+    }
+    content
+}
+
+/// `host-record` lines, which only answer for the names themselves like the hosts file does
+pub fn dnsmasq_host_records(hosts: &Hosts) -> String {
+    let mut content = String::from("# Generated by Bebasin\n");
+    for (hostname, ips) in records(hosts) {
+        for ip in ips {
+            content.push_str(&format!("host-record={},{}\n", hostname, ip));
+        }
+    }
+    content
+}
+
+fn unbound(hosts: &Hosts) -> String {
+    let mut content = String::from("# Generated by Bebasin, include it with include:\nserver:\n");
+    for (hostname, ips) in records(hosts) {
+        for ip in ips {
+            content.push_str(&format!(
+                "    local-data: \"{}. {} {}\"\n",
+                hostname,
+                record_type(ip),
+                ip
+            ));
+        }
+    }
+    content
+}
+
+fn rpz(hosts: &Hosts) -> String {
+    // The owner names are relative to the zone, whatever it is named in named.conf
+    let mut content = format!(
+        "; Generated by Bebasin, declare it as a response-policy zone\n\
+        $TTL {ttl}\n\
+        @ IN SOA {server} hostmaster.{server} {serial} 3600 600 86400 {ttl}\n\
+        \x20 IN NS {server}\n",
+        ttl = RPZ_TTL,
+        server = RPZ_NAME_SERVER,
+        serial = chrono::Local::now().format("%Y%m%d%H")
+    );
+    for (hostname, ips) in records(hosts) {
+        for ip in ips {
+            content.push_str(&format!("{} {} {}\n", hostname, record_type(ip), ip));
+        }
+    }
+    content
+}
+
+/// One hostname per line, which both the CoreDNS hosts plugin and Pi-hole read
+fn hosts_lines(hosts: &Hosts, header: &str) -> String {
+    let mut content = String::from(header);
+    for (hostname, ips) in records(hosts) {
+        for ip in ips {
+            content.push_str(&format!("{} {}\n", ip, hostname));
+        }
+    }
+    content
+}
+
+/// The mapping written for `format`
+pub fn export(hosts: &Hosts, format: Format) -> String {
+    match format {
+        Format::Dnsmasq => dnsmasq(hosts),
+        Format::Unbound => unbound(hosts),
+        Format::Rpz => rpz(hosts),
+        Format::CoreDns => hosts_lines(
+            hosts,
+            "# Generated by Bebasin, read it with the hosts plugin:\n\
+            # hosts /path/to/this/file { fallthrough }\n",
+        ),
+        // Pi-hole rewrites custom.list itself, comments wouldn't survive
+        Format::PiHole => hosts_lines(hosts, ""),
+    }
+}
+
+pub fn export_to_file(path: &str, hosts: &Hosts, format: Format) -> Result<(), ErrorKind> {
+    write_file(
+        Path::new(path),
+        export(hosts, format).as_bytes(),
+        state::FILE_MODE,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import::{self, DEFAULT_TARGET};
+    use crate::progress::Progress;
+    use std::collections::HashSet;
+
+    fn hosts(entries: &[(&str, &[&str])]) -> Hosts {
+        entries
+            .iter()
+            .map(|(ip, hostnames)| {
+                let hostnames = hostnames.iter().map(|hostname| hostname.to_string());
+                (ip.to_string(), hostnames.collect::<HashSet<_>>())
+            })
+            .collect()
+    }
+
+    fn mapping() -> Hosts {
+        hosts(&[
+            ("0.0.0.0", &["ads.example.com", "Tracker.Example.NET."]),
+            ("10.0.0.1", &["printer.example.com", "nas.example.com"]),
+            // A hostname with an address of each family
+            ("10.0.0.2", &["dual.example.com"]),
+            ("fd00::2", &["dual.example.com"]),
+            ("::", &["ads.example.com"]),
+        ])
+    }
+
+    /// What the servers resolve, the hostnames without their case nor their trailing dot
+    fn expected() -> Hosts {
+        hosts(&[
+            ("0.0.0.0", &["ads.example.com", "tracker.example.net"]),
+            ("10.0.0.1", &["printer.example.com", "nas.example.com"]),
+            ("10.0.0.2", &["dual.example.com"]),
+            ("fd00::2", &["dual.example.com"]),
+            ("::", &["ads.example.com"]),
+        ])
+    }
+
+    fn import_back(name: &str, exported: &str, import_format: import::Format) {
+        assert!(import::Format::detect(exported) == import_format);
+
+        let imported =
+            import::import(exported, import_format, DEFAULT_TARGET, &Progress::none()).unwrap();
+        assert!(
+            imported.unrepresentable.is_empty(),
+            "{} export has unrepresentable lines",
+            name
+        );
+        assert_eq!(imported.hosts, expected(), "{} export", name);
+    }
+
+    fn round_trip(format: Format, import_format: import::Format) {
+        import_back(format.as_str(), &export(&mapping(), format), import_format);
+    }
+
+    #[test]
+    fn dnsmasq_round_trip() {
+        round_trip(Format::Dnsmasq, import::Format::Dnsmasq);
+    }
+
+    #[test]
+    fn dnsmasq_host_records_round_trip() {
+        let exported = dnsmasq_host_records(&mapping());
+        // The drop-in must not block the subdomains
+        assert!(!exported.contains("address="));
+        import_back("host-record", &exported, import::Format::Dnsmasq);
+    }
+
+    #[test]
+    fn unbound_round_trip() {
+        round_trip(Format::Unbound, import::Format::Unbound);
+    }
+
+    #[test]
+    fn rpz_round_trip() {
+        round_trip(Format::Rpz, import::Format::Rpz);
+    }
+
+    #[test]
+    fn hosts_lines_round_trip() {
+        round_trip(Format::CoreDns, import::Format::Hosts);
+        round_trip(Format::PiHole, import::Format::Hosts);
+    }
+
+    #[test]
+    fn exports_are_stable() {
+        // The same mapping built in another order gives the same file
+        let mut reordered = Hosts::new();
+        for (ip, hostnames) in mapping() {
+            reordered.insert(ip, hostnames);
+        }
+        // The serial of the RPZ export follows the clock
+        for format in Format::ALL
+            .iter()
+            .copied()
+            .filter(|format| *format != Format::Rpz)
+        {
+            assert_eq!(export(&mapping(), format), export(&reordered, format));
+        }
+    }
+
+    #[test]
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    fn exports_refuse_symbolic_links() {
+        use crate::testing::TempDir;
+        use std::fs;
+
+        let directory = TempDir::new("hosts-export");
+        let victim = directory.path.join("victim");
+        fs::write(&victim, "untouched").unwrap();
+        let link = directory.path.join("bebasin.conf");
+        std::os::unix::fs::symlink(&victim, &link).unwrap();
+
+        match export_to_file(&link.display().to_string(), &mapping(), Format::Dnsmasq) {
+            Err(ErrorKind::String(_)) => {}
+            result => panic!("unexpected result {:?}", result.map(|_| ())),
+        }
+        assert_eq!(fs::read_to_string(&victim).unwrap(), "untouched");
+    }
+}
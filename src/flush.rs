@@ -0,0 +1,121 @@
+use std::process::{Command, Stdio};
+
+/// The DNS caches found after a change of the hosts file
+#[derive(Default)]
+pub struct Report {
+    flushed: Vec<&'static str>,
+    failed: Vec<&'static str>,
+}
+
+impl Report {
+    fn add(&mut self, cache: &'static str, is_flushed: bool) {
+        if is_flushed {
+            self.flushed.push(cache);
+        } else {
+            self.failed.push(cache);
+        }
+    }
+
+    /// What the user has to know after changing the hosts file
+    pub fn describe(&self) -> String {
+        let mut lines = Vec::new();
+        if !self.flushed.is_empty() {
+            lines.push(format!(
+                "The DNS cache of {} has been flushed",
+                self.flushed.join(", ")
+            ));
+        }
+        if !self.failed.is_empty() {
+            lines.push(format!(
+                "The DNS cache of {} could not be flushed,\n\
+                Please restart your network/machine",
+                self.failed.join(", ")
+            ));
+        }
+        if lines.is_empty() {
+            lines.push(String::from(
+                "No known DNS cache was found, the applications keeping a cache of their own\n\
+                may need a restart to pick up the change",
+            ));
+        }
+        lines.join("\n")
+    }
+}
+
+fn run(program: &str, args: &[&str]) -> bool {
+    Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(false)
+}
+
+/// Processes running `name`, as shown in /proc/<pid>/comm
+#[cfg(target_os = "linux")]
+pub fn find_processes(name: &str) -> Vec<i32> {
+    let entries = match std::fs::read_dir("/proc") {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().to_str()?.parse::<i32>().ok())
+        .filter(|pid| {
+            std::fs::read_to_string(format!("/proc/{}/comm", pid))
+                .map(|comm| comm.trim() == name)
+                .unwrap_or(false)
+        })
+        .collect()
+}
+
+/// Flush every DNS cache that would keep serving the previous entries
+#[cfg(target_os = "linux")]
+pub fn flush() -> Report {
+    use nix::sys::signal::{kill, Signal};
+    use nix::unistd::Pid;
+
+    let mut report = Report::default();
+
+    // The process name is truncated to 15 characters
+    if !find_processes("systemd-resolve").is_empty() {
+        let is_flushed =
+            run("resolvectl", &["flush-caches"]) || run("systemd-resolve", &["--flush-caches"]);
+        report.add("systemd-resolved", is_flushed);
+    }
+    if !find_processes("nscd").is_empty() {
+        report.add("nscd", run("nscd", &["--invalidate=hosts"]));
+    }
+    // dnsmasq reads the hosts file again and clears its cache on SIGHUP
+    let dnsmasq = find_processes("dnsmasq");
+    if !dnsmasq.is_empty() {
+        let is_flushed = dnsmasq
+            .into_iter()
+            .all(|pid| kill(Pid::from_raw(pid), Signal::SIGHUP).is_ok());
+        report.add("dnsmasq", is_flushed);
+    }
+    if !find_processes("unbound").is_empty() {
+        report.add("unbound", run("unbound-control", &["reload"]));
+    }
+
+    report
+}
+
+#[cfg(target_os = "macos")]
+pub fn flush() -> Report {
+    let mut report = Report::default();
+    report.add(
+        "macOS",
+        run("dscacheutil", &["-flushcache"]) && run("killall", &["-HUP", "mDNSResponder"]),
+    );
+    report
+}
+
+#[cfg(target_os = "windows")]
+pub fn flush() -> Report {
+    let mut report = Report::default();
+    report.add("Windows", run("ipconfig", &["/flushdns"]));
+    report
+}
@@ -0,0 +1,440 @@
+use crate::audit::{self, Operation, Snapshot};
+use crate::config::Config;
+use crate::error::ErrorKind;
+use crate::list::{self, Fetched};
+use crate::os::{self, is_has_admin_access, HOSTS_BACKUP_PATH};
+use crate::parser::{parse_from_str, Hosts};
+use crate::policy;
+use crate::signature;
+use crate::target::{self, Kind};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead as _, BufReader, Read as _, Write as _};
+use std::net::IpAddr;
+use std::process::{Child, ChildStdin, ChildStdout, Stdio};
+use std::sync::Mutex;
+
+/// Anything longer is refused before being parsed
+const MAX_REQUEST_SIZE: u64 = 64 * 1024 * 1024;
+const MAX_SOURCE_LENGTH: usize = 4096;
+
+/// The only operations the privileged helper accepts, one JSON object per line.
+/// Nothing coming from the unprivileged side is trusted, the helper checks it again.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "operation", rename_all = "kebab-case")]
+pub enum Request {
+    /// Install the hosts list in use and the subscriptions, as the helper reads them itself
+    Install,
+    /// Write a custom hosts list as the block managed by Bebasin, on top of the original hosts file.
+    /// The entries for protected hostnames are refused, they have to be left out beforehand.
+    CustomImport { source: String, hosts: String },
+    /// Cache a fetched hosts list, and write it as the managed block when Bebasin is installed.
+    /// Its signature is verified again against the configuration of the privileged side.
+    ApplyList { fetched: Fetched },
+    /// Remove the mapping from the install target, the original hosts file is put back
+    Uninstall,
+    /// Flush the DNS caches, and restart the DNS server of the install target
+    Reload,
+}
+
+/// The answer to a request, `message` describes the result or the error
+#[derive(Serialize, Deserialize)]
+struct Response {
+    ok: bool,
+    message: String,
+}
+
+/// The running helper, when this process isn't privileged itself
+struct Client {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+static CLIENT: Mutex<Option<Client>> = Mutex::new(None);
+
+impl Client {
+    fn read_response(&mut self) -> Result<Response, ErrorKind> {
+        let mut line = String::new();
+        match self.stdout.read_line(&mut line) {
+            Ok(0) => Err(ErrorKind::IOError(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "The privileged helper has stopped",
+            ))),
+            Ok(_) => match serde_json::from_str(&line) {
+                Ok(response) => Ok(response),
+                Err(err) => Err(ErrorKind::SerdeJSONError(err)),
+            },
+            Err(err) => Err(ErrorKind::IOError(err)),
+        }
+    }
+
+    fn call(&mut self, request: &Request) -> Result<String, ErrorKind> {
+        let line = match serde_json::to_string(request) {
+            Ok(line) => line,
+            Err(err) => return Err(ErrorKind::SerdeJSONError(err)),
+        };
+        if let Err(err) = writeln!(self.stdin, "{}", line).and_then(|_| self.stdin.flush()) {
+            return Err(ErrorKind::IOError(err));
+        }
+
+        let response = self.read_response()?;
+        if response.ok {
+            Ok(response.message)
+        } else {
+            Err(ErrorKind::String(response.message))
+        }
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        // The helper stops at the end of its input
+        self.child.wait().ok();
+    }
+}
+
+/// Start the helper through sudo or pkexec, which may ask for a password on the terminal
+pub fn start() -> Result<(), ErrorKind> {
+    let mut command = os::admin_command(&["helper"])?;
+    let mut child = match command.stdin(Stdio::piped()).stdout(Stdio::piped()).spawn() {
+        Ok(child) => child,
+        Err(err) => return Err(ErrorKind::IOError(err)),
+    };
+    let (stdin, stdout) = match (child.stdin.take(), child.stdout.take()) {
+        (Some(stdin), Some(stdout)) => (stdin, BufReader::new(stdout)),
+        _ => {
+            child.kill().ok();
+            return Err(ErrorKind::String(String::from(
+                "The privileged helper could not be started",
+            )));
+        }
+    };
+    let mut client = Client {
+        child,
+        stdin,
+        stdout,
+    };
+
+    // The helper greets once it runs, which is after the password has been typed
+    match client.read_response() {
+        Ok(ref response) if response.ok => {}
+        _ => {
+            return Err(ErrorKind::String(String::from(
+                "The privileged helper could not be started",
+            )))
+        }
+    }
+    if let Ok(mut guard) = CLIENT.lock() {
+        *guard = Some(client);
+    }
+    Ok(())
+}
+
+pub fn is_running() -> bool {
+    CLIENT.lock().map(|guard| guard.is_some()).unwrap_or(false)
+}
+
+/// Run `request` right away with administrator rights, otherwise through the helper
+pub fn execute(request: Request) -> Result<String, ErrorKind> {
+    if is_has_admin_access() {
+        return handle(request);
+    }
+
+    let mut guard = match CLIENT.lock() {
+        Ok(guard) => guard,
+        Err(_) => {
+            return Err(ErrorKind::String(String::from(
+                "The privileged helper is unusable",
+            )))
+        }
+    };
+    let result = match guard.as_mut() {
+        Some(client) => client.call(&request),
+        None => {
+            return Err(ErrorKind::String(String::from(
+                "Changing the hosts file needs administrator rights",
+            )))
+        }
+    };
+    // A refused request is fine, anything else means the helper is gone
+    if let Err(ErrorKind::IOError(_)) | Err(ErrorKind::SerdeJSONError(_)) = result {
+        *guard = None;
+    }
+    result
+}
+
+fn validate_source(source: &str) -> Result<(), ErrorKind> {
+    if source.is_empty() || source.len() > MAX_SOURCE_LENGTH || source.chars().any(char::is_control)
+    {
+        return Err(ErrorKind::String(String::from("Invalid source")));
+    }
+    Ok(())
+}
+
+/// Parse hosts coming from the unprivileged side, every address has to be an IP address
+fn validate_hosts(content: &str) -> Result<Hosts, ErrorKind> {
+    let hosts = parse_from_str(content)?;
+    if let Some(ip) = hosts.keys().find(|ip| ip.parse::<IpAddr>().is_err()) {
+        return Err(ErrorKind::String(format!("Invalid IP address '{}'", ip)));
+    }
+    Ok(hosts)
+}
+
+/// The hosts list in use comes from the state directory, the subscriptions were filtered
+/// by the protected hostnames policy when they were fetched
+fn install() -> Result<String, ErrorKind> {
+    let hosts = list::with_subscriptions(&list::current())?;
+    let summary = list::write_managed_block(hosts, Operation::Install, &list::current_source())?;
+    list::set_custom_source(None)?;
+    Ok(summary)
+}
+
+/// The protected hostnames are refused whatever the unprivileged side asked,
+/// the configuration of the privileged side decides what is protected
+fn custom_import(source: &str, content: &str) -> Result<String, ErrorKind> {
+    validate_source(source)?;
+    let hosts = validate_hosts(content)?;
+    let violations = policy::check(&hosts, &Config::load()?);
+    if !violations.is_empty() {
+        return Err(ErrorKind::String(format!(
+            "Refusing to redirect protected hostnames:\n{}",
+            violations
+                .iter()
+                .map(policy::Violation::describe)
+                .collect::<Vec<_>>()
+                .join("\n")
+        )));
+    }
+    // The subscriptions come along with the Bebasin hosts list, not with a custom one
+    let summary = list::write_managed_block(hosts, Operation::CustomImport, source)?;
+    // A refresh of the Bebasin hosts list must not replace it
+    list::set_custom_source(Some(source))?;
+    Ok(summary)
+}
+
+/// Check a fetched hosts list again, against the configuration of the privileged side
+fn verify_fetched(fetched: &Fetched, config: &Config) -> Result<(), ErrorKind> {
+    validate_source(&fetched.info.source)?;
+    // The unprivileged side verified it already, but it could have sent anything
+    signature::verify_or_refuse(
+        fetched.content.as_bytes(),
+        fetched.signature.as_deref(),
+        config.allow_unsigned,
+        &format!("The hosts list from {}", fetched.info.source),
+    )?;
+    // The checksum is what keeps the cache from being tampered with later
+    if signature::sha256(fetched.content.as_bytes()) != fetched.info.checksum {
+        return Err(ErrorKind::String(String::from(
+            "The hosts list doesn't match its checksum",
+        )));
+    }
+    validate_hosts(&fetched.content)?;
+    Ok(())
+}
+
+fn apply_list(fetched: &Fetched) -> Result<String, ErrorKind> {
+    verify_fetched(fetched, &Config::load()?)?;
+    list::apply(fetched)
+}
+
+fn uninstall() -> Result<String, ErrorKind> {
+    let target = target::configured();
+    let before = Snapshot::take();
+    target.uninstall()?;
+    list::set_custom_source(None)?;
+    let path = target.path().display().to_string();
+    // The hosts file gets its original content back, a drop-in is only removed
+    let (operation, source) = if target.kind() == Kind::HostsFile {
+        (Operation::Restore, HOSTS_BACKUP_PATH.to_owned())
+    } else {
+        (Operation::Uninstall, path.clone())
+    };
+    Ok(audit::record_with_warning(
+        &format!("Bebasin has been removed from {}", path),
+        operation,
+        &source,
+        before,
+    ))
+}
+
+/// Carry out a request, this is the only code running with administrator rights
+/// when the helper is used
+pub fn handle(request: Request) -> Result<String, ErrorKind> {
+    match request {
+        Request::Install => install(),
+        Request::CustomImport { source, hosts } => custom_import(&source, &hosts),
+        Request::ApplyList { fetched } => apply_list(&fetched),
+        Request::Uninstall => uninstall(),
+        Request::Reload => Ok(target::configured().reload()),
+    }
+}
+
+fn respond(response: Response) -> Result<(), ErrorKind> {
+    let line = match serde_json::to_string(&response) {
+        Ok(line) => line,
+        Err(err) => return Err(ErrorKind::SerdeJSONError(err)),
+    };
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    match writeln!(stdout, "{}", line).and_then(|_| stdout.flush()) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(ErrorKind::IOError(err)),
+    }
+}
+
+/// Answer the requests read from the standard input until it is closed.
+/// This is what `bebasin helper` runs as root.
+pub fn serve() -> Result<(), ErrorKind> {
+    if !is_has_admin_access() {
+        return Err(ErrorKind::String(String::from(
+            "The helper has to run with administrator rights",
+        )));
+    }
+    respond(Response {
+        ok: true,
+        message: String::from("ready"),
+    })?;
+
+    let stdin = std::io::stdin();
+    let mut stdin = stdin.lock();
+    loop {
+        let mut line = String::new();
+        match (&mut stdin).take(MAX_REQUEST_SIZE).read_line(&mut line) {
+            Ok(0) => return Ok(()),
+            Ok(_) => {}
+            Err(err) => return Err(ErrorKind::IOError(err)),
+        }
+        if line.len() as u64 == MAX_REQUEST_SIZE && !line.ends_with('\n') {
+            return Err(ErrorKind::String(String::from("The request is too long")));
+        }
+
+        let result = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => handle(request),
+            Err(err) => Err(ErrorKind::SerdeJSONError(err)),
+        };
+        respond(match result {
+            Ok(message) => Response { ok: true, message },
+            Err(err) => Response {
+                ok: false,
+                message: err.to_string(),
+            },
+        })?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::list::ListInfo;
+
+    const CONTENT: &str = "0.0.0.0 ads.example.com\n";
+
+    fn fetched(content: &str, signature: Option<&str>) -> Fetched {
+        Fetched {
+            content: content.to_owned(),
+            info: ListInfo {
+                version: None,
+                checksum: signature::sha256(CONTENT.as_bytes()),
+                source: String::from("https://example.com/hosts"),
+                fetched_at: String::new(),
+            },
+            signature: signature.map(str::to_owned),
+        }
+    }
+
+    fn unsigned_allowed() -> Config {
+        Config {
+            allow_unsigned: true,
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn decodes_requests() {
+        match serde_json::from_str::<Request>(r#"{"operation":"install"}"#) {
+            Ok(Request::Install) => {}
+            _ => panic!("install request not decoded"),
+        }
+        match serde_json::from_str::<Request>(
+            r#"{"operation":"custom-import","source":"hosts.txt","hosts":"0.0.0.0 a.example"}"#,
+        ) {
+            Ok(Request::CustomImport { source, hosts }) => {
+                assert_eq!(source, "hosts.txt");
+                assert_eq!(hosts, "0.0.0.0 a.example");
+            }
+            _ => panic!("custom import request not decoded"),
+        }
+
+        let request = Request::ApplyList {
+            fetched: fetched(CONTENT, Some("signature")),
+        };
+        let line = serde_json::to_string(&request).unwrap();
+        match serde_json::from_str::<Request>(&line) {
+            Ok(Request::ApplyList { fetched }) => {
+                assert_eq!(fetched.content, CONTENT);
+                assert_eq!(fetched.signature.as_deref(), Some("signature"));
+            }
+            _ => panic!("apply list request not decoded"),
+        }
+
+        for line in &[
+            r#"{"operation":"format-disk"}"#,
+            r#"{"operation":"custom-import","source":"hosts.txt"}"#,
+            r#"{"source":"hosts.txt","hosts":""}"#,
+            "install",
+        ] {
+            assert!(serde_json::from_str::<Request>(line).is_err(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn refuses_invalid_hosts_and_sources() {
+        // Neither a hostname nor out of range numbers are IP addresses
+        for content in &["localhost example.com\n", "999.0.0.1 example.com\n"] {
+            assert!(validate_hosts(content).is_err(), "{}", content);
+        }
+        assert!(validate_hosts(CONTENT).is_ok());
+
+        for source in &[
+            "",
+            "hosts\nfrom elsewhere",
+            &"a".repeat(MAX_SOURCE_LENGTH + 1),
+        ] {
+            assert!(validate_source(source).is_err());
+        }
+    }
+
+    #[test]
+    fn custom_imports_refuse_protected_hostnames() {
+        // The request has no way to confirm them
+        match custom_import("hosts.txt", "0.0.0.0 api.github.com\n") {
+            Err(ErrorKind::String(_)) => {}
+            result => panic!("unexpected result {:?}", result.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn fetched_lists_are_verified_again() {
+        // Unsigned lists are only accepted when the privileged configuration allows it
+        match verify_fetched(&fetched(CONTENT, None), &Config::default()) {
+            Err(ErrorKind::String(_)) => {}
+            result => panic!("unexpected result {:?}", result),
+        }
+        assert!(verify_fetched(&fetched(CONTENT, None), &unsigned_allowed()).is_ok());
+
+        match verify_fetched(
+            &fetched(CONTENT, Some("not a signature")),
+            &unsigned_allowed(),
+        ) {
+            Err(ErrorKind::SignatureError(_)) => {}
+            result => panic!("unexpected result {:?}", result),
+        }
+
+        // The content differs from what its checksum was computed from
+        let tampered = fetched("0.0.0.0 bank.example.com\n", None);
+        match verify_fetched(&tampered, &unsigned_allowed()) {
+            Err(ErrorKind::String(_)) => {}
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+}
@@ -0,0 +1,724 @@
+use crate::error::ErrorKind;
+use crate::parser::{parse_from_str, Hosts};
+use crate::progress::Progress;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+
+/// Where the blocked domains point to, for the formats that don't name an address
+pub const DEFAULT_TARGET: &str = "0.0.0.0";
+const MAX_HOSTNAME_LENGTH: usize = 253;
+/// How many lines are looked at to detect the format
+const DETECTION_LINES: usize = 50;
+
+/// The blocklist and allowlist formats a custom hosts list can be imported from
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+    Hosts,
+    /// One domain per line
+    Domains,
+    /// Adblock Plus style `||domain^` rules
+    Adblock,
+    /// dnsmasq `address=/domain/ip` and `host-record=domain,ip` lines
+    Dnsmasq,
+    /// unbound `local-data` records and `local-zone` declarations
+    Unbound,
+    /// Response policy zone file
+    Rpz,
+}
+
+impl Format {
+    pub const ALL: [Format; 6] = [
+        Format::Hosts,
+        Format::Domains,
+        Format::Adblock,
+        Format::Dnsmasq,
+        Format::Unbound,
+        Format::Rpz,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Format::Hosts => "hosts",
+            Format::Domains => "domains",
+            Format::Adblock => "adblock",
+            Format::Dnsmasq => "dnsmasq",
+            Format::Unbound => "unbound",
+            Format::Rpz => "rpz",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Format> {
+        Format::ALL
+            .iter()
+            .copied()
+            .find(|format| format.as_str() == name)
+    }
+
+    /// Guess the format from the first lines that aren't comments
+    pub fn detect(content: &str) -> Format {
+        let lines = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| {
+                !line.is_empty()
+                    && !line.starts_with('#')
+                    && !line.starts_with(';')
+                    && !line.starts_with('!')
+            })
+            .take(DETECTION_LINES)
+            .collect::<Vec<_>>();
+
+        let any = |predicate: &dyn Fn(&str) -> bool| lines.iter().any(|line| predicate(line));
+        if any(&|line| line.starts_with("||") || line.starts_with("@@||")) {
+            Format::Adblock
+        } else if any(&|line| {
+            line.starts_with("address=/")
+                || line.starts_with("local=/")
+                || line.starts_with("host-record=")
+        }) {
+            Format::Dnsmasq
+        } else if any(&|line| line.starts_with("local-data:") || line.starts_with("local-zone:")) {
+            Format::Unbound
+        } else if any(&|line| {
+            line.starts_with("$ORIGIN") || line.starts_with("$TTL") || line.contains(" SOA ")
+        }) {
+            Format::Rpz
+        } else if any(&|line| match line.split_whitespace().next() {
+            Some(first) => first.parse::<IpAddr>().is_ok(),
+            None => false,
+        }) {
+            Format::Hosts
+        } else {
+            Format::Domains
+        }
+    }
+}
+
+/// A line of the imported list that has no equivalent in a hosts file
+pub struct Unrepresentable {
+    pub line: usize,
+    pub content: String,
+    pub reason: &'static str,
+}
+
+impl Unrepresentable {
+    pub fn describe(&self) -> String {
+        format!("line {}: {} ({})", self.line, self.content, self.reason)
+    }
+}
+
+pub struct Imported {
+    pub hosts: Hosts,
+    pub unrepresentable: Vec<Unrepresentable>,
+}
+
+/// The entries a line of the list stands for, none for comments and declarations.
+/// An error tells why the line can't be represented.
+type Entries = Result<Vec<(String, String)>, &'static str>;
+
+/// A domain as it is written to the hosts file, in lowercase and without the trailing dot
+fn normalize_hostname(name: &str) -> Option<String> {
+    let name = name.trim_end_matches('.').to_lowercase();
+    let is_valid = !name.is_empty()
+        && name.len() <= MAX_HOSTNAME_LENGTH
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+        && !name.starts_with('.')
+        && !name.starts_with('-')
+        && !name.contains("..");
+    if is_valid {
+        Some(name)
+    } else {
+        None
+    }
+}
+
+fn entry(ip: &str, name: &str) -> Entries {
+    if name.starts_with("*.") {
+        return Err("wildcards match subdomains, a hosts file can't");
+    }
+    match normalize_hostname(name) {
+        Some(hostname) => Ok(vec![(ip.to_owned(), hostname)]),
+        None => Err("not a valid domain"),
+    }
+}
+
+fn parse_hosts(line: &str) -> Entries {
+    // The grammar only allows a comment at the end of a line it has ended
+    match parse_from_str(&format!("{}\n", line)) {
+        Ok(hosts) => Ok(hosts
+            .into_iter()
+            .flat_map(|(ip, hostnames)| {
+                hostnames
+                    .into_iter()
+                    .map(move |hostname| (ip.clone(), hostname))
+            })
+            .collect()),
+        Err(_) => Err("not an address followed by hostnames"),
+    }
+}
+
+fn parse_domain(line: &str, target: &str) -> Entries {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(Vec::new());
+    }
+    let mut words = line.split_whitespace();
+    match (words.next(), words.next()) {
+        (Some(domain), None) => entry(target, domain),
+        // A trailing comment
+        (Some(domain), Some(word)) if word.starts_with('#') => entry(target, domain),
+        _ => Err("more than a domain on the line"),
+    }
+}
+
+fn parse_adblock(line: &str, target: &str) -> Entries {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('!') || line.starts_with('[') {
+        return Ok(Vec::new());
+    }
+    if line.starts_with("@@") {
+        return Err("exceptions can't be expressed in a hosts file");
+    }
+    if line.contains("##") || line.contains("#@#") || line.contains("#?#") {
+        return Err("element hiding rules only apply to web pages");
+    }
+    let domain = match line
+        .strip_prefix("||")
+        .and_then(|rule| rule.strip_suffix('^'))
+    {
+        Some(domain) => domain,
+        None => match line
+            .strip_prefix("||")
+            .and_then(|rule| rule.split_once("^$"))
+        {
+            Some(_) => return Err("rule options can't be expressed in a hosts file"),
+            None => return Err("only ||domain^ rules block a whole domain"),
+        },
+    };
+    if domain.contains('/') || domain.contains('*') {
+        return Err("only ||domain^ rules block a whole domain");
+    }
+    entry(target, domain)
+}
+
+fn parse_dnsmasq(line: &str, target: &str) -> Entries {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(Vec::new());
+    }
+    let (directive, value) = match line.split_once('=') {
+        Some(split) => split,
+        None => return Err("not an address, local or host-record directive"),
+    };
+    if directive.trim() == "host-record" {
+        return parse_dnsmasq_host_record(value);
+    }
+    let value = match (directive.trim(), value.trim().strip_prefix('/')) {
+        ("address", Some(value)) | ("local", Some(value)) => value,
+        ("server", _) => return Err("forwarding to another server has no hosts equivalent"),
+        _ => return Err("not an address, local or host-record directive"),
+    };
+
+    // address=/first/second/ip, the domains are followed by the address
+    let mut parts = value.split('/').collect::<Vec<_>>();
+    let ip = match parts.pop() {
+        // No address or `#` both mean the domains don't resolve
+        Some("") | Some("#") | None => target,
+        Some(ip) => match ip.parse::<IpAddr>() {
+            Ok(_) if directive.trim() == "address" => ip,
+            _ => return Err("not a valid address"),
+        },
+    };
+    if parts.contains(&"#") {
+        return Err("matching every domain has no hosts equivalent");
+    }
+    let mut entries = Vec::new();
+    for domain in parts {
+        entries.extend(entry(ip, domain)?);
+    }
+    Ok(entries)
+}
+
+/// host-record=first,second,ipv4,ipv6,ttl, the names are followed by the addresses
+fn parse_dnsmasq_host_record(value: &str) -> Entries {
+    let mut names = Vec::new();
+    let mut ips = Vec::new();
+    for field in value.split(',').map(str::trim) {
+        if field.parse::<IpAddr>().is_ok() {
+            ips.push(field);
+        } else if !ips.is_empty() && field.parse::<u32>().is_ok() {
+            // The TTL comes last
+        } else if ips.is_empty() {
+            names.push(field);
+        } else {
+            return Err("not a valid address");
+        }
+    }
+    if names.is_empty() || ips.is_empty() {
+        return Err("a host record needs a name and an address");
+    }
+
+    let mut entries = Vec::new();
+    for ip in ips {
+        for name in &names {
+            entries.extend(entry(ip, name)?);
+        }
+    }
+    Ok(entries)
+}
+
+/// The value of an unbound option, without its quotes
+fn unquote(value: &str) -> &str {
+    value.trim().trim_matches('"')
+}
+
+/// Zone types answering without the real records. A static zone answers with its
+/// local-data records, it only blocks the names that have none.
+const UNBOUND_BLOCKING_ZONES: [&str; 7] = [
+    "refuse",
+    "deny",
+    "always_refuse",
+    "always_nxdomain",
+    "always_null",
+    "inform_deny",
+    "always_deny",
+];
+
+/// The name a local-data line gives a record to
+fn unbound_record_owner(line: &str) -> Option<String> {
+    let record = line.trim().strip_prefix("local-data:")?;
+    normalize_hostname(unquote(record).split_whitespace().next()?)
+}
+
+fn parse_unbound(line: &str, target: &str, owners: &HashSet<String>) -> Entries {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') || line == "server:" {
+        return Ok(Vec::new());
+    }
+
+    if let Some(record) = line.strip_prefix("local-data:") {
+        let words = unquote(record).split_whitespace().collect::<Vec<_>>();
+        // name [ttl] [class] type value
+        let (name, rest) = match words.split_first() {
+            Some(split) => split,
+            None => return Err("not a valid record"),
+        };
+        let rest = rest
+            .iter()
+            .skip_while(|word| word.parse::<u32>().is_ok() || word.eq_ignore_ascii_case("IN"))
+            .collect::<Vec<_>>();
+        return match rest.as_slice() {
+            [kind, ip] if kind.eq_ignore_ascii_case("A") || kind.eq_ignore_ascii_case("AAAA") => {
+                match ip.parse::<IpAddr>() {
+                    Ok(_) => entry(ip, name),
+                    Err(_) => Err("not a valid address"),
+                }
+            }
+            _ => Err("only A and AAAA records have a hosts equivalent"),
+        };
+    }
+
+    if let Some(zone) = line.strip_prefix("local-zone:") {
+        let words = zone.split_whitespace().map(unquote).collect::<Vec<_>>();
+        return match words.as_slice() {
+            [name, kind] if UNBOUND_BLOCKING_ZONES.contains(kind) => entry(target, name),
+            [name, "static"] => match normalize_hostname(name) {
+                // The zone answers with the records, which come with their own lines
+                Some(ref name) if owners.contains(name) => Ok(Vec::new()),
+                _ => entry(target, name),
+            },
+            // The records of the zone come with their own local-data lines
+            [_, "redirect"] | [_, "transparent"] | [_, "typetransparent"] => Ok(Vec::new()),
+            _ => Err("this zone type has no hosts equivalent"),
+        };
+    }
+    Err("not a local-data or local-zone line")
+}
+
+/// Reads a response policy zone line by line, the owner names are relative to the zone
+struct RpzReader {
+    origin: Option<String>,
+    owner: Option<String>,
+    /// Inside the parentheses of a record spanning several lines, like the SOA one
+    is_continued: bool,
+}
+
+/// Suffixes of the triggers matching something else than the queried name
+const RPZ_OTHER_TRIGGERS: [&str; 4] = ["rpz-ip", "rpz-nsdname", "rpz-nsip", "rpz-client-ip"];
+
+impl RpzReader {
+    /// The queried name an owner name stands for, relative to the zone
+    fn trigger(&self, owner: &str) -> Option<String> {
+        let owner = owner.to_lowercase();
+        match owner.strip_suffix('.') {
+            Some(absolute) => match &self.origin {
+                Some(origin) => absolute
+                    .strip_suffix(origin.as_str())
+                    .and_then(|name| name.strip_suffix('.'))
+                    .map(str::to_owned),
+                None => Some(absolute.to_owned()),
+            },
+            None => Some(owner),
+        }
+    }
+
+    fn parse(&mut self, line: &str, target: &str) -> Entries {
+        let line = match line.split_once(';') {
+            Some((line, _)) => line,
+            None => line,
+        };
+        if self.is_continued {
+            self.is_continued = !line.contains(')');
+            return Ok(Vec::new());
+        }
+        if line.trim().is_empty() {
+            return Ok(Vec::new());
+        }
+        if line.contains('(') && !line.contains(')') {
+            self.is_continued = true;
+        }
+
+        let mut words = line.split_whitespace().collect::<Vec<_>>();
+        if let Some(directive) = words.first() {
+            if directive.eq_ignore_ascii_case("$ORIGIN") {
+                self.origin = words
+                    .get(1)
+                    .map(|origin| origin.trim_end_matches('.').to_lowercase());
+                return Ok(Vec::new());
+            }
+            if directive.starts_with('$') {
+                return Ok(Vec::new());
+            }
+        }
+        // A line starting with a blank belongs to the previous owner
+        if !line.starts_with(' ') && !line.starts_with('\t') {
+            self.owner = Some(words.remove(0).to_owned());
+        }
+        let owner = match &self.owner {
+            Some(owner) => owner.clone(),
+            None => return Err("a record without an owner"),
+        };
+
+        // [ttl] [class] type value
+        let rest = words
+            .into_iter()
+            .skip_while(|word| word.parse::<u32>().is_ok() || word.eq_ignore_ascii_case("IN"))
+            .collect::<Vec<_>>();
+        let (kind, value) = match rest.as_slice() {
+            [kind, value, ..] => (kind.to_uppercase(), *value),
+            _ => return Err("not a valid record"),
+        };
+        if kind == "SOA" || kind == "NS" || owner == "@" {
+            return Ok(Vec::new());
+        }
+
+        let name = match self.trigger(&owner) {
+            Some(name) => name,
+            None => return Err("the owner is outside of the zone"),
+        };
+        if RPZ_OTHER_TRIGGERS
+            .iter()
+            .any(|trigger| name == *trigger || name.ends_with(&format!(".{}", trigger)))
+        {
+            return Err("only triggers on the queried name have a hosts equivalent");
+        }
+        match (kind.as_str(), value) {
+            // NXDOMAIN, NODATA and dropping all keep the domain from resolving
+            ("CNAME", ".") | ("CNAME", "*.") | ("CNAME", "rpz-drop.") => entry(target, &name),
+            ("CNAME", "rpz-passthru.") => Err("exceptions can't be expressed in a hosts file"),
+            ("CNAME", _) => Err("redirecting to another name has no hosts equivalent"),
+            ("A", ip) | ("AAAA", ip) => match ip.parse::<IpAddr>() {
+                Ok(_) => entry(ip, &name),
+                Err(_) => Err("not a valid address"),
+            },
+            _ => Err("only CNAME, A and AAAA records have a hosts equivalent"),
+        }
+    }
+}
+
+/// Turn every line of `content` into hosts entries, collecting the ones that can't be
+fn import_lines<F>(content: &str, progress: &Progress, mut parse: F) -> Result<Imported, ErrorKind>
+where
+    F: FnMut(&str) -> Entries,
+{
+    let mut imported = Imported {
+        hosts: HashMap::new(),
+        unrepresentable: Vec::new(),
+    };
+    for (index, line) in content.lines().enumerate() {
+        progress.parsed(index + 1);
+        progress.check()?;
+
+        match parse(line) {
+            Ok(entries) => {
+                for (ip, hostname) in entries {
+                    imported.hosts.entry(ip).or_default().insert(hostname);
+                }
+            }
+            Err(reason) => imported.unrepresentable.push(Unrepresentable {
+                line: index + 1,
+                content: line.trim().to_owned(),
+                reason,
+            }),
+        }
+    }
+    Ok(imported)
+}
+
+/// Import a list in `format` as hosts entries, the domains it blocks without naming
+/// an address point to `target`
+pub fn import(
+    content: &str,
+    format: Format,
+    target: &str,
+    progress: &Progress,
+) -> Result<Imported, ErrorKind> {
+    if target.parse::<IpAddr>().is_err() {
+        return Err(ErrorKind::String(format!(
+            "Invalid target address '{}'",
+            target
+        )));
+    }
+
+    match format {
+        Format::Hosts => import_lines(content, progress, parse_hosts),
+        Format::Domains => import_lines(content, progress, |line| parse_domain(line, target)),
+        Format::Adblock => import_lines(content, progress, |line| parse_adblock(line, target)),
+        Format::Dnsmasq => import_lines(content, progress, |line| parse_dnsmasq(line, target)),
+        Format::Unbound => {
+            // A zone may be declared before its records
+            let owners = content.lines().filter_map(unbound_record_owner).collect();
+            import_lines(content, progress, |line| {
+                parse_unbound(line, target, &owners)
+            })
+        }
+        Format::Rpz => {
+            let mut reader = RpzReader {
+                origin: None,
+                owner: None,
+                is_continued: false,
+            };
+            import_lines(content, progress, |line| reader.parse(line, target))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn import_str(content: &str, format: Format) -> Imported {
+        import(content, format, DEFAULT_TARGET, &Progress::none()).unwrap()
+    }
+
+    fn hostnames(imported: &Imported, ip: &str) -> Vec<String> {
+        let mut hostnames = imported
+            .hosts
+            .get(ip)
+            .map(|hostnames| hostnames.iter().cloned().collect::<Vec<_>>())
+            .unwrap_or_default();
+        hostnames.sort();
+        hostnames
+    }
+
+    fn unrepresentable_lines(imported: &Imported) -> Vec<usize> {
+        imported
+            .unrepresentable
+            .iter()
+            .map(|unrepresentable| unrepresentable.line)
+            .collect()
+    }
+
+    #[test]
+    fn detects_the_formats() {
+        let cases = [
+            ("||ads.example.com^\n", Format::Adblock),
+            ("! Title\n@@||good.example.com^\n", Format::Adblock),
+            (
+                "# Comment\naddress=/ads.example.com/0.0.0.0\n",
+                Format::Dnsmasq,
+            ),
+            ("host-record=nas.example.com,10.0.0.2\n", Format::Dnsmasq),
+            (
+                "server:\nlocal-zone: \"ads.example.com\" refuse\n",
+                Format::Unbound,
+            ),
+            (
+                "$TTL 300\n@ IN SOA localhost. hostmaster.localhost. 1 1 1 1 1\n",
+                Format::Rpz,
+            ),
+            ("; Comment\n0.0.0.0 ads.example.com\n", Format::Hosts),
+            ("::1 localhost\n", Format::Hosts),
+            ("ads.example.com\ntracker.example.com\n", Format::Domains),
+            ("", Format::Domains),
+        ];
+        for (content, format) in cases.iter() {
+            assert!(
+                Format::detect(content) == *format,
+                "{:?} not detected as {}",
+                content,
+                format.as_str()
+            );
+        }
+    }
+
+    #[test]
+    fn adblock_rules_only_block_whole_domains() {
+        let imported = import_str(
+            "! Title\n\
+            [Adblock Plus 2.0]\n\
+            ||ads.example.com^\n\
+            @@||good.example.com^\n\
+            ##.banner\n\
+            ||tracker.example.net^$third-party\n\
+            /banner/*\n\
+            ||cdn.example.org/ads^\n\
+            ||*.wild.example.com^\n",
+            Format::Adblock,
+        );
+        assert_eq!(hostnames(&imported, DEFAULT_TARGET), ["ads.example.com"]);
+        assert_eq!(unrepresentable_lines(&imported), [4, 5, 6, 7, 8, 9]);
+    }
+
+    #[test]
+    fn domains_point_to_the_target() {
+        let imported = import(
+            "# Comment\n\
+            ads.example.com\n\
+            Tracker.Example.NET. # A trailing comment\n\
+            \n\
+            ads.example.com tracker.example.com\n\
+            *.wild.example.com\n\
+            bad_name.example.com\n",
+            Format::Domains,
+            "127.0.0.1",
+            &Progress::none(),
+        )
+        .unwrap();
+        assert_eq!(imported.hosts.len(), 1);
+        assert_eq!(
+            hostnames(&imported, "127.0.0.1"),
+            ["ads.example.com", "tracker.example.net"]
+        );
+        assert_eq!(unrepresentable_lines(&imported), [5, 6, 7]);
+
+        match import(
+            "ads.example.com\n",
+            Format::Domains,
+            "nowhere",
+            &Progress::none(),
+        ) {
+            Err(ErrorKind::String(_)) => {}
+            result => panic!("unexpected result {:?}", result.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn dnsmasq_directives() {
+        let imported = import_str(
+            "address=/ads.example.com/0.0.0.0\n\
+            address=/one.example.com/two.example.com/\n\
+            local=/blocked.example/\n\
+            address=/dual.example.com/::1\n\
+            host-record=nas.example.com,nas,10.0.0.2,fd00::2,3600\n\
+            server=/corp.example/10.0.0.53\n\
+            address=/#/0.0.0.0\n\
+            address=/bad.example.com/not-an-address\n\
+            cache-size=1000\n\
+            no-resolv\n",
+            Format::Dnsmasq,
+        );
+        assert_eq!(
+            hostnames(&imported, DEFAULT_TARGET),
+            [
+                "ads.example.com",
+                "blocked.example",
+                "one.example.com",
+                "two.example.com"
+            ]
+        );
+        assert_eq!(hostnames(&imported, "::1"), ["dual.example.com"]);
+        assert_eq!(hostnames(&imported, "10.0.0.2"), ["nas", "nas.example.com"]);
+        assert_eq!(hostnames(&imported, "fd00::2"), ["nas", "nas.example.com"]);
+        assert_eq!(unrepresentable_lines(&imported), [6, 7, 8, 9, 10]);
+    }
+
+    #[test]
+    fn rpz_triggers_on_the_queried_name() {
+        let imported = import_str(
+            "$TTL 300\n\
+            @ IN SOA localhost. hostmaster.localhost. (\n\
+            \x20   1 3600 600 86400 300 )\n\
+            \x20 IN NS localhost.\n\
+            ads.example.com CNAME .\n\
+            *.ads.example.com CNAME .\n\
+            drop.example.com CNAME rpz-drop. ; A comment\n\
+            printer.example.com 300 IN A 10.0.0.1\n\
+            good.example.com CNAME rpz-passthru.\n\
+            other.example.com CNAME www.example.net.\n\
+            32.1.0.0.10.rpz-ip CNAME .\n\
+            text.example.com TXT \"hello\"\n",
+            Format::Rpz,
+        );
+        assert_eq!(
+            hostnames(&imported, DEFAULT_TARGET),
+            ["ads.example.com", "drop.example.com"]
+        );
+        assert_eq!(hostnames(&imported, "10.0.0.1"), ["printer.example.com"]);
+        assert_eq!(unrepresentable_lines(&imported), [6, 9, 10, 11, 12]);
+    }
+
+    #[test]
+    fn rpz_owners_are_relative_to_the_origin() {
+        let imported = import_str(
+            "$ORIGIN rpz.example.\n\
+            printer.example.com.rpz.example. A 10.0.0.1\n\
+            nas.example.com A 10.0.0.2\n\
+            outside.example.org. A 10.0.0.3\n",
+            Format::Rpz,
+        );
+        assert_eq!(hostnames(&imported, "10.0.0.1"), ["printer.example.com"]);
+        assert_eq!(hostnames(&imported, "10.0.0.2"), ["nas.example.com"]);
+        assert_eq!(unrepresentable_lines(&imported), [4]);
+    }
+
+    #[test]
+    fn static_zones_only_block_names_without_records() {
+        let imported = import_str(
+            "server:\n\
+            local-zone: \"blocked.example.com.\" static\n\
+            local-zone: \"printer.example.com.\" static\n\
+            local-data: \"printer.example.com. A 10.0.0.1\"\n\
+            local-data: \"nas.example.com. A 10.0.0.2\"\n\
+            local-zone: \"nas.example.com\" static\n",
+            Format::Unbound,
+        );
+        assert!(imported.unrepresentable.is_empty());
+        assert_eq!(
+            hostnames(&imported, DEFAULT_TARGET),
+            ["blocked.example.com"]
+        );
+        assert_eq!(hostnames(&imported, "10.0.0.1"), ["printer.example.com"]);
+        assert_eq!(hostnames(&imported, "10.0.0.2"), ["nas.example.com"]);
+    }
+
+    #[test]
+    fn invalid_hosts_lines_are_listed() {
+        let imported = import_str(
+            "# A comment\n\
+            0.0.0.0 ads.example.com tracker.example.com\n\
+            not-an-address ads.example.net\n\
+            10.0.0.1 printer.example.com # A trailing comment\n\
+            0.0.0.0 bad_name.example.com\n",
+            Format::Hosts,
+        );
+        assert_eq!(
+            hostnames(&imported, DEFAULT_TARGET),
+            ["ads.example.com", "tracker.example.com"]
+        );
+        assert_eq!(hostnames(&imported, "10.0.0.1"), ["printer.example.com"]);
+        assert_eq!(unrepresentable_lines(&imported), [3, 5]);
+    }
+}
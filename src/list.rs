@@ -0,0 +1,338 @@
+use crate::audit::{self, Operation, Snapshot};
+use crate::config::Config;
+use crate::error::ErrorKind;
+use crate::helpers::AppendableMap;
+use crate::parser::{parse_from_str, parse_from_str_with_progress, Hosts};
+use crate::progress::Progress;
+use crate::signature::{self, SIGNATURE_EXTENSION};
+use crate::subscription;
+use crate::target;
+use crate::transport::Transport;
+use crate::updater::is_installed;
+use crate::{state, HOSTS_BEBASIN};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+
+const LIST_FILE: &str = "hosts-list";
+const LIST_INFO_FILE: &str = "hosts-list.json";
+/// The source of the custom hosts list installed instead of the Bebasin one
+const CUSTOM_SOURCE_FILE: &str = "custom-source";
+const EMBEDDED_SOURCE: &str = "bebasin";
+const UNNAMED_SECTION: &str = "Other";
+
+/// Metadata of the cached hosts list
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ListInfo {
+    pub version: Option<String>,
+    pub checksum: String,
+    pub source: String,
+    pub fetched_at: String,
+}
+
+/// A hosts list that has been fetched and validated, but not applied yet
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Fetched {
+    pub content: String,
+    pub info: ListInfo,
+    /// The detached signature, the privileged helper verifies it again
+    #[serde(default)]
+    pub signature: Option<String>,
+}
+
+/// Difference between two hosts lists
+pub struct Changes {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl Changes {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+
+    pub fn describe(&self) -> String {
+        let mut description = format!("{} added, {} removed", self.added.len(), self.removed.len());
+        for entry in &self.added {
+            description.push_str(&format!("\n+ {}", entry));
+        }
+        for entry in &self.removed {
+            description.push_str(&format!("\n- {}", entry));
+        }
+        description
+    }
+}
+
+/// Find the `# Version: ...` comment of a hosts list
+fn parse_version(content: &str) -> Option<String> {
+    content.lines().find_map(|line| {
+        let line = line.trim_start_matches('#').trim();
+        if line.to_lowercase().starts_with("version:") {
+            Some(line["version:".len()..].trim().to_owned())
+        } else {
+            None
+        }
+    })
+}
+
+fn checksum(content: &str) -> String {
+    signature::sha256(content.as_bytes())
+}
+
+fn entries(hosts: &Hosts) -> BTreeSet<String> {
+    let mut entries = BTreeSet::new();
+    for (ip, hostnames) in hosts {
+        for hostname in hostnames {
+            entries.insert(format!("{} {}", ip, hostname));
+        }
+    }
+    entries
+}
+
+/// Read a hosts list from an URL, or from a local path
+pub fn read(
+    transport: &dyn Transport,
+    location: &str,
+    progress: &Progress,
+) -> Result<String, ErrorKind> {
+    if location.starts_with("http://") || location.starts_with("https://") {
+        match crate::transport::download_cached(transport, location, progress) {
+            Ok(bytes) => Ok(String::from_utf8_lossy(&bytes).into_owned()),
+            Err(err) => Err(err),
+        }
+    } else {
+        match fs::read_to_string(location.trim_start_matches("file://")) {
+            Ok(content) => Ok(content),
+            Err(err) => Err(ErrorKind::IOError(err)),
+        }
+    }
+}
+
+/// Fetch the hosts list from an URL or a local file, and make sure it is signed and can be parsed
+pub fn fetch(config: &Config, progress: &Progress) -> Result<Fetched, ErrorKind> {
+    let transport = crate::transport::from_config(config);
+    let location = config.hosts_list_url.as_str();
+    progress.step(&format!("Downloading the hosts list from {}", location));
+    let content = read(transport.as_ref(), location, progress)?;
+
+    // A missing signature is only acceptable when unsigned lists are explicitly allowed
+    progress.step("Verifying the hosts list");
+    let signature = read(
+        transport.as_ref(),
+        &format!("{}{}", location, SIGNATURE_EXTENSION),
+        progress,
+    )
+    .ok();
+    progress.check()?;
+    signature::verify_or_refuse(
+        content.as_bytes(),
+        signature.as_deref(),
+        config.allow_unsigned,
+        &format!("The hosts list from {}", location),
+    )?;
+
+    progress.step("Parsing the hosts list");
+    let hosts = parse_from_str_with_progress(&content, progress)?;
+    if hosts.is_empty() {
+        return Err(ErrorKind::String(format!(
+            "The hosts list from {} contains no entry",
+            location
+        )));
+    }
+
+    Ok(Fetched {
+        info: ListInfo {
+            version: parse_version(&content),
+            checksum: checksum(&content),
+            source: location.to_owned(),
+            fetched_at: chrono::Local::now().to_rfc3339(),
+        },
+        content,
+        signature,
+    })
+}
+
+/// The entries of the hosts list under a `# [name]` comment
+pub struct Section {
+    pub name: String,
+    pub hosts: Hosts,
+}
+
+/// Split a hosts list into its sections, the entries before the first one have a section of
+/// their own. Commented out entries, like the optional ones, aren't part of any section.
+pub fn sections(content: &str) -> Result<Vec<Section>, ErrorKind> {
+    let mut sections = vec![Section {
+        name: String::from(UNNAMED_SECTION),
+        hosts: HashMap::new(),
+    }];
+    for line in content.lines() {
+        let line = line.trim();
+        let name = line
+            .strip_prefix('#')
+            .map(str::trim)
+            .and_then(|comment| comment.strip_prefix('['))
+            .and_then(|comment| comment.strip_suffix(']'));
+        if let Some(name) = name {
+            sections.push(Section {
+                name: name.to_owned(),
+                hosts: HashMap::new(),
+            });
+        } else if !line.is_empty() && !line.starts_with('#') {
+            let hosts = parse_from_str(line)?;
+            if let Some(section) = sections.last_mut() {
+                section.hosts.append(hosts).ok();
+            }
+        }
+    }
+    sections.retain(|section| !section.hosts.is_empty());
+    Ok(sections)
+}
+
+/// Information of the cached hosts list, `None` when the embedded one is used
+pub fn installed_info() -> Option<ListInfo> {
+    let content = fs::read_to_string(state::path(LIST_INFO_FILE)).ok()?;
+    serde_json::from_str::<ListInfo>(&content).ok()
+}
+
+/// Whether the cached hosts list is still the one that has been fetched
+pub fn is_cache_intact() -> bool {
+    match installed_info() {
+        Some(info) => match fs::read_to_string(state::path(LIST_FILE)) {
+            Ok(content) => info.checksum == checksum(&content),
+            Err(_) => false,
+        },
+        None => true,
+    }
+}
+
+/// Where the custom hosts list installed instead of the Bebasin one came from
+pub fn custom_source() -> Option<String> {
+    if !is_installed() {
+        return None;
+    }
+    fs::read_to_string(state::path(CUSTOM_SOURCE_FILE))
+        .ok()
+        .filter(|source| !source.is_empty())
+}
+
+/// Remember that a custom hosts list has been installed, `None` once the Bebasin one
+/// or nothing is installed again
+pub fn set_custom_source(source: Option<&str>) -> Result<(), ErrorKind> {
+    let path = state::path(CUSTOM_SOURCE_FILE);
+    match source {
+        Some(source) => {
+            state::ensure()?;
+            state::write(&path, source.as_bytes())
+        }
+        None => match fs::remove_file(&path) {
+            Ok(_) => Ok(()),
+            Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(ErrorKind::IOError(err)),
+        },
+    }
+}
+
+/// The hosts list in use, the cached one when it is still valid, the embedded one otherwise
+pub fn current() -> String {
+    if let (Some(info), Ok(content)) =
+        (installed_info(), fs::read_to_string(state::path(LIST_FILE)))
+    {
+        if info.checksum == checksum(&content) && parse_from_str(&content).is_ok() {
+            return content;
+        }
+    }
+    String::from(HOSTS_BEBASIN)
+}
+
+/// Checksum of the hosts list in use
+pub fn current_checksum() -> String {
+    match installed_info() {
+        Some(info) => info.checksum,
+        None => checksum(HOSTS_BEBASIN),
+    }
+}
+
+/// Where the hosts list in use came from
+pub fn current_source() -> String {
+    match installed_info() {
+        Some(info) => info.source,
+        None => String::from(EMBEDDED_SOURCE),
+    }
+}
+
+/// What would change when replacing the list in use with the fetched one
+pub fn changes(fetched: &Fetched) -> Result<Changes, ErrorKind> {
+    let old_entries = entries(&parse_from_str(&current())?);
+    let new_entries = entries(&parse_from_str(&fetched.content)?);
+
+    Ok(Changes {
+        added: new_entries.difference(&old_entries).cloned().collect(),
+        removed: old_entries.difference(&new_entries).cloned().collect(),
+    })
+}
+
+/// The entries of a hosts list along with the ones of the subscriptions
+pub fn with_subscriptions(content: &str) -> Result<Hosts, ErrorKind> {
+    let mut hosts = parse_from_str(content)?;
+    hosts.append(subscription::cached_hosts()?).ok();
+    Ok(hosts)
+}
+
+/// Write `hosts` as the block managed by Bebasin to the configured target,
+/// and record the change in the audit log
+pub fn write_managed_block(
+    hosts: Hosts,
+    kind: Operation,
+    source: &str,
+) -> Result<String, ErrorKind> {
+    let target = target::configured();
+    let before = Snapshot::take();
+    target.install(hosts)?;
+    Ok(audit::record_with_warning(
+        &format!("{} has been updated", target.path().display()),
+        kind,
+        source,
+        before,
+    ))
+}
+
+/// Cache the fetched list, and reapply it to the hosts file when Bebasin is installed
+pub fn apply(fetched: &Fetched) -> Result<String, ErrorKind> {
+    // The custom entries would be replaced without anyone asking for it
+    if let Some(source) = custom_source() {
+        return Err(ErrorKind::String(format!(
+            "The custom hosts list from {} is installed, install Bebasin again \
+            before updating its hosts list",
+            source
+        )));
+    }
+    state::ensure()?;
+    state::write(&state::path(LIST_FILE), fetched.content.as_bytes())?;
+    match serde_json::to_string(&fetched.info) {
+        Ok(info) => state::write(&state::path(LIST_INFO_FILE), info.as_bytes())?,
+        Err(err) => return Err(ErrorKind::SerdeJSONError(err)),
+    };
+
+    if !is_installed() {
+        return Ok(String::from("The hosts list has been updated"));
+    }
+    write_managed_block(
+        with_subscriptions(&fetched.content)?,
+        Operation::ListUpdate,
+        &fetched.info.source,
+    )
+}
+
+/// Rewrite the hosts file after the subscriptions changed, when Bebasin is installed
+pub fn reapply(source: &str) -> Result<(), ErrorKind> {
+    // The subscriptions don't come along with a custom hosts list
+    if !is_installed() || custom_source().is_some() {
+        return Ok(());
+    }
+    write_managed_block(
+        with_subscriptions(&current())?,
+        Operation::ListUpdate,
+        source,
+    )?;
+    Ok(())
+}
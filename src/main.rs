@@ -12,24 +12,68 @@ extern crate itertools;
 extern crate zip;
 
 mod app;
+mod audit;
+mod check;
+mod cli;
+mod config;
+mod dns;
+mod doctor;
+mod doh;
 mod error;
+mod export;
+mod flush;
+mod helper;
 mod helpers;
+mod import;
+mod list;
 mod os;
 mod parser;
+mod policy;
+mod progress;
+mod rollback;
+mod schedule;
+mod signature;
+mod state;
+mod subscription;
+mod target;
+#[cfg(test)]
+mod testing;
+mod transport;
 mod ui;
 mod updater;
+mod verify;
+mod version;
 
-#[allow(clippy::unreadable_literal)]
-const CURRENT_VERSION: u64 = 202011120913;
+// The semantic version of Cargo.toml, the releases are tagged with it.
+// The releases up to `version::LAST_TIMESTAMP_RELEASE` were versioned by their build time.
+const CURRENT_VERSION: &str = env!("CARGO_PKG_VERSION");
+// Target triple this binary is built for, set by build.rs
+const TARGET: &str = env!("TARGET");
 const REPOSITORY_URL: &str = "https://github.com/bebasid/bebasin";
 const LATEST_VERSION_URL: &str =
     "https://raw.githubusercontent.com/bebasid/bebasin/master/latest.json";
 const UPDATE_URL: &str = "https://api.github.com/repos/bebasid/bebasin/releases/latest";
+const RELEASES_URL: &str = "https://api.github.com/repos/bebasid/bebasin/releases?per_page=100";
+const RELEASE_TAG_URL: &str = "https://api.github.com/repos/bebasid/bebasin/releases/tags/";
+const HOSTS_LIST_URL: &str = "https://raw.githubusercontent.com/bebasid/bebasin/master/misc/hosts";
+// Public key of the minisign key pair used to sign the releases and the hosts list.
+// The pair has been generated with `minisign -G -p bebasin.pub -s bebasin.key`, the secret
+// key is only kept as the MINISIGN_SECRET_KEY secret of the GitHub repository and its
+// password as MINISIGN_PASSWORD. The release job of .github/workflows/ci.yml signs the
+// release archives, .github/workflows/sign-hosts.yml signs misc/hosts whenever it changes.
+// Rotating it means generating a new pair, replacing both secrets and this key, and
+// re-signing misc/hosts. The releases signed with the previous key stop verifying.
+const SIGNING_PUBLIC_KEY: &str = "RWSahQh+otdX1n7ZzIwGpNCgsZCgPBvXUBWAJNDfKzh8eWrYyFmohwHC";
 const HOSTS_HEADER: &str = include_str!("../misc/header-hosts");
 const HOSTS_BEBASIN: &str = include_str!("../misc/hosts");
 
 fn main() {
     updater::remove_temp_file();
 
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        std::process::exit(cli::run(&args));
+    }
+
     app::App::new().dispatch();
 }
@@ -1,31 +1,288 @@
+use crate::error::ErrorKind;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the previous executable left behind by an update, until the next start
+pub const OLD_EXECUTABLE_NAME: &str = ".bebasin_tmp";
+/// Permissions of the hosts file when it has to be created, every user has to read it
+pub const HOSTS_FILE_MODE: u32 = 0o644;
+
+/// Write `content` to `path`, refusing to follow a symbolic link planted there.
+/// `mode` is the permissions of the file when it has to be created.
+pub fn write_file(path: &Path, content: &[u8], mode: u32) -> Result<(), ErrorKind> {
+    use std::io::Write as _;
+
+    let mut file = match open_options(mode).create(true).truncate(true).open(path) {
+        Ok(file) => file,
+        Err(err) => return Err(open_error(path, err)),
+    };
+    match file.write_all(content) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(ErrorKind::IOError(err)),
+    }
+}
+
+/// Run a program to completion, failing with it is an error
+pub fn run(program: &str, args: &[&str]) -> Result<(), ErrorKind> {
+    match std::process::Command::new(program).args(args).status() {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(ErrorKind::String(format!(
+            "{} {} failed with {}",
+            program,
+            args.join(" "),
+            status
+        ))),
+        Err(err) => Err(ErrorKind::IOError(err)),
+    }
+}
+
+/// Run systemctl, a failing unit is an error
+pub fn systemctl(args: &[&str]) -> Result<(), ErrorKind> {
+    run("systemctl", args)
+}
+
+/// Create a file that doesn't exist yet, whatever is in the way makes it fail
+pub fn create_new_file(path: &Path, mode: u32) -> Result<fs::File, ErrorKind> {
+    match open_options(mode).create_new(true).open(path) {
+        Ok(file) => Ok(file),
+        Err(err) => Err(open_error(path, err)),
+    }
+}
+
 // Windows
 #[cfg(target_os = "windows")]
 pub const HOSTS_PATH: &str = "C:\\Windows\\System32\\drivers\\etc\\hosts";
 #[cfg(target_os = "windows")]
 pub const HOSTS_BACKUP_PATH: &str = "C:\\Windows\\System32\\drivers\\etc\\hosts-backup";
+#[cfg(target_os = "windows")]
+pub const STATE_PATH: &str = "C:\\ProgramData\\bebasin";
+
+/// The cache directory of the user running Bebasin
+#[cfg(target_os = "windows")]
+pub fn user_cache_path() -> Option<PathBuf> {
+    let local_app_data = std::env::var_os("LOCALAPPDATA")?;
+    Some(PathBuf::from(local_app_data).join("bebasin"))
+}
 
 #[cfg(target_os = "windows")]
 pub mod windows;
 
+#[cfg(target_os = "windows")]
+pub const EXECUTABLE_NAME: &str = "bebasin.exe";
+
 #[cfg(target_os = "windows")]
 pub fn is_has_admin_access() -> bool {
     windows::is_app_elevated().unwrap_or(false)
 }
 
+#[cfg(target_os = "windows")]
+pub fn admin_command<S: AsRef<std::ffi::OsStr>>(
+    _args: &[S],
+) -> Result<std::process::Command, ErrorKind> {
+    Err(ErrorKind::String(String::from(
+        "Right-click Bebasin and choose \"Run as administrator\"",
+    )))
+}
+
+#[cfg(target_os = "windows")]
+pub fn run_as_admin() -> Result<i32, ErrorKind> {
+    Err(ErrorKind::String(String::from(
+        "Right-click Bebasin and choose \"Run as administrator\"",
+    )))
+}
+
+/// Options for writing, the permissions come from the ACL of the directory
+#[cfg(target_os = "windows")]
+pub fn open_options(_mode: u32) -> fs::OpenOptions {
+    let mut options = fs::OpenOptions::new();
+    options.write(true);
+    options
+}
+
+#[cfg(target_os = "windows")]
+pub fn set_mode(_file: &fs::File, _mode: u32) -> Result<(), ErrorKind> {
+    Ok(())
+}
+
+#[cfg(target_os = "windows")]
+fn open_error(_path: &Path, err: std::io::Error) -> ErrorKind {
+    ErrorKind::IOError(err)
+}
+
+/// Swap the running executable with the new one
+#[cfg(target_os = "windows")]
+pub fn replace_executable(new_path: &Path, current_path: &Path) -> Result<(), ErrorKind> {
+    // A running executable can't be overwritten, but it can be moved out of the way
+    let old_path = current_path.with_file_name(OLD_EXECUTABLE_NAME);
+    if old_path.exists() {
+        fs::remove_file(&old_path).ok();
+    }
+
+    if let Err(err) = fs::rename(current_path, &old_path) {
+        return Err(ErrorKind::IOError(err));
+    }
+    if let Err(err) = fs::rename(new_path, current_path) {
+        // Put the current executable back
+        fs::rename(&old_path, current_path).ok();
+        return Err(ErrorKind::IOError(err));
+    }
+    Ok(())
+}
+
 // Linux
 #[cfg(target_os = "linux")]
 pub const HOSTS_PATH: &str = "/etc/hosts";
 #[cfg(target_os = "linux")]
 pub const HOSTS_BACKUP_PATH: &str = "/etc/hosts-backup";
+#[cfg(target_os = "linux")]
+pub const STATE_PATH: &str = "/var/lib/bebasin";
+
+/// The cache directory of the user running Bebasin
+#[cfg(target_os = "linux")]
+pub fn user_cache_path() -> Option<PathBuf> {
+    let cache_path = match std::env::var_os("XDG_CACHE_HOME") {
+        Some(cache_home) if !cache_home.is_empty() => PathBuf::from(cache_home),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".cache"),
+    };
+    Some(cache_path.join("bebasin"))
+}
 
 // macos
 #[cfg(target_os = "macos")]
 pub const HOSTS_PATH: &str = "/private/etc/hosts";
 #[cfg(target_os = "macos")]
 pub const HOSTS_BACKUP_PATH: &str = "/private/etc/hosts-backup";
+#[cfg(target_os = "macos")]
+pub const STATE_PATH: &str = "/Library/Application Support/bebasin";
+
+/// The cache directory of the user running Bebasin
+#[cfg(target_os = "macos")]
+pub fn user_cache_path() -> Option<PathBuf> {
+    let home = std::env::var_os("HOME")?;
+    Some(PathBuf::from(home).join("Library/Caches/bebasin"))
+}
 
 // *nix
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub const EXECUTABLE_NAME: &str = "bebasin";
+
 #[cfg(any(target_os = "linux", target_os = "macos"))]
 pub fn is_has_admin_access() -> bool {
-    !nix::unistd::geteuid().is_root()
+    nix::unistd::geteuid().is_root()
+}
+
+/// Programs able to run a command as root, the first one found is used
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+const ELEVATION_PROGRAMS: [&str; 2] = ["sudo", "pkexec"];
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn find_program(name: &str) -> Option<std::path::PathBuf> {
+    let paths = std::env::var_os("PATH")?;
+    std::env::split_paths(&paths)
+        .map(|path| path.join(name))
+        .find(|path| path.is_file())
+}
+
+/// A command running Bebasin as root with `args`
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub fn admin_command<S: AsRef<std::ffi::OsStr>>(
+    args: &[S],
+) -> Result<std::process::Command, ErrorKind> {
+    let exe_path = match std::env::current_exe() {
+        Ok(exe_path) => exe_path,
+        Err(err) => return Err(ErrorKind::IOError(err)),
+    };
+    let program = match ELEVATION_PROGRAMS
+        .iter()
+        .find_map(|name| find_program(name))
+    {
+        Some(program) => program,
+        None => {
+            return Err(ErrorKind::String(format!(
+                "Neither {} are available, run Bebasin as root",
+                ELEVATION_PROGRAMS.join(" nor ")
+            )))
+        }
+    };
+
+    let mut command = std::process::Command::new(program);
+    command.arg(exe_path).args(args);
+    Ok(command)
+}
+
+/// Run Bebasin again as root with the same arguments, returns its exit code
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub fn run_as_admin() -> Result<i32, ErrorKind> {
+    let args = std::env::args_os().skip(1).collect::<Vec<_>>();
+    match admin_command(&args)?.status() {
+        Ok(status) => Ok(status.code().unwrap_or(1)),
+        Err(err) => Err(ErrorKind::IOError(err)),
+    }
+}
+
+/// Options for writing that never follow a symbolic link,
+/// `mode` is the permissions of a created file
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub fn open_options(mode: u32) -> fs::OpenOptions {
+    use std::os::unix::fs::OpenOptionsExt as _;
+
+    let mut options = fs::OpenOptions::new();
+    options
+        .write(true)
+        .mode(mode)
+        .custom_flags(nix::libc::O_NOFOLLOW);
+    options
+}
+
+/// Change the permissions of an opened file, whatever they were when it was created
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub fn set_mode(file: &fs::File, mode: u32) -> Result<(), ErrorKind> {
+    use std::os::unix::fs::PermissionsExt as _;
+
+    match file.set_permissions(fs::Permissions::from_mode(mode)) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(ErrorKind::IOError(err)),
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn open_error(path: &Path, err: std::io::Error) -> ErrorKind {
+    // O_NOFOLLOW fails with ELOOP on a symbolic link
+    if err.raw_os_error() == Some(nix::libc::ELOOP) {
+        return ErrorKind::String(format!(
+            "Refusing to write {}, it is a symbolic link",
+            path.display()
+        ));
+    }
+    ErrorKind::IOError(err)
+}
+
+/// Swap the running executable with the new one
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub fn replace_executable(new_path: &Path, current_path: &Path) -> Result<(), ErrorKind> {
+    // Renaming over a running executable is atomic and the running process keeps the old inode
+    match fs::rename(new_path, current_path) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(ErrorKind::IOError(err)),
+    }
+}
+
+#[cfg(all(test, any(target_os = "linux", target_os = "macos")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn admin_access_follows_the_effective_user() {
+        let output = std::process::Command::new("id").arg("-u").output().unwrap();
+        let is_root = String::from_utf8_lossy(&output.stdout).trim() == "0";
+        assert_eq!(is_has_admin_access(), is_root);
+    }
+
+    #[test]
+    fn user_cache_is_a_directory_of_its_own() {
+        // Without a home directory, the cache falls back to the state directory
+        if let Some(path) = user_cache_path() {
+            assert!(path.ends_with("bebasin"));
+        }
+    }
 }
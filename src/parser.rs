@@ -1,27 +1,20 @@
 use crate::error::ErrorKind;
+use crate::os::{write_file, HOSTS_FILE_MODE};
+use crate::progress::Progress;
 use itertools::Itertools as _;
 use pest::Parser;
 use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::io::prelude::*;
+use std::path::Path;
 
-type Hosts = HashMap<String, HashSet<String>>;
+pub type Hosts = HashMap<String, HashSet<String>>;
 
 #[derive(Parser)]
 #[grammar = "grammar.pest"]
 struct HostsParser;
 
-pub fn write_to_file(file_path: &str, hosts: &Hosts, header: &str) -> Result<(), ErrorKind> {
-    let mut file;
-    match fs::File::create(file_path) {
-        Ok(f) => {
-            file = f;
-        }
-        Err(err) => {
-            return Err(ErrorKind::IOError(err));
-        }
-    };
-
+/// The content of a hosts file, starting with `header`
+pub fn to_string(hosts: &Hosts, header: &str) -> String {
     let mut hosts_stringify = String::new();
     hosts_stringify.push_str(header);
     for host in hosts {
@@ -30,11 +23,16 @@ pub fn write_to_file(file_path: &str, hosts: &Hosts, header: &str) -> Result<(),
 
         hosts_stringify.push_str(&format!("{} {}\n", ip, hostnames));
     }
+    hosts_stringify
+}
 
-    match file.write_all(hosts_stringify.as_bytes().as_ref()) {
-        Ok(()) => Ok(()),
-        Err(err) => Err(ErrorKind::IOError(err)),
-    }
+/// Write the hosts file or its backup, a symbolic link at `file_path` is refused
+pub fn write_to_file(file_path: &str, hosts: &Hosts, header: &str) -> Result<(), ErrorKind> {
+    write_file(
+        Path::new(file_path),
+        to_string(hosts, header).as_bytes(),
+        HOSTS_FILE_MODE,
+    )
 }
 
 pub fn parse_from_file(file_path: &str) -> Result<Hosts, ErrorKind> {
@@ -45,7 +43,13 @@ pub fn parse_from_file(file_path: &str) -> Result<Hosts, ErrorKind> {
 }
 
 pub fn parse_from_str(str: &str) -> Result<Hosts, ErrorKind> {
+    parse_from_str_with_progress(str, &Progress::none())
+}
+
+/// Same as `parse_from_str`, reporting the number of parsed entries
+pub fn parse_from_str_with_progress(str: &str, progress: &Progress) -> Result<Hosts, ErrorKind> {
     let mut hosts: Hosts = HashMap::new();
+    let mut parsed = 0;
     let res = match HostsParser::parse(Rule::main, str) {
         Ok(x) => x,
         Err(err) => return Err(ErrorKind::PestRuleError(err)),
@@ -53,6 +57,10 @@ pub fn parse_from_str(str: &str) -> Result<Hosts, ErrorKind> {
 
     for pair in res {
         if let Rule::statement = pair.as_rule() {
+            parsed += 1;
+            progress.parsed(parsed);
+            progress.check()?;
+
             let mut ip = String::new();
             let mut hostnames: HashSet<String> = HashSet::new();
 
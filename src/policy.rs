@@ -0,0 +1,343 @@
+use crate::config::Config;
+use crate::parser::Hosts;
+use crate::{HOSTS_LIST_URL, LATEST_VERSION_URL, REPOSITORY_URL, UPDATE_URL};
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr};
+
+/// Names every system resolves to itself
+const LOOPBACK_NAMES: [&str; 4] = [
+    "localhost",
+    "localhost.localdomain",
+    "ip6-localhost",
+    "ip6-loopback",
+];
+
+/// Where the operating systems and the package managers get their updates from
+const UPDATE_DOMAINS: [&str; 17] = [
+    "windowsupdate.com",
+    "update.microsoft.com",
+    "windowsupdate.microsoft.com",
+    "delivery.mp.microsoft.com",
+    "swscan.apple.com",
+    "swcdn.apple.com",
+    "mesu.apple.com",
+    "updates.cdn-apple.com",
+    "archive.ubuntu.com",
+    "security.ubuntu.com",
+    "deb.debian.org",
+    "security.debian.org",
+    "dl.fedoraproject.org",
+    "mirrors.fedoraproject.org",
+    "archlinux.org",
+    "download.opensuse.org",
+    "dl-cdn.alpinelinux.org",
+];
+
+/// Where the Bebasin releases are downloaded from, besides the configured URLs
+const RELEASE_DOMAINS: [&str; 2] = [
+    "objects.githubusercontent.com",
+    "github-releases.githubusercontent.com",
+];
+
+/// Names that only exist on a local network, they legitimately point to private addresses
+const LOCAL_SUFFIXES: [&str; 7] = [
+    "local",
+    "lan",
+    "home",
+    "internal",
+    "home.arpa",
+    "localdomain",
+    "corp",
+];
+
+/// Why an entry of a custom hosts list needs a confirmation
+pub enum Reason {
+    LoopbackName,
+    UpdateDomain,
+    BebasinEndpoint,
+    SensitiveDomain,
+    SuspiciousAddress(&'static str),
+}
+
+pub struct Violation {
+    pub ip: String,
+    pub hostname: String,
+    pub reason: Reason,
+}
+
+impl Violation {
+    pub fn describe(&self) -> String {
+        let reason = match self.reason {
+            Reason::LoopbackName => String::from("a loopback name pointing elsewhere"),
+            Reason::UpdateDomain => String::from("an update server"),
+            Reason::BebasinEndpoint => String::from("a server Bebasin updates from"),
+            Reason::SensitiveDomain => String::from("a protected domain of the configuration"),
+            Reason::SuspiciousAddress(range) => format!("a domain pointing to {}", range),
+        };
+        format!("{} {}: {}", self.ip, self.hostname, reason)
+    }
+}
+
+/// Whether `hostname` is `domain` or one of its subdomains, both in lowercase
+fn is_within(hostname: &str, domain: &str) -> bool {
+    match hostname.strip_suffix(domain) {
+        Some(prefix) => prefix.is_empty() || prefix.ends_with('.'),
+        None => false,
+    }
+}
+
+/// The host part of an URL, without the credentials nor the port
+fn url_host(url: &str) -> Option<&str> {
+    let (_, rest) = url.split_once("://")?;
+    let authority = rest.split('/').next()?;
+    let host = authority.rsplit('@').next()?;
+    let host = match host.strip_prefix('[') {
+        Some(host) => host.split(']').next()?,
+        None => host.split(':').next()?,
+    };
+    if host.is_empty() {
+        None
+    } else {
+        Some(host)
+    }
+}
+
+fn bebasin_endpoints(config: &Config) -> HashSet<String> {
+    let mut urls = vec![
+        REPOSITORY_URL,
+        LATEST_VERSION_URL,
+        UPDATE_URL,
+        HOSTS_LIST_URL,
+        &config.hosts_list_url,
+    ];
+    urls.extend(config.mirrors.iter().map(String::as_str));
+
+    let mut endpoints = urls
+        .into_iter()
+        .filter_map(url_host)
+        .map(str::to_lowercase)
+        .collect::<HashSet<_>>();
+    endpoints.extend(RELEASE_DOMAINS.iter().map(|domain| domain.to_string()));
+    endpoints
+}
+
+/// The name of the range `ip` belongs to, when nothing public should be redirected there.
+/// The unspecified and loopback addresses are how hosts lists block a domain, they are fine.
+fn suspicious_range(ip: &IpAddr) -> Option<&'static str> {
+    match ip {
+        IpAddr::V4(ip) => {
+            let octets = ip.octets();
+            if ip.is_private() {
+                Some("a private address")
+            } else if ip.is_link_local() {
+                Some("a link-local address")
+            } else if ip.is_multicast() {
+                Some("a multicast address")
+            } else if *ip == Ipv4Addr::BROADCAST {
+                Some("the broadcast address")
+            } else if ip.is_documentation() {
+                Some("a documentation address")
+            } else if octets[0] == 100 && (octets[1] & 0xc0) == 64 {
+                Some("a carrier-grade NAT address")
+            } else if octets[0] >= 240 {
+                Some("a reserved address")
+            } else {
+                None
+            }
+        }
+        IpAddr::V6(ip) => {
+            let first = ip.segments()[0];
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                suspicious_range(&IpAddr::V4(ip))
+            } else if ip.is_multicast() {
+                Some("a multicast address")
+            } else if (first & 0xfe00) == 0xfc00 {
+                Some("a private address")
+            } else if (first & 0xffc0) == 0xfe80 {
+                Some("a link-local address")
+            } else if first == 0x2001 && ip.segments()[1] == 0xdb8 {
+                Some("a documentation address")
+            } else {
+                None
+            }
+        }
+    }
+}
+
+// `Option::is_some_and` needs a more recent compiler than `map_or`
+#[allow(clippy::unnecessary_map_or)]
+fn is_loopback(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_loopback(),
+        IpAddr::V6(ip) => {
+            ip.is_loopback() || ip.to_ipv4_mapped().map_or(false, |ip| ip.is_loopback())
+        }
+    }
+}
+
+/// The protected domains, in lowercase
+struct Protected {
+    endpoints: HashSet<String>,
+    sensitive: Vec<String>,
+}
+
+fn check_entry(ip: &str, hostname: &str, protected: &Protected) -> Option<Reason> {
+    let hostname = hostname.to_lowercase();
+    let hostname = hostname.as_str();
+    let address = ip.parse::<IpAddr>().ok();
+    let is_loopback_name = LOOPBACK_NAMES.contains(&hostname) || is_within(hostname, "localhost");
+    if is_loopback_name {
+        return match address {
+            Some(address) if is_loopback(&address) => None,
+            _ => Some(Reason::LoopbackName),
+        };
+    }
+
+    if protected
+        .endpoints
+        .iter()
+        .any(|endpoint| is_within(hostname, endpoint))
+    {
+        return Some(Reason::BebasinEndpoint);
+    }
+    if UPDATE_DOMAINS
+        .iter()
+        .any(|domain| is_within(hostname, domain))
+    {
+        return Some(Reason::UpdateDomain);
+    }
+    if protected
+        .sensitive
+        .iter()
+        .any(|domain| is_within(hostname, domain))
+    {
+        return Some(Reason::SensitiveDomain);
+    }
+
+    let is_local_name = !hostname.contains('.')
+        || LOCAL_SUFFIXES
+            .iter()
+            .any(|suffix| is_within(hostname, suffix));
+    if is_local_name {
+        return None;
+    }
+    address
+        .as_ref()
+        .and_then(suspicious_range)
+        .map(Reason::SuspiciousAddress)
+}
+
+/// The entries of a custom hosts list that need an explicit confirmation
+pub fn check(hosts: &Hosts, config: &Config) -> Vec<Violation> {
+    let protected = Protected {
+        endpoints: bebasin_endpoints(config),
+        sensitive: config
+            .protected_hostnames
+            .iter()
+            .map(|domain| domain.trim_matches('.').to_lowercase())
+            .filter(|domain| !domain.is_empty())
+            .collect(),
+    };
+    let mut violations = Vec::new();
+    for (ip, hostnames) in hosts {
+        for hostname in hostnames {
+            if let Some(reason) = check_entry(ip, hostname, &protected) {
+                violations.push(Violation {
+                    ip: ip.to_owned(),
+                    hostname: hostname.to_owned(),
+                    reason,
+                });
+            }
+        }
+    }
+    violations.sort_by(|a, b| a.hostname.cmp(&b.hostname).then(a.ip.cmp(&b.ip)));
+    violations
+}
+
+/// `hosts` without the entries of `violations`
+pub fn without(mut hosts: Hosts, violations: &[Violation]) -> Hosts {
+    for violation in violations {
+        if let Some(hostnames) = hosts.get_mut(&violation.ip) {
+            hostnames.remove(&violation.hostname);
+        }
+    }
+    hosts.retain(|_, hostnames| !hostnames.is_empty());
+    hosts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hosts(entries: &[(&str, &[&str])]) -> Hosts {
+        entries
+            .iter()
+            .map(|(ip, hostnames)| {
+                let hostnames = hostnames.iter().map(|hostname| hostname.to_string());
+                (ip.to_string(), hostnames.collect::<HashSet<_>>())
+            })
+            .collect()
+    }
+
+    fn flagged(hosts: &Hosts, config: &Config) -> Vec<String> {
+        check(hosts, config)
+            .into_iter()
+            .map(|violation| violation.hostname)
+            .collect()
+    }
+
+    #[test]
+    fn ordinary_entries_pass() {
+        let ordinary = hosts(&[
+            ("0.0.0.0", &["ads.example.com", "tracker.example.net"]),
+            ("127.0.0.1", &["localhost", "telemetry.example.org"]),
+            ("::1", &["ip6-localhost"]),
+            // Local names legitimately point to private addresses
+            ("192.168.1.10", &["nas", "printer.lan", "router.home.arpa"]),
+            ("93.184.216.34", &["example.com"]),
+        ]);
+        assert!(check(&ordinary, &Config::default()).is_empty());
+    }
+
+    #[test]
+    fn protected_entries_are_flagged() {
+        let config = Config {
+            protected_hostnames: vec![String::from(".Bank.Example.")],
+            ..Config::default()
+        };
+        let protected = hosts(&[
+            ("0.0.0.0", &["api.github.com", "Security.Debian.Org"]),
+            ("93.184.216.34", &["localhost", "login.bank.example"]),
+            ("10.0.0.1", &["www.example.com"]),
+        ]);
+        assert_eq!(
+            flagged(&protected, &config),
+            vec![
+                "Security.Debian.Org",
+                "api.github.com",
+                "localhost",
+                "login.bank.example",
+                "www.example.com",
+            ]
+        );
+        // The protected domains of the configuration are only protected when configured
+        assert!(
+            !flagged(&protected, &Config::default()).contains(&String::from("login.bank.example"))
+        );
+    }
+
+    #[test]
+    fn without_removes_the_flagged_entries() {
+        let mixed = hosts(&[
+            ("0.0.0.0", &["ads.example.com", "update.microsoft.com"]),
+            ("10.0.0.1", &["www.example.com"]),
+        ]);
+        let violations = check(&mixed, &Config::default());
+        assert_eq!(violations.len(), 2);
+        // The address left without any hostname goes away too
+        assert_eq!(
+            without(mixed, &violations),
+            hosts(&[("0.0.0.0", &["ads.example.com"])])
+        );
+    }
+}
@@ -0,0 +1,121 @@
+use crate::error::ErrorKind;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+// Reporting every received chunk or every parsed line would flood the UI
+const TRANSFER_REPORT_STEP: u64 = 64 * 1024;
+const PARSE_REPORT_STEP: usize = 1000;
+
+struct Inner {
+    report: Box<dyn Fn(String) + Send + Sync>,
+    cancelled: AtomicBool,
+    step: Mutex<String>,
+    expected_size: AtomicU64,
+    last_reported: AtomicU64,
+}
+
+/// Shared between an operation running on a worker thread and whoever waits for it.
+/// The operation reports what it is doing and stops when it has been cancelled.
+#[derive(Clone)]
+pub struct Progress(Arc<Inner>);
+
+fn format_size(bytes: u64) -> String {
+    if bytes >= 1024 * 1024 {
+        format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0))
+    } else {
+        format!("{:.1} KiB", bytes as f64 / 1024.0)
+    }
+}
+
+impl Progress {
+    pub fn new<F: Fn(String) + Send + Sync + 'static>(report: F) -> Progress {
+        Progress(Arc::new(Inner {
+            report: Box::new(report),
+            cancelled: AtomicBool::new(false),
+            step: Mutex::new(String::new()),
+            expected_size: AtomicU64::new(0),
+            last_reported: AtomicU64::new(u64::MAX),
+        }))
+    }
+
+    /// Progress that nobody is watching, for the non-interactive commands
+    pub fn none() -> Progress {
+        Progress::new(|_| {})
+    }
+
+    pub fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Stop here when the operation has been cancelled
+    pub fn check(&self) -> Result<(), ErrorKind> {
+        if self.is_cancelled() {
+            Err(ErrorKind::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn step_message(&self) -> String {
+        match self.0.step.lock() {
+            Ok(step) => step.clone(),
+            Err(_) => String::new(),
+        }
+    }
+
+    /// Start the next step of the operation
+    pub fn step(&self, step: &str) {
+        if let Ok(mut current) = self.0.step.lock() {
+            *current = step.to_owned();
+        }
+        self.0.expected_size.store(0, Ordering::SeqCst);
+        self.0.last_reported.store(u64::MAX, Ordering::SeqCst);
+        (self.0.report)(step.to_owned());
+    }
+
+    /// The size of the next transfer, for servers that don't announce it
+    pub fn expect_size(&self, size: u64) {
+        self.0.expected_size.store(size, Ordering::SeqCst);
+    }
+
+    pub fn transferred(&self, downloaded: u64, total: u64) {
+        let chunk = downloaded / TRANSFER_REPORT_STEP;
+        if self.0.last_reported.swap(chunk, Ordering::SeqCst) == chunk {
+            return;
+        }
+
+        let total = if total > 0 {
+            total
+        } else {
+            self.0.expected_size.load(Ordering::SeqCst)
+        };
+        let message = if total > 0 {
+            format!(
+                "{}\n\n{} of {}",
+                self.step_message(),
+                format_size(downloaded),
+                format_size(total)
+            )
+        } else {
+            format!("{}\n\n{}", self.step_message(), format_size(downloaded))
+        };
+        (self.0.report)(message);
+    }
+
+    pub fn parsed(&self, entries: usize) {
+        let chunk = (entries / PARSE_REPORT_STEP) as u64;
+        if self.0.last_reported.swap(chunk, Ordering::SeqCst) == chunk {
+            return;
+        }
+
+        (self.0.report)(format!(
+            "{}\n\n{} entries parsed",
+            self.step_message(),
+            entries
+        ));
+    }
+}
@@ -0,0 +1,194 @@
+use crate::error::ErrorKind;
+use crate::os::{replace_executable, EXECUTABLE_NAME};
+use crate::updater::stage;
+use crate::{state, CURRENT_VERSION};
+use std::env::current_exe;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+const BINARIES_DIRECTORY: &str = "binaries";
+
+/// A previous executable kept in the state directory
+pub struct Kept {
+    pub version: String,
+    path: PathBuf,
+    kept_at: SystemTime,
+}
+
+/// Every kept executable, the most recently kept first
+pub fn kept() -> Result<Vec<Kept>, ErrorKind> {
+    let directories = match fs::read_dir(state::path(BINARIES_DIRECTORY)) {
+        Ok(directories) => directories,
+        Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(ErrorKind::IOError(err)),
+    };
+
+    let mut kept = Vec::new();
+    for directory in directories {
+        let directory = match directory {
+            Ok(directory) => directory,
+            Err(err) => return Err(ErrorKind::IOError(err)),
+        };
+        let mut path = directory.path();
+        path.push(EXECUTABLE_NAME);
+        let kept_at = match fs::metadata(&path).and_then(|metadata| metadata.modified()) {
+            Ok(kept_at) => kept_at,
+            // An incomplete copy, there is nothing to roll back to
+            Err(_) => continue,
+        };
+
+        kept.push(Kept {
+            version: directory.file_name().to_string_lossy().into_owned(),
+            path,
+            kept_at,
+        });
+    }
+    kept.sort_by_key(|kept| std::cmp::Reverse(kept.kept_at));
+    Ok(kept)
+}
+
+/// Copy the running executable to the state directory, and only keep the last `limit` of them
+pub fn keep_current(limit: usize) -> Result<(), ErrorKind> {
+    let exe_path = match current_exe() {
+        Ok(exe_path) => exe_path,
+        Err(err) => return Err(ErrorKind::IOError(err)),
+    };
+    keep(&exe_path, CURRENT_VERSION, limit)
+}
+
+/// Copy the executable at `exe_path` as `version`, a limit of 0 keeps nothing at all
+fn keep(exe_path: &Path, version: &str, limit: usize) -> Result<(), ErrorKind> {
+    if limit > 0 {
+        state::ensure()?;
+        let mut kept_path = state::path(BINARIES_DIRECTORY);
+        kept_path.push(version);
+        state::create_directory(&kept_path)?;
+        kept_path.push(EXECUTABLE_NAME);
+        // Only the content matters, it is staged again with the right permissions when restored
+        let binary = match fs::read(exe_path) {
+            Ok(binary) => binary,
+            Err(err) => return Err(ErrorKind::IOError(err)),
+        };
+        state::write(&kept_path, &binary)?;
+    }
+
+    for old in kept()?.into_iter().skip(limit) {
+        if let Some(directory) = old.path.parent() {
+            fs::remove_dir_all(directory).ok();
+        }
+    }
+    Ok(())
+}
+
+/// Replace the running executable with a kept one, the running one is kept as well
+pub fn restore(version: &str, limit: usize) -> Result<(), ErrorKind> {
+    let exe_path = match current_exe() {
+        Ok(exe_path) => exe_path,
+        Err(err) => return Err(ErrorKind::IOError(err)),
+    };
+    restore_to(&exe_path, version, limit)
+}
+
+fn restore_to(exe_path: &Path, version: &str, limit: usize) -> Result<(), ErrorKind> {
+    let kept = match kept()?.into_iter().find(|kept| kept.version == version) {
+        Some(kept) => kept,
+        None => {
+            return Err(ErrorKind::String(format!(
+                "Version {} is not available to roll back to",
+                version
+            )))
+        }
+    };
+
+    let binary = match fs::read(&kept.path) {
+        Ok(binary) => binary,
+        Err(err) => return Err(ErrorKind::IOError(err)),
+    };
+
+    keep(exe_path, CURRENT_VERSION, limit)?;
+    let staged_path = stage(exe_path, &binary)?;
+    let result = replace_executable(&staged_path, exe_path);
+    if result.is_err() {
+        fs::remove_file(&staged_path).ok();
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use std::sync::{Mutex, MutexGuard};
+    use std::thread;
+    use std::time::Duration;
+
+    /// The tests share the kept executables of the state directory
+    static STATE: Mutex<()> = Mutex::new(());
+
+    /// Take the state directory for a test, without the executables the other tests kept
+    fn lock_state() -> MutexGuard<'static, ()> {
+        let guard = STATE.lock().unwrap_or_else(|err| err.into_inner());
+        fs::remove_dir_all(state::path(BINARIES_DIRECTORY)).ok();
+        guard
+    }
+
+    fn versions() -> Vec<String> {
+        kept()
+            .unwrap()
+            .into_iter()
+            .map(|kept| kept.version)
+            .collect()
+    }
+
+    /// Keep a fake executable as `version`, later than the ones kept before
+    fn keep_version(directory: &TempDir, version: &str, limit: usize) {
+        // The modification times have to differ
+        thread::sleep(Duration::from_millis(20));
+        let exe_path = directory.path.join(EXECUTABLE_NAME);
+        fs::write(&exe_path, version).unwrap();
+        keep(&exe_path, version, limit).unwrap();
+    }
+
+    #[test]
+    fn keeps_the_last_executables() {
+        let _state = lock_state();
+        let directory = TempDir::new("rollback-keep");
+        keep_version(&directory, "1.0.0", 2);
+        keep_version(&directory, "1.1.0", 2);
+        assert_eq!(versions(), ["1.1.0", "1.0.0"]);
+
+        // The oldest goes away
+        keep_version(&directory, "1.2.0", 2);
+        assert_eq!(versions(), ["1.2.0", "1.1.0"]);
+        let kept = kept().unwrap();
+        assert_eq!(fs::read_to_string(&kept[0].path).unwrap(), "1.2.0");
+
+        // Nothing is kept, not even the executable that was just running
+        keep_version(&directory, "1.3.0", 0);
+        assert!(versions().is_empty());
+    }
+
+    #[test]
+    fn restores_a_kept_executable() {
+        let _state = lock_state();
+        let directory = TempDir::new("rollback-restore");
+        keep_version(&directory, "1.0.0", 3);
+        let exe_path = directory.path.join(EXECUTABLE_NAME);
+        fs::write(&exe_path, "running").unwrap();
+
+        match restore_to(&exe_path, "0.9.0", 3) {
+            Err(ErrorKind::String(_)) => {}
+            result => panic!("unexpected result {:?}", result),
+        }
+        assert_eq!(fs::read_to_string(&exe_path).unwrap(), "running");
+
+        thread::sleep(Duration::from_millis(20));
+        restore_to(&exe_path, "1.0.0", 3).unwrap();
+        assert_eq!(fs::read_to_string(&exe_path).unwrap(), "1.0.0");
+        // The executable that was running can be restored in turn
+        assert_eq!(versions(), [CURRENT_VERSION, "1.0.0"]);
+        let kept = kept().unwrap();
+        assert_eq!(fs::read_to_string(&kept[0].path).unwrap(), "running");
+    }
+}
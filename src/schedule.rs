@@ -0,0 +1,274 @@
+use crate::audit;
+use crate::config::Config;
+use crate::error::ErrorKind;
+use crate::list;
+#[cfg(target_os = "linux")]
+use crate::os::systemctl;
+use crate::os::STATE_PATH;
+use crate::progress::Progress;
+use crate::state;
+use crate::subscription::{self, SUBSCRIPTIONS_SOURCE};
+use crate::target;
+use crate::updater::is_installed;
+use crate::verify;
+
+pub const DEFAULT_INTERVAL: u64 = 24;
+
+#[cfg(target_os = "linux")]
+const UNIT_NAME: &str = "bebasin-refresh";
+#[cfg(target_os = "linux")]
+const SYSTEMD_UNITS_PATH: &str = "/etc/systemd/system";
+// Only exists when systemd is the running init system
+#[cfg(target_os = "linux")]
+const SYSTEMD_RUNTIME_PATH: &str = "/run/systemd/system";
+#[cfg(target_os = "linux")]
+const CRON_PATH: &str = "/etc/cron.d";
+#[cfg(target_os = "linux")]
+const CRON_FILE_NAME: &str = "bebasin";
+
+/// Refuse to touch the hosts file when the state Bebasin manages has been changed behind its back
+fn check_tampering() -> Result<(), ErrorKind> {
+    let insecure_paths = state::insecure_paths();
+    if !insecure_paths.is_empty() {
+        return Err(ErrorKind::String(format!(
+            "Refusing to refresh, other users are able to modify the state in {}:\n{}",
+            STATE_PATH,
+            insecure_paths
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>()
+                .join("\n")
+        )));
+    }
+
+    if !list::is_cache_intact() {
+        return Err(ErrorKind::String(String::from(
+            "Refusing to refresh, the cached hosts list doesn't match its recorded checksum",
+        )));
+    }
+
+    if is_installed() && audit::is_hosts_file_modified()? {
+        return Err(ErrorKind::String(String::from(
+            "Refusing to refresh, the hosts file changed since the last change recorded \
+            in the audit log. Review it, then install Bebasin again from the menu",
+        )));
+    }
+    Ok(())
+}
+
+/// Fetch the hosts list and the subscriptions, and apply them when they changed,
+/// without any interaction. This is what the scheduled service runs.
+pub fn refresh() -> Result<String, ErrorKind> {
+    check_tampering()?;
+
+    let config = Config::load()?;
+    // The subscriptions are cached first, applying the hosts list writes them too
+    let subscriptions = subscription::refresh(&config, &Progress::none())?;
+    let fetched = list::fetch(&config, &Progress::none())?;
+    let changes = list::changes(&fetched)?;
+    let mut result = if changes.is_empty() {
+        if subscriptions.is_changed {
+            list::reapply(SUBSCRIPTIONS_SOURCE)?;
+        }
+        format!("The hosts list from {} is up to date", fetched.info.source)
+    } else {
+        list::apply(&fetched)?;
+        format!(
+            "The hosts list has been updated from {}, {} added, {} removed",
+            fetched.info.source,
+            changes.added.len(),
+            changes.removed.len()
+        )
+    };
+    if !subscriptions.lines.is_empty() {
+        result = format!("{}\n{}", result, subscriptions.describe());
+    }
+    if is_installed() && (subscriptions.is_changed || !changes.is_empty()) {
+        result = format!(
+            "{}\n{}\n{}",
+            result,
+            target::configured().reload(),
+            verify::verify(&Progress::none())?.describe()
+        );
+    }
+    Ok(result)
+}
+
+/// A double quoted argument of ExecStart, which also expands specifiers and variables
+#[cfg(target_os = "linux")]
+fn escape_unit_argument(argument: &str) -> String {
+    argument
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%")
+        .replace('$', "$$")
+}
+
+/// A command line of cron goes through the shell, and `%` stands for a new line in it
+#[cfg(target_os = "linux")]
+fn quote_cron_argument(argument: &str) -> String {
+    format!("'{}'", argument.replace('\'', "'\\''").replace('%', "\\%"))
+}
+
+/// The cron schedule running every `interval` hours. The hours field restarts at midnight,
+/// so only the intervals dividing a day are evenly spaced.
+#[cfg(target_os = "linux")]
+fn cron_schedule(interval: u64) -> Result<String, ErrorKind> {
+    match interval {
+        24 => Ok(String::from("0 3 * * *")),
+        1..=23 if 24 % interval == 0 => Ok(format!("0 */{} * * *", interval)),
+        _ => Err(ErrorKind::String(format!(
+            "Cron can't refresh every {} hours, only every 1, 2, 3, 4, 6, 8, 12 or 24 hours",
+            interval
+        ))),
+    }
+}
+
+/// Unit and cron files are read by every user, like the rest of /etc
+#[cfg(target_os = "linux")]
+fn write(path: &std::path::Path, content: &str) -> Result<(), ErrorKind> {
+    crate::os::write_file(path, content.as_bytes(), 0o644)
+}
+
+/// Refresh the hosts list every `interval` hours, through a systemd timer or a cron entry.
+/// The timer counts from the boot and the previous run, cron from midnight.
+/// Returns what has been installed.
+#[cfg(target_os = "linux")]
+pub fn install(interval: u64) -> Result<String, ErrorKind> {
+    use std::path::Path;
+
+    if interval == 0 {
+        return Err(ErrorKind::String(String::from(
+            "The interval has to be at least one hour",
+        )));
+    }
+    let exe_path = match std::env::current_exe() {
+        Ok(exe_path) => exe_path,
+        Err(err) => return Err(ErrorKind::IOError(err)),
+    };
+
+    if Path::new(SYSTEMD_RUNTIME_PATH).is_dir() {
+        let units_path = Path::new(SYSTEMD_UNITS_PATH);
+        write(
+            &units_path.join(format!("{}.service", UNIT_NAME)),
+            &format!(
+                "[Unit]\n\
+                Description=Refresh the Bebasin hosts list\n\
+                Wants=network-online.target\n\
+                After=network-online.target\n\
+                \n\
+                [Service]\n\
+                Type=oneshot\n\
+                ExecStart=\"{}\" refresh\n",
+                escape_unit_argument(&exe_path.display().to_string())
+            ),
+        )?;
+        write(
+            &units_path.join(format!("{}.timer", UNIT_NAME)),
+            &format!(
+                "[Unit]\n\
+                Description=Refresh the Bebasin hosts list every {} hours\n\
+                \n\
+                [Timer]\n\
+                OnBootSec=15min\n\
+                OnUnitActiveSec={}h\n\
+                RandomizedDelaySec=15min\n\
+                \n\
+                [Install]\n\
+                WantedBy=timers.target\n",
+                interval, interval
+            ),
+        )?;
+        systemctl(&["daemon-reload"])?;
+        systemctl(&["enable", "--now", &format!("{}.timer", UNIT_NAME)])?;
+        return Ok(format!("the systemd timer {}.timer", UNIT_NAME));
+    }
+
+    let cron_path = Path::new(CRON_PATH);
+    if !cron_path.is_dir() {
+        return Err(ErrorKind::String(format!(
+            "Neither systemd nor cron ({}) are available",
+            CRON_PATH
+        )));
+    }
+    let schedule = cron_schedule(interval)?;
+    let cron_file_path = cron_path.join(CRON_FILE_NAME);
+    write(
+        &cron_file_path,
+        &format!(
+            "# Refresh the Bebasin hosts list\n{} root {} refresh\n",
+            schedule,
+            quote_cron_argument(&exe_path.display().to_string())
+        ),
+    )?;
+    Ok(format!("the cron entry {}", cron_file_path.display()))
+}
+
+/// Stop refreshing the hosts list, whichever way it has been scheduled
+#[cfg(target_os = "linux")]
+pub fn remove() -> Result<(), ErrorKind> {
+    use std::path::Path;
+
+    let units_path = Path::new(SYSTEMD_UNITS_PATH);
+    let timer_path = units_path.join(format!("{}.timer", UNIT_NAME));
+    if timer_path.exists() {
+        systemctl(&["disable", "--now", &format!("{}.timer", UNIT_NAME)])?;
+        std::fs::remove_file(&timer_path).ok();
+        std::fs::remove_file(units_path.join(format!("{}.service", UNIT_NAME))).ok();
+        systemctl(&["daemon-reload"])?;
+    }
+
+    match std::fs::remove_file(Path::new(CRON_PATH).join(CRON_FILE_NAME)) {
+        Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(ErrorKind::IOError(err)),
+        Ok(_) => Ok(()),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn install(_interval: u64) -> Result<String, ErrorKind> {
+    Err(ErrorKind::String(String::from(
+        "Scheduled refresh is only supported on Linux",
+    )))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn remove() -> Result<(), ErrorKind> {
+    Err(ErrorKind::String(String::from(
+        "Scheduled refresh is only supported on Linux",
+    )))
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unit_arguments_are_escaped() {
+        assert_eq!(
+            escape_unit_argument("/opt/my apps/100%/$HOME/\"b\\\"/bebasin"),
+            "/opt/my apps/100%%/$$HOME/\\\"b\\\\\\\"/bebasin"
+        );
+    }
+
+    #[test]
+    fn cron_intervals_divide_a_day() {
+        assert_eq!(cron_schedule(1).unwrap(), "0 */1 * * *");
+        assert_eq!(cron_schedule(6).unwrap(), "0 */6 * * *");
+        assert_eq!(cron_schedule(24).unwrap(), "0 3 * * *");
+        for interval in &[0, 5, 7, 18, 48] {
+            match cron_schedule(*interval) {
+                Err(ErrorKind::String(_)) => {}
+                result => panic!("unexpected result {:?}", result),
+            }
+        }
+    }
+
+    #[test]
+    fn cron_arguments_are_quoted() {
+        assert_eq!(
+            quote_cron_argument("/opt/it's 100%/bebasin"),
+            "'/opt/it'\\''s 100\\%/bebasin'"
+        );
+    }
+}
@@ -0,0 +1,45 @@
+use crate::error::ErrorKind;
+use crate::SIGNING_PUBLIC_KEY;
+use minisign_verify::{PublicKey, Signature};
+use sha2::{Digest as _, Sha256};
+
+/// File extension of the detached minisign signatures
+pub const SIGNATURE_EXTENSION: &str = ".minisig";
+
+pub fn sha256(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// Verify the detached minisign `signature` of `data` against the embedded public key
+pub fn verify(data: &[u8], signature: &str) -> Result<(), ErrorKind> {
+    let public_key = match PublicKey::from_base64(SIGNING_PUBLIC_KEY) {
+        Ok(public_key) => public_key,
+        Err(err) => return Err(ErrorKind::SignatureError(err)),
+    };
+    let signature = match Signature::decode(signature) {
+        Ok(signature) => signature,
+        Err(err) => return Err(ErrorKind::SignatureError(err)),
+    };
+
+    match public_key.verify(data, &signature, false) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(ErrorKind::SignatureError(err)),
+    }
+}
+
+/// Verify `data` when a signature is available, or refuse it unless unsigned data is allowed
+pub fn verify_or_refuse(
+    data: &[u8],
+    signature: Option<&str>,
+    allow_unsigned: bool,
+    name: &str,
+) -> Result<(), ErrorKind> {
+    match signature {
+        Some(signature) => verify(data, signature),
+        None if allow_unsigned => Ok(()),
+        None => Err(ErrorKind::String(format!(
+            "{} is not signed, refusing to install it",
+            name
+        ))),
+    }
+}
@@ -0,0 +1,145 @@
+use crate::error::ErrorKind;
+use crate::os::create_new_file;
+use std::fs;
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+
+/// Only the owner changes what Bebasin keeps, but the interactive menu runs unprivileged
+/// and has to read the configuration, the cached lists and the audit log
+pub const FILE_MODE: u32 = 0o644;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+const DIRECTORY_MODE: u32 = 0o755;
+
+/// The state directory of the system
+#[cfg(not(test))]
+fn root() -> PathBuf {
+    PathBuf::from(crate::os::STATE_PATH)
+}
+
+/// Tests get a state directory of their own, they never touch the one of the system
+#[cfg(test)]
+fn root() -> PathBuf {
+    std::env::temp_dir().join(format!("bebasin-test-{}", std::process::id()))
+}
+
+/// Path of a file inside the state directory, it may not exist yet.
+pub fn path(name: &str) -> PathBuf {
+    let mut path = root();
+    path.push(name);
+    path
+}
+
+/// The directory of what only saves work, like the update checks and the cached downloads.
+/// Only the administrator writes to the state directory, the others keep their own cache.
+#[cfg(not(test))]
+fn cache_root() -> PathBuf {
+    if crate::os::is_has_admin_access() {
+        return root();
+    }
+    crate::os::user_cache_path().unwrap_or_else(root)
+}
+
+#[cfg(test)]
+fn cache_root() -> PathBuf {
+    root()
+}
+
+/// Path of a file inside the cache directory, it may not exist yet.
+pub fn cache_path(name: &str) -> PathBuf {
+    let mut path = cache_root();
+    path.push(name);
+    path
+}
+
+/// The state directory and the files in it that other users are able to modify.
+/// Nobody else than the owner should be able to change what Bebasin writes to the hosts file.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub fn insecure_paths() -> Vec<PathBuf> {
+    use std::os::unix::fs::MetadataExt as _;
+
+    let state_path = root();
+    let mut paths = vec![state_path.clone()];
+    if let Ok(entries) = fs::read_dir(&state_path) {
+        paths.extend(
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path()),
+        );
+    }
+
+    let owner = nix::unistd::geteuid().as_raw();
+    paths
+        .into_iter()
+        .filter(|path| match fs::symlink_metadata(path) {
+            Ok(metadata) => metadata.uid() != owner || metadata.mode() & 0o022 != 0,
+            Err(_) => false,
+        })
+        .collect()
+}
+
+#[cfg(target_os = "windows")]
+pub fn insecure_paths() -> Vec<PathBuf> {
+    // The state directory inherits the ACL of ProgramData
+    Vec::new()
+}
+
+/// Create a directory that every user is able to read, along with its parents.
+pub fn create_directory(path: &Path) -> Result<(), ErrorKind> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    {
+        use std::os::unix::fs::DirBuilderExt as _;
+        builder.mode(DIRECTORY_MODE);
+    }
+    match builder.create(path) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(ErrorKind::IOError(err)),
+    }
+}
+
+/// Create the state directory if it doesn't exist yet.
+pub fn ensure() -> Result<PathBuf, ErrorKind> {
+    let path = root();
+    create_directory(&path)?;
+    Ok(path)
+}
+
+/// Create the cache directory if it doesn't exist yet.
+pub fn ensure_cache() -> Result<PathBuf, ErrorKind> {
+    let path = cache_root();
+    create_directory(&path)?;
+    Ok(path)
+}
+
+/// Write a file of the state directory through a temporary file created exclusively next to it,
+/// which then replaces the previous one. Nothing planted at either path is followed.
+pub fn write(path: &Path, content: &[u8]) -> Result<(), ErrorKind> {
+    let file_name = match path.file_name() {
+        Some(file_name) => file_name.to_string_lossy(),
+        None => {
+            return Err(ErrorKind::String(format!(
+                "Invalid state file {}",
+                path.display()
+            )))
+        }
+    };
+    let temp_path = path.with_file_name(format!(".{}.tmp", file_name));
+
+    // A leftover of an interrupted write, removing it doesn't follow a symbolic link
+    if fs::symlink_metadata(&temp_path).is_ok() {
+        if let Err(err) = fs::remove_file(&temp_path) {
+            return Err(ErrorKind::IOError(err));
+        }
+    }
+    let mut file = create_new_file(&temp_path, FILE_MODE)?;
+    if let Err(err) = file.write_all(content).and_then(|_| file.sync_all()) {
+        fs::remove_file(&temp_path).ok();
+        return Err(ErrorKind::IOError(err));
+    }
+    if let Err(err) = fs::rename(&temp_path, path) {
+        fs::remove_file(&temp_path).ok();
+        return Err(ErrorKind::IOError(err));
+    }
+    Ok(())
+}
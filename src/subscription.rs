@@ -0,0 +1,367 @@
+use crate::config::{Config, Subscription};
+use crate::error::ErrorKind;
+use crate::helpers::AppendableMap;
+use crate::import::{self, Format, DEFAULT_TARGET};
+use crate::list;
+use crate::parser::{self, parse_from_str, Hosts};
+use crate::policy;
+use crate::progress::Progress;
+use crate::signature;
+use crate::state;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const SUBSCRIPTIONS_DIRECTORY: &str = "subscriptions";
+const STATUS_FILE: &str = "subscriptions.json";
+const MAX_NAME_LENGTH: usize = 64;
+/// What the audit log records when the subscriptions alone changed the hosts file
+pub const SUBSCRIPTIONS_SOURCE: &str = "subscriptions";
+
+/// How the last refresh of a subscription went
+#[derive(Serialize, Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct Status {
+    /// When the subscription has been fetched successfully for the last time
+    pub fetched_at: Option<String>,
+    pub entries: usize,
+    /// Entries left out because they redirect protected hostnames
+    pub skipped: usize,
+    /// Lines of the list without a hosts equivalent
+    pub unrepresentable: usize,
+    /// Why the last refresh failed, the previously fetched entries are still used
+    pub error: Option<String>,
+}
+
+impl Status {
+    pub fn describe(&self) -> String {
+        let mut description = match &self.fetched_at {
+            Some(fetched_at) => format!(
+                "{} entries, fetched at {}",
+                self.entries,
+                chrono::DateTime::parse_from_rfc3339(fetched_at)
+                    .map(|date| date.format("%Y-%m-%d %H:%M").to_string())
+                    .unwrap_or_else(|_| fetched_at.to_owned())
+            ),
+            None => String::from("never fetched"),
+        };
+        if self.skipped > 0 {
+            description.push_str(&format!(", {} protected entries left out", self.skipped));
+        }
+        if self.unrepresentable > 0 {
+            description.push_str(&format!(
+                ", {} lines without a hosts equivalent",
+                self.unrepresentable
+            ));
+        }
+        if let Some(error) = &self.error {
+            description.push_str(&format!("\nlast refresh failed: {}", error));
+        }
+        description
+    }
+}
+
+/// The outcome of refreshing every subscription
+pub struct Refreshed {
+    pub lines: Vec<String>,
+    /// Whether the entries of a subscription changed, the hosts file has to be rewritten then
+    pub is_changed: bool,
+}
+
+impl Refreshed {
+    pub fn describe(&self) -> String {
+        self.lines.join("\n")
+    }
+}
+
+/// The cached entries of a subscription, named after its hash so any name is a valid file name
+fn cache_path(name: &str) -> PathBuf {
+    state::path(SUBSCRIPTIONS_DIRECTORY).join(signature::sha256(name.as_bytes()))
+}
+
+/// The status of every subscription that has been refreshed, by name
+pub fn statuses() -> BTreeMap<String, Status> {
+    fs::read_to_string(state::path(STATUS_FILE))
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+fn save_statuses(statuses: &BTreeMap<String, Status>) -> Result<(), ErrorKind> {
+    match serde_json::to_string(statuses) {
+        Ok(content) => state::write(&state::path(STATUS_FILE), content.as_bytes()),
+        Err(err) => Err(ErrorKind::SerdeJSONError(err)),
+    }
+}
+
+fn is_url(location: &str) -> bool {
+    location.starts_with("http://") || location.starts_with("https://")
+}
+
+/// Add a subscription to the configuration, it is fetched by the next refresh
+pub fn add(config: &mut Config, name: &str, location: &str) -> Result<(), ErrorKind> {
+    let name = name.trim();
+    let location = location.trim();
+    if name.is_empty() || name.len() > MAX_NAME_LENGTH || name.chars().any(char::is_control) {
+        return Err(ErrorKind::String(format!(
+            "Invalid subscription name '{}'",
+            name
+        )));
+    }
+    if config
+        .subscriptions
+        .iter()
+        .any(|subscription| subscription.name == name)
+    {
+        return Err(ErrorKind::String(format!(
+            "There is already a subscription named '{}'",
+            name
+        )));
+    }
+    if !is_url(location) && !Path::new(location.trim_start_matches("file://")).is_file() {
+        return Err(ErrorKind::String(format!(
+            "'{}' is neither an URL nor an existing file",
+            location
+        )));
+    }
+
+    config.subscriptions.push(Subscription {
+        name: name.to_owned(),
+        location: location.to_owned(),
+    });
+    config.save()
+}
+
+/// Remove a subscription from the configuration along with its cached entries.
+/// Returns whether it had entries, the hosts file has to be rewritten then.
+pub fn remove(config: &mut Config, name: &str) -> Result<bool, ErrorKind> {
+    let count = config.subscriptions.len();
+    config
+        .subscriptions
+        .retain(|subscription| subscription.name != name);
+    if config.subscriptions.len() == count {
+        return Err(ErrorKind::String(format!(
+            "There is no subscription named '{}'",
+            name
+        )));
+    }
+    config.save()?;
+
+    let mut statuses = statuses();
+    if statuses.remove(name).is_some() {
+        save_statuses(&statuses)?;
+    }
+    match fs::remove_file(cache_path(name)) {
+        Ok(_) => Ok(true),
+        Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(ErrorKind::IOError(err)),
+    }
+}
+
+/// Fetch and import a subscription in whichever format it is written in, the entries
+/// redirecting protected hostnames are left out. Returns the entries to cache and the status.
+fn fetch(
+    subscription: &Subscription,
+    config: &Config,
+    progress: &Progress,
+) -> Result<(Hosts, Status), ErrorKind> {
+    let transport = crate::transport::from_config(config);
+    let content = list::read(transport.as_ref(), &subscription.location, progress)?;
+    let imported = import::import(&content, Format::detect(&content), DEFAULT_TARGET, progress)?;
+    let violations = policy::check(&imported.hosts, config);
+    let hosts = policy::without(imported.hosts, &violations);
+    let status = Status {
+        fetched_at: Some(chrono::Local::now().to_rfc3339()),
+        entries: hosts.values().map(|hostnames| hostnames.len()).sum(),
+        skipped: violations.len(),
+        unrepresentable: imported.unrepresentable.len(),
+        error: None,
+    };
+    Ok((hosts, status))
+}
+
+/// Fetch every subscription of the configuration and cache its entries in the state directory.
+/// A failing subscription keeps its previous entries, the failure is recorded in its status.
+pub fn refresh(config: &Config, progress: &Progress) -> Result<Refreshed, ErrorKind> {
+    state::ensure()?;
+    state::create_directory(&state::path(SUBSCRIPTIONS_DIRECTORY))?;
+
+    let mut statuses = statuses();
+    let mut refreshed = Refreshed {
+        lines: Vec::new(),
+        is_changed: false,
+    };
+    for subscription in &config.subscriptions {
+        progress.step(&format!(
+            "Downloading {} from {}",
+            subscription.name, subscription.location
+        ));
+        let status = statuses.entry(subscription.name.clone()).or_default();
+        match fetch(subscription, config, progress) {
+            Ok((hosts, fetched)) => {
+                // The order of the written entries varies, only the entries themselves matter
+                let path = cache_path(&subscription.name);
+                let cached = fs::read_to_string(&path)
+                    .ok()
+                    .and_then(|content| parse_from_str(&content).ok());
+                if cached.as_ref() != Some(&hosts) {
+                    state::write(&path, parser::to_string(&hosts, "").as_bytes())?;
+                    refreshed.is_changed = true;
+                }
+                *status = fetched;
+            }
+            Err(ErrorKind::Cancelled) => return Err(ErrorKind::Cancelled),
+            Err(err) => status.error = Some(err.to_string()),
+        }
+        refreshed
+            .lines
+            .push(format!("{}: {}", subscription.name, status.describe()));
+    }
+    save_statuses(&statuses)?;
+    Ok(refreshed)
+}
+
+/// The cached entries of every subscription of the configuration
+pub fn cached_hosts() -> Result<Hosts, ErrorKind> {
+    let mut hosts: Hosts = HashMap::new();
+    for subscription in Config::load()?.subscriptions {
+        // Never fetched yet
+        let content = match fs::read_to_string(cache_path(&subscription.name)) {
+            Ok(content) => content,
+            Err(_) => continue,
+        };
+        hosts.append(parse_from_str(&content)?).ok();
+    }
+    Ok(hosts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, HttpServer, Reply, TempDir};
+    use std::collections::HashSet;
+    use std::sync::Mutex;
+
+    /// The tests share the configuration and the cache of the state directory
+    static STATE: Mutex<()> = Mutex::new(());
+
+    fn names(config: &Config) -> Vec<&str> {
+        config
+            .subscriptions
+            .iter()
+            .map(|subscription| subscription.name.as_str())
+            .collect()
+    }
+
+    fn remove_every_subscription() {
+        let mut config = Config::load().unwrap();
+        let names = names(&config)
+            .into_iter()
+            .map(str::to_owned)
+            .collect::<Vec<_>>();
+        for name in names {
+            remove(&mut config, &name).unwrap();
+        }
+    }
+
+    #[test]
+    fn adds_and_removes_subscriptions() {
+        let _state = STATE.lock().unwrap_or_else(|err| err.into_inner());
+        let directory = TempDir::new("subscription-add");
+        let path = directory.path.join("domains.txt");
+        fs::write(&path, "ads.example.com\n").unwrap();
+        let path = path.display().to_string();
+        let server = HttpServer::start();
+        server.route("hosts", vec![Reply::status(404)]);
+
+        let mut config = testing::config();
+        add(&mut config, " local ", &path).unwrap();
+        add(&mut config, "remote", &server.url("hosts")).unwrap();
+        assert_eq!(names(&config), ["local", "remote"]);
+        assert_eq!(names(&Config::load().unwrap()), ["local", "remote"]);
+
+        for (name, location) in &[
+            ("local", "https://example.com/other"),
+            ("", "https://example.com/other"),
+            ("line\nbreak", "https://example.com/other"),
+            ("missing", "/nonexistent/bebasin/hosts"),
+        ] {
+            match add(&mut config, name, location) {
+                Err(ErrorKind::String(_)) => {}
+                result => panic!("unexpected result {:?}", result),
+            }
+        }
+        assert_eq!(names(&config), ["local", "remote"]);
+
+        refresh(&config, &Progress::none()).unwrap();
+        // Only the subscription that could be fetched had entries in the hosts file
+        assert!(remove(&mut config, "local").unwrap());
+        assert!(!cache_path("local").exists());
+        assert!(!statuses().contains_key("local"));
+        assert!(!remove(&mut config, "remote").unwrap());
+        match remove(&mut config, "remote") {
+            Err(ErrorKind::String(_)) => {}
+            result => panic!("unexpected result {:?}", result),
+        }
+        assert!(Config::load().unwrap().subscriptions.is_empty());
+    }
+
+    #[test]
+    fn refresh_merges_the_subscriptions() {
+        let _state = STATE.lock().unwrap_or_else(|err| err.into_inner());
+        let server = HttpServer::start();
+        server.route(
+            "adblock.txt",
+            vec![
+                // The endpoint Bebasin updates from is protected
+                Reply::ok(b"||ads.example.com^\n||api.github.com^\n@@||good.example.com^\n"),
+                Reply::status(500),
+            ],
+        );
+        server.route("gone.txt", vec![Reply::status(404)]);
+        let directory = TempDir::new("subscription-merge");
+        let path = directory.path.join("hosts");
+        fs::write(&path, "0.0.0.0 tracker.example.net\n10.0.0.1 nas.lan\n").unwrap();
+
+        let mut config = testing::config();
+        add(&mut config, "adblock", &server.url("adblock.txt")).unwrap();
+        add(&mut config, "hosts", &path.display().to_string()).unwrap();
+        add(&mut config, "gone", &server.url("gone.txt")).unwrap();
+
+        let refreshed = refresh(&config, &Progress::none()).unwrap();
+        assert!(refreshed.is_changed);
+        assert_eq!(refreshed.lines.len(), 3);
+        let statuses = statuses();
+        assert_eq!(statuses["adblock"].entries, 1);
+        assert_eq!(statuses["adblock"].skipped, 1);
+        assert_eq!(statuses["adblock"].unrepresentable, 1);
+        assert_eq!(statuses["hosts"].entries, 2);
+        assert!(statuses["gone"].fetched_at.is_none());
+        assert!(statuses["gone"].error.is_some());
+
+        let expected: Hosts = vec![
+            (
+                String::from("0.0.0.0"),
+                vec!["ads.example.com", "tracker.example.net"],
+            ),
+            (String::from("10.0.0.1"), vec!["nas.lan"]),
+        ]
+        .into_iter()
+        .map(|(ip, hostnames)| {
+            let hostnames = hostnames.into_iter().map(str::to_owned);
+            (ip, hostnames.collect::<HashSet<_>>())
+        })
+        .collect();
+        assert_eq!(cached_hosts().unwrap(), expected);
+
+        // A failing refresh keeps the entries fetched before
+        let refreshed = refresh(&config, &Progress::none()).unwrap();
+        assert!(!refreshed.is_changed);
+        assert!(super::statuses()["adblock"].error.is_some());
+        assert_eq!(cached_hosts().unwrap(), expected);
+
+        remove_every_subscription();
+        assert!(cached_hosts().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,287 @@
+use crate::config::Config;
+use crate::error::ErrorKind;
+use crate::export;
+use crate::flush;
+use crate::helpers::AppendableMap;
+use crate::import;
+use crate::os::{self, write_file, HOSTS_BACKUP_PATH, HOSTS_FILE_MODE, HOSTS_PATH};
+use crate::parser::{parse_from_file, parse_from_str, write_to_file, Hosts};
+use crate::progress::Progress;
+use crate::updater::{backup, is_backed};
+use crate::HOSTS_HEADER;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+const DNSMASQ_PATH: &str = "/etc/dnsmasq.d/bebasin.conf";
+const UNBOUND_PATH: &str = "/etc/unbound/unbound.conf.d/bebasin.conf";
+const NETWORK_MANAGER_PATH: &str = "/etc/NetworkManager/dnsmasq.d/bebasin.conf";
+/// Drop-ins are read by the DNS servers running as another user
+const DROP_IN_MODE: u32 = 0o644;
+
+/// Where the mapping managed by Bebasin gets installed
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Kind {
+    HostsFile,
+    /// A drop-in of the dnsmasq configuration directory
+    Dnsmasq,
+    /// An include of the unbound configuration directory
+    Unbound,
+    /// A drop-in of the local dnsmasq NetworkManager runs as the system resolver,
+    /// which it only does with `dns=dnsmasq` in NetworkManager.conf
+    NetworkManager,
+}
+
+impl Kind {
+    pub const ALL: [Kind; 4] = [
+        Kind::HostsFile,
+        Kind::Dnsmasq,
+        Kind::Unbound,
+        Kind::NetworkManager,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Kind::HostsFile => "hosts-file",
+            Kind::Dnsmasq => "dnsmasq",
+            Kind::Unbound => "unbound",
+            Kind::NetworkManager => "network-manager",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Kind> {
+        Kind::ALL.iter().copied().find(|kind| kind.as_str() == name)
+    }
+
+    /// The DNS servers the drop-ins are written for only run on Linux
+    pub fn is_available(self) -> bool {
+        self == Kind::HostsFile || cfg!(target_os = "linux")
+    }
+}
+
+/// Everything that installs the mapping somewhere goes through a target
+pub trait Target {
+    fn kind(&self) -> Kind;
+
+    /// The file holding the mapping
+    fn path(&self) -> &Path;
+
+    fn is_installed(&self) -> bool;
+
+    /// Write `hosts` as the mapping managed by Bebasin, replacing the previous one
+    fn install(&self, hosts: Hosts) -> Result<(), ErrorKind>;
+
+    /// Remove the mapping managed by Bebasin, leaving the rest as it was before
+    fn uninstall(&self) -> Result<(), ErrorKind>;
+
+    /// Read the mapping back from the content of the file
+    fn parse(&self, content: &str) -> Result<Hosts, ErrorKind>;
+
+    /// Make the DNS resolution pick the change up, returns what the user has to know
+    fn reload(&self) -> String;
+
+    fn status(&self) -> String {
+        format!(
+            "{}: {} {}",
+            self.kind().as_str(),
+            self.path().display(),
+            if self.is_installed() {
+                "(installed)"
+            } else {
+                "(not installed)"
+            }
+        )
+    }
+}
+
+/// The system hosts file, the original entries are kept in a backup next to it
+pub struct HostsFile;
+
+impl Target for HostsFile {
+    fn kind(&self) -> Kind {
+        Kind::HostsFile
+    }
+
+    fn path(&self) -> &Path {
+        Path::new(HOSTS_PATH)
+    }
+
+    fn is_installed(&self) -> bool {
+        is_backed()
+    }
+
+    fn install(&self, mut hosts: Hosts) -> Result<(), ErrorKind> {
+        if !is_backed() {
+            backup()?;
+        }
+        let hosts_backup = parse_from_file(HOSTS_BACKUP_PATH)?;
+        hosts.append(hosts_backup).ok();
+        write_to_file(HOSTS_PATH, &hosts, HOSTS_HEADER)
+    }
+
+    fn uninstall(&self) -> Result<(), ErrorKind> {
+        if !is_backed() {
+            return Err(ErrorKind::String(String::from(
+                "There is no backup of the original hosts file",
+            )));
+        }
+
+        let backup = match fs::read(HOSTS_BACKUP_PATH) {
+            Ok(backup) => backup,
+            Err(err) => return Err(ErrorKind::IOError(err)),
+        };
+        write_file(Path::new(HOSTS_PATH), &backup, HOSTS_FILE_MODE)?;
+        match fs::remove_file(HOSTS_BACKUP_PATH) {
+            Ok(_) => Ok(()),
+            Err(err) => Err(ErrorKind::IOError(err)),
+        }
+    }
+
+    fn parse(&self, content: &str) -> Result<Hosts, ErrorKind> {
+        parse_from_str(content)
+    }
+
+    fn reload(&self) -> String {
+        flush::flush().describe()
+    }
+}
+
+/// A file of its own in the configuration directory of a DNS server
+pub struct DropIn {
+    kind: Kind,
+    path: &'static str,
+    format: export::Format,
+    /// The DNS server reading the drop-in, dnsmasq and unbound only read their configuration
+    /// at start so their systemd unit gets restarted
+    unit: &'static str,
+}
+
+impl Target for DropIn {
+    fn kind(&self) -> Kind {
+        self.kind
+    }
+
+    fn path(&self) -> &Path {
+        Path::new(self.path)
+    }
+
+    fn is_installed(&self) -> bool {
+        fs::symlink_metadata(self.path).is_ok()
+    }
+
+    fn install(&self, hosts: Hosts) -> Result<(), ErrorKind> {
+        // A missing directory means the DNS server isn't installed, creating it wouldn't help
+        if let Some(directory) = self.path().parent() {
+            if !directory.is_dir() {
+                return Err(ErrorKind::String(format!(
+                    "{} is missing, is {} installed?",
+                    directory.display(),
+                    self.unit
+                )));
+            }
+        }
+        let content = match self.format {
+            // address= would block the subdomains too, unlike the hosts file does
+            export::Format::Dnsmasq => export::dnsmasq_host_records(&hosts),
+            format => export::export(&hosts, format),
+        };
+        write_file(self.path(), content.as_bytes(), DROP_IN_MODE)
+    }
+
+    fn uninstall(&self) -> Result<(), ErrorKind> {
+        match fs::remove_file(self.path) {
+            Ok(_) => Ok(()),
+            Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => Err(ErrorKind::String(
+                format!("Bebasin isn't installed to {}", self.path),
+            )),
+            Err(err) => Err(ErrorKind::IOError(err)),
+        }
+    }
+
+    fn parse(&self, content: &str) -> Result<Hosts, ErrorKind> {
+        let format = match self.format {
+            export::Format::Unbound => import::Format::Unbound,
+            _ => import::Format::Dnsmasq,
+        };
+        import::import(content, format, import::DEFAULT_TARGET, &Progress::none())
+            .map(|imported| imported.hosts)
+    }
+
+    fn reload(&self) -> String {
+        // Restarting NetworkManager would take the connections down, the DNS plugin is enough
+        let restarted = match self.kind {
+            Kind::NetworkManager => match os::run("nmcli", &["general", "reload", "dns-full"]) {
+                Ok(_) => String::from("The DNS configuration of NetworkManager has been reloaded"),
+                Err(err) => format!(
+                    "The DNS configuration of NetworkManager could not be reloaded, \
+                    please run nmcli general reload dns-full\n{}",
+                    err
+                ),
+            },
+            _ => match os::systemctl(&["try-restart", self.unit]) {
+                Ok(_) => format!("{} has been restarted", self.unit),
+                Err(err) => format!(
+                    "{} could not be restarted, please restart it\n{}",
+                    self.unit, err
+                ),
+            },
+        };
+        // Whatever resolved through the server before may still be cached
+        format!("{}\n{}", restarted, flush::flush().describe())
+    }
+}
+
+pub fn from_kind(kind: Kind) -> Box<dyn Target> {
+    match kind {
+        Kind::HostsFile => Box::new(HostsFile),
+        Kind::Dnsmasq => Box::new(DropIn {
+            kind,
+            path: DNSMASQ_PATH,
+            format: export::Format::Dnsmasq,
+            unit: "dnsmasq",
+        }),
+        Kind::Unbound => Box::new(DropIn {
+            kind,
+            path: UNBOUND_PATH,
+            format: export::Format::Unbound,
+            unit: "unbound",
+        }),
+        Kind::NetworkManager => Box::new(DropIn {
+            kind,
+            path: NETWORK_MANAGER_PATH,
+            format: export::Format::Dnsmasq,
+            unit: "NetworkManager",
+        }),
+    }
+}
+
+/// The target of the configuration, the hosts file when the configuration can't be read
+pub fn configured() -> Box<dyn Target> {
+    match Config::load() {
+        Ok(config) if config.install_target.is_available() => from_kind(config.install_target),
+        _ => Box::new(HostsFile),
+    }
+}
+
+/// Change the target of the configuration, Bebasin has to be uninstalled first
+/// so that nothing is left behind in the previous one
+pub fn select(kind: Kind) -> Result<(), ErrorKind> {
+    if !kind.is_available() {
+        return Err(ErrorKind::String(format!(
+            "The {} target is only available on Linux",
+            kind.as_str()
+        )));
+    }
+    let current = configured();
+    if current.kind() != kind && current.is_installed() {
+        return Err(ErrorKind::String(format!(
+            "Uninstall Bebasin from {} before changing the install target",
+            current.path().display()
+        )));
+    }
+
+    let mut config = Config::load()?;
+    config.install_target = kind;
+    config.save()
+}
@@ -0,0 +1,239 @@
+//! Fixtures shared by the tests, nothing here is part of the application
+
+use crate::config::Config;
+use crate::error::ErrorKind;
+use crate::progress::Progress;
+use crate::transport::{self, Response, Transport, Validators};
+use std::collections::{HashMap, VecDeque};
+use std::io::{Read as _, Write as _};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// What the server answers to a request
+#[derive(Clone)]
+pub struct Reply {
+    pub status: u32,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Reply {
+    pub fn ok(body: &[u8]) -> Reply {
+        Reply::status(200).body(body)
+    }
+
+    pub fn status(status: u32) -> Reply {
+        Reply {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn body(mut self, body: &[u8]) -> Reply {
+        self.body = body.to_vec();
+        self
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Reply {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+}
+
+/// A request the server received, the header names are lowercase
+pub struct Received {
+    pub path: String,
+    pub headers: HashMap<String, String>,
+}
+
+#[derive(Default)]
+struct Routes {
+    /// The replies of every path in order, the last one is repeated
+    replies: HashMap<String, VecDeque<Reply>>,
+    received: Vec<Received>,
+}
+
+/// A HTTP server on the loopback interface, answering the replies it has been given.
+/// A reply with an ETag is answered with 304 when the request carries the same one.
+pub struct HttpServer {
+    address: SocketAddr,
+    routes: Arc<Mutex<Routes>>,
+}
+
+impl HttpServer {
+    pub fn start() -> HttpServer {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let routes = Arc::new(Mutex::new(Routes::default()));
+        let server_routes = routes.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => answer(stream, &server_routes),
+                    Err(_) => return,
+                }
+            }
+        });
+        HttpServer { address, routes }
+    }
+
+    /// The URL of `path` on this server
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}/{}", self.address, path.trim_start_matches('/'))
+    }
+
+    /// Answer `replies` in order to the requests for `path`
+    pub fn route(&self, path: &str, replies: Vec<Reply>) {
+        self.routes
+            .lock()
+            .unwrap()
+            .replies
+            .insert(path.trim_start_matches('/').to_owned(), replies.into());
+    }
+
+    /// The requests received for `path`, oldest first
+    pub fn received(&self, path: &str) -> Vec<Received> {
+        let path = path.trim_start_matches('/');
+        let mut routes = self.routes.lock().unwrap();
+        let (matching, others) = routes
+            .received
+            .drain(..)
+            .partition(|received| received.path == path);
+        routes.received = others;
+        matching
+    }
+}
+
+fn read_request(stream: &mut TcpStream) -> Option<Received> {
+    let mut head = Vec::new();
+    let mut byte = [0; 1];
+    while !head.ends_with(b"\r\n\r\n") {
+        if stream.read(&mut byte).ok()? == 0 {
+            return None;
+        }
+        head.push(byte[0]);
+    }
+
+    let head = String::from_utf8_lossy(&head).into_owned();
+    let mut lines = head.lines();
+    let target = lines.next()?.split_whitespace().nth(1)?.to_owned();
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_owned()))
+        .collect();
+    Some(Received {
+        path: target.trim_start_matches('/').to_owned(),
+        headers,
+    })
+}
+
+fn answer(mut stream: TcpStream, routes: &Mutex<Routes>) {
+    let received = match read_request(&mut stream) {
+        Some(received) => received,
+        None => return,
+    };
+
+    let reply = {
+        let mut routes = routes.lock().unwrap();
+        let reply = match routes.replies.get_mut(&received.path) {
+            Some(replies) if replies.len() > 1 => replies.pop_front(),
+            Some(replies) => replies.front().cloned(),
+            None => None,
+        };
+        let is_not_modified = match &reply {
+            Some(reply) => reply.headers.iter().any(|(name, value)| {
+                name.eq_ignore_ascii_case("etag")
+                    && received.headers.get("if-none-match") == Some(value)
+            }),
+            None => false,
+        };
+        routes.received.push(received);
+        match reply {
+            Some(_) if is_not_modified => Reply::status(304),
+            Some(reply) => reply,
+            None => Reply::status(404),
+        }
+    };
+
+    let mut response = format!(
+        "HTTP/1.1 {} Test\r\nContent-Length: {}\r\nConnection: close\r\n",
+        reply.status,
+        reply.body.len()
+    );
+    for (name, value) in &reply.headers {
+        response.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    response.push_str("\r\n");
+    let mut response = response.into_bytes();
+    response.extend_from_slice(&reply.body);
+    stream.write_all(&response).ok();
+}
+
+/// Sends every request to the local server, keeping the path of the URL like a mirror does.
+/// The URLs already pointing to the server are left alone.
+pub struct Redirect {
+    base: String,
+    inner: Box<dyn Transport>,
+}
+
+impl Redirect {
+    pub fn new(server: &HttpServer, config: &Config) -> Redirect {
+        Redirect {
+            base: server.url(""),
+            inner: transport::from_config(config),
+        }
+    }
+}
+
+impl Transport for Redirect {
+    fn request(
+        &self,
+        url: &str,
+        cached: Option<&Validators>,
+        progress: &Progress,
+    ) -> Result<Response, ErrorKind> {
+        if url.starts_with(&self.base) {
+            self.inner.request(url, cached, progress)
+        } else {
+            self.inner
+                .request(&transport::mirror_url(&self.base, url), cached, progress)
+        }
+    }
+}
+
+/// The configuration of the network tests, failing fast
+pub fn config() -> Config {
+    Config {
+        timeout: 5,
+        retries: 0,
+        ..Config::default()
+    }
+}
+
+/// A directory of its own for a test, removed when it goes out of scope
+pub struct TempDir {
+    pub path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        let path = std::env::temp_dir().join(format!(
+            "bebasin-{}-{}-{:?}",
+            name,
+            std::process::id(),
+            thread::current().id()
+        ));
+        std::fs::remove_dir_all(&path).ok();
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        std::fs::remove_dir_all(&self.path).ok();
+    }
+}
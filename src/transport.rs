@@ -0,0 +1,463 @@
+use crate::config::Config;
+use crate::error::ErrorKind;
+use crate::progress::Progress;
+use crate::signature;
+use crate::{state, CURRENT_VERSION};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::thread::sleep;
+use std::time::Duration;
+
+const CACHE_DIRECTORY: &str = "cache";
+
+/// What identifies a downloaded version of a resource, the server answers 304 while it matches
+#[derive(Default, Serialize, Deserialize)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl Validators {
+    fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+}
+
+pub struct Response {
+    pub status: u32,
+    pub body: Vec<u8>,
+    pub validators: Validators,
+}
+
+impl Response {
+    pub fn is_success(&self) -> bool {
+        self.status >= 200 && self.status < 300
+    }
+}
+
+/// Everything that talks to the network goes through a transport,
+/// so the network can be swapped with a local directory
+pub trait Transport: Send + Sync {
+    /// Get `url`, the server answers 304 when `cached` still matches the resource
+    fn request(
+        &self,
+        url: &str,
+        cached: Option<&Validators>,
+        progress: &Progress,
+    ) -> Result<Response, ErrorKind>;
+
+    fn download(&self, url: &str) -> Result<Vec<u8>, ErrorKind> {
+        self.download_with_progress(url, &Progress::none())
+    }
+
+    /// Get the body of `url`, any non-successful status is an error
+    fn download_with_progress(&self, url: &str, progress: &Progress) -> Result<Vec<u8>, ErrorKind> {
+        let response = self.request(url, None, progress)?;
+        if response.is_success() {
+            Ok(response.body)
+        } else {
+            Err(ErrorKind::HTTPError(response.status, url.to_owned()))
+        }
+    }
+}
+
+/// Download `url` with the ETag and Last-Modified date of its previous download, an unchanged
+/// resource is then read from the cache directory. Unchanged resources don't count
+/// against the GitHub API rate limit.
+pub fn download_cached(
+    transport: &dyn Transport,
+    url: &str,
+    progress: &Progress,
+) -> Result<Vec<u8>, ErrorKind> {
+    let body_path = state::cache_path(CACHE_DIRECTORY).join(signature::sha256(url.as_bytes()));
+    let validators_path = body_path.with_extension("json");
+    let cached = if body_path.exists() {
+        fs::read_to_string(&validators_path)
+            .ok()
+            .and_then(|content| serde_json::from_str::<Validators>(&content).ok())
+    } else {
+        None
+    };
+
+    let response = transport.request(url, cached.as_ref(), progress)?;
+    if response.status == 304 {
+        return match fs::read(&body_path) {
+            Ok(body) => Ok(body),
+            // The cache went away in the meantime
+            Err(_) => transport.download_with_progress(url, progress),
+        };
+    }
+    if !response.is_success() {
+        return Err(ErrorKind::HTTPError(response.status, url.to_owned()));
+    }
+
+    // The cache only saves requests, failing to write it is fine
+    if !response.validators.is_empty() {
+        if let (Ok(_), Ok(_), Ok(validators)) = (
+            state::ensure_cache(),
+            state::create_directory(&state::cache_path(CACHE_DIRECTORY)),
+            serde_json::to_string(&response.validators),
+        ) {
+            if state::write(&body_path, &response.body).is_ok() {
+                state::write(&validators_path, validators.as_bytes()).ok();
+            }
+        }
+    }
+    Ok(response.body)
+}
+
+/// Build the transport configured by the user
+pub fn from_config(config: &Config) -> Box<dyn Transport> {
+    match &config.local_mirror {
+        Some(root) => Box::new(LocalTransport {
+            root: PathBuf::from(root),
+        }),
+        None => Box::new(CurlTransport {
+            timeout: Duration::from_secs(config.timeout),
+            retries: config.retries,
+            mirrors: config.mirrors.clone(),
+            proxy: config.proxy.clone(),
+            ca_bundle: config.ca_bundle.clone(),
+        }),
+    }
+}
+
+/// The path of an URL, without the scheme and the host
+pub fn url_path(url: &str) -> &str {
+    let path = match url.split_once("://") {
+        Some((_, rest)) => rest,
+        None => url,
+    };
+    match path.split_once('/') {
+        Some((_, path)) => path,
+        None => "",
+    }
+}
+
+/// The same resource on a mirror, mirrors keep the path of the original URL
+pub fn mirror_url(base: &str, url: &str) -> String {
+    format!("{}/{}", base.trim_end_matches('/'), url_path(url))
+}
+
+/// The proxy from the standard environment variables, the same ones curl and most tools read
+fn proxy_from_env(url: &str) -> Option<String> {
+    let names: &[&str] = if url.starts_with("https://") {
+        &["https_proxy", "HTTPS_PROXY", "all_proxy", "ALL_PROXY"]
+    } else {
+        &["http_proxy", "HTTP_PROXY", "all_proxy", "ALL_PROXY"]
+    };
+    names
+        .iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|proxy| !proxy.is_empty())
+}
+
+fn no_proxy_from_env() -> Option<String> {
+    std::env::var("no_proxy")
+        .or_else(|_| std::env::var("NO_PROXY"))
+        .ok()
+}
+
+pub struct CurlTransport {
+    timeout: Duration,
+    retries: u32,
+    mirrors: Vec<String>,
+    proxy: Option<String>,
+    ca_bundle: Option<String>,
+}
+
+fn is_transient(err: &curl::Error) -> bool {
+    err.is_couldnt_connect()
+        || err.is_couldnt_resolve_host()
+        || err.is_operation_timedout()
+        || err.is_recv_error()
+        || err.is_send_error()
+        || err.is_partial_file()
+}
+
+impl CurlTransport {
+    fn get_once(
+        &self,
+        url: &str,
+        cached: Option<&Validators>,
+        progress: &Progress,
+    ) -> Result<Response, curl::Error> {
+        let mut body = Vec::new();
+        let mut validators = Validators::default();
+        let mut curl_instance = curl::easy::Easy::new();
+        curl_instance.url(url)?;
+        curl_instance.useragent(&format!("bebasin/{}", CURRENT_VERSION))?;
+        curl_instance.follow_location(true)?;
+        curl_instance.connect_timeout(self.timeout)?;
+        // Large downloads may take a while, only give up when the transfer stalls
+        curl_instance.low_speed_limit(1)?;
+        curl_instance.low_speed_time(self.timeout)?;
+        curl_instance.progress(true)?;
+        // A proxy given explicitly applies to every host, the environment one honours no_proxy
+        match &self.proxy {
+            Some(proxy) => curl_instance.proxy(proxy)?,
+            None => {
+                if let Some(proxy) = proxy_from_env(url) {
+                    curl_instance.proxy(&proxy)?;
+                    if let Some(no_proxy) = no_proxy_from_env() {
+                        curl_instance.noproxy(&no_proxy)?;
+                    }
+                }
+            }
+        };
+        if let Some(ca_bundle) = &self.ca_bundle {
+            curl_instance.cainfo(ca_bundle)?;
+        }
+        if let Some(cached) = cached {
+            let mut headers = curl::easy::List::new();
+            if let Some(etag) = &cached.etag {
+                headers.append(&format!("If-None-Match: {}", etag))?;
+            }
+            if let Some(last_modified) = &cached.last_modified {
+                headers.append(&format!("If-Modified-Since: {}", last_modified))?;
+            }
+            curl_instance.http_headers(headers)?;
+        }
+        {
+            let mut handler = curl_instance.transfer();
+            handler.write_function(|data| {
+                body.extend_from_slice(data);
+                Ok(data.len())
+            })?;
+            handler.header_function(|header| {
+                if let Some((name, value)) = String::from_utf8_lossy(header).split_once(':') {
+                    let name = name.trim();
+                    if name.eq_ignore_ascii_case("etag") {
+                        validators.etag = Some(value.trim().to_owned());
+                    } else if name.eq_ignore_ascii_case("last-modified") {
+                        validators.last_modified = Some(value.trim().to_owned());
+                    }
+                }
+                true
+            })?;
+            // Returning false aborts the transfer
+            handler.progress_function(|download_total, downloaded, _, _| {
+                progress.transferred(downloaded as u64, download_total as u64);
+                !progress.is_cancelled()
+            })?;
+            handler.perform()?;
+        }
+
+        Ok(Response {
+            status: curl_instance.response_code()?,
+            body,
+            validators,
+        })
+    }
+}
+
+impl CurlTransport {
+    fn get_with_retries(
+        &self,
+        url: &str,
+        cached: Option<&Validators>,
+        progress: &Progress,
+    ) -> Result<Response, ErrorKind> {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            match self.get_once(url, cached, progress) {
+                // Server errors are usually temporary too
+                Ok(ref response) if response.status >= 500 && attempt <= self.retries => {}
+                Ok(response) => return Ok(response),
+                Err(ref err) if err.is_aborted_by_callback() => return Err(ErrorKind::Cancelled),
+                Err(ref err) if is_transient(err) && attempt <= self.retries => {}
+                Err(err) => return Err(ErrorKind::CurlError(err)),
+            };
+            sleep(Duration::from_secs(u64::from(attempt)));
+            progress.check()?;
+        }
+    }
+}
+
+impl Transport for CurlTransport {
+    fn request(
+        &self,
+        url: &str,
+        cached: Option<&Validators>,
+        progress: &Progress,
+    ) -> Result<Response, ErrorKind> {
+        let mut result = self.get_with_retries(url, cached, progress);
+
+        for mirror in &self.mirrors {
+            match result {
+                Ok(ref response) if response.is_success() || response.status == 304 => break,
+                Err(ErrorKind::Cancelled) => break,
+                _ => {}
+            };
+            result = self.get_with_retries(&mirror_url(mirror, url), cached, progress);
+        }
+        result
+    }
+}
+
+/// Serve every request from a local directory, the URL path is used as the file path.
+/// It is meant for testing the update flows and for machines without network access.
+pub struct LocalTransport {
+    root: PathBuf,
+}
+
+impl Transport for LocalTransport {
+    fn request(
+        &self,
+        url: &str,
+        _cached: Option<&Validators>,
+        progress: &Progress,
+    ) -> Result<Response, ErrorKind> {
+        progress.check()?;
+
+        // Only the path of the URL matters, a query can't be part of a file name
+        let path = url_path(url);
+        let path = match path.split_once('?') {
+            Some((path, _)) => path,
+            None => path,
+        };
+        let mut file_path = self.root.clone();
+        file_path.extend(
+            path.split('/')
+                .filter(|part| !part.is_empty() && *part != ".."),
+        );
+
+        match fs::read(&file_path) {
+            Ok(body) => {
+                progress.transferred(body.len() as u64, body.len() as u64);
+                Ok(Response {
+                    status: 200,
+                    body,
+                    validators: Validators::default(),
+                })
+            }
+            Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Response {
+                status: 404,
+                body: Vec::new(),
+                validators: Validators::default(),
+            }),
+            Err(err) => Err(ErrorKind::IOError(err)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, HttpServer, Reply};
+    use std::net::TcpListener;
+
+    fn transport(retries: u32, mirrors: Vec<String>) -> Box<dyn Transport> {
+        from_config(&Config {
+            retries,
+            mirrors,
+            ..testing::config()
+        })
+    }
+
+    #[test]
+    fn retries_server_errors() {
+        let server = HttpServer::start();
+        server.route("list", vec![Reply::status(503), Reply::ok(b"hosts")]);
+
+        let body = transport(1, Vec::new()).download(&server.url("list"));
+        assert_eq!(body.unwrap(), b"hosts");
+        assert_eq!(server.received("list").len(), 2);
+    }
+
+    #[test]
+    fn gives_up_after_the_retries() {
+        let server = HttpServer::start();
+        server.route("list", vec![Reply::status(500)]);
+
+        match transport(1, Vec::new()).download(&server.url("list")) {
+            Err(ErrorKind::HTTPError(500, _)) => {}
+            result => panic!("unexpected result {:?}", result.map(|_| ())),
+        }
+        assert_eq!(server.received("list").len(), 2);
+    }
+
+    #[test]
+    fn client_errors_are_not_retried() {
+        let server = HttpServer::start();
+
+        match transport(2, Vec::new()).download(&server.url("missing")) {
+            Err(ErrorKind::HTTPError(404, _)) => {}
+            result => panic!("unexpected result {:?}", result.map(|_| ())),
+        }
+        assert_eq!(server.received("missing").len(), 1);
+    }
+
+    #[test]
+    fn unreachable_servers_are_errors() {
+        // Nothing listens on the port anymore
+        let address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        match transport(0, Vec::new()).download(&format!("http://{}/list", address)) {
+            Err(ErrorKind::CurlError(err)) => assert!(err.is_couldnt_connect()),
+            result => panic!("unexpected result {:?}", result.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn fails_over_to_the_mirrors() {
+        let server = HttpServer::start();
+        let broken_mirror = HttpServer::start();
+        let mirror = HttpServer::start();
+        server.route("lists/hosts", vec![Reply::status(502)]);
+        broken_mirror.route("lists/hosts", vec![Reply::status(404)]);
+        mirror.route("lists/hosts", vec![Reply::ok(b"mirrored")]);
+
+        let transport = transport(0, vec![broken_mirror.url(""), mirror.url("")]);
+        let body = transport.download(&server.url("lists/hosts"));
+        assert_eq!(body.unwrap(), b"mirrored");
+        assert_eq!(broken_mirror.received("lists/hosts").len(), 1);
+    }
+
+    #[test]
+    fn cached_downloads_are_revalidated() {
+        let server = HttpServer::start();
+        server.route(
+            "latest.json",
+            vec![Reply::ok(b"{}").header("ETag", "\"1\"")],
+        );
+        let transport = transport(0, Vec::new());
+        let url = server.url("latest.json");
+
+        let first = download_cached(transport.as_ref(), &url, &Progress::none());
+        assert_eq!(first.unwrap(), b"{}");
+        // The server answers 304 without a body, it comes from the cache
+        let second = download_cached(transport.as_ref(), &url, &Progress::none());
+        assert_eq!(second.unwrap(), b"{}");
+
+        let received = server.received("latest.json");
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[0].headers.get("if-none-match"), None);
+        assert_eq!(
+            received[1].headers.get("if-none-match").map(String::as_str),
+            Some("\"1\"")
+        );
+    }
+
+    #[test]
+    fn local_transport_reads_the_url_path() {
+        let root = testing::TempDir::new("local-transport");
+        fs::create_dir_all(root.path.join("releases")).unwrap();
+        fs::write(root.path.join("releases").join("latest"), b"release").unwrap();
+        let transport = LocalTransport {
+            root: root.path.clone(),
+        };
+
+        let body = transport.download("https://example.com/releases/latest?page=1");
+        assert_eq!(body.unwrap(), b"release");
+        match transport.download("https://example.com/../releases/missing") {
+            Err(ErrorKind::HTTPError(404, _)) => {}
+            result => panic!("unexpected result {:?}", result.map(|_| ())),
+        }
+    }
+}
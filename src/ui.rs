@@ -1,23 +1,140 @@
+use crate::audit;
+use crate::check;
+use crate::config::Config;
+use crate::doctor;
+use crate::doh;
 use crate::error::ErrorKind;
-use crate::os::{HOSTS_BACKUP_PATH, HOSTS_PATH};
-use crate::parser::{parse_from_file, parse_from_str, write_to_file};
-use crate::{updater, CURRENT_VERSION, HOSTS_BEBASIN, HOSTS_HEADER, REPOSITORY_URL};
+use crate::export;
+use crate::helper::{self, Request};
+use crate::import::{self, Format, Unrepresentable, DEFAULT_TARGET};
+use crate::list;
+use crate::os::is_has_admin_access;
+use crate::parser::{self, parse_from_str_with_progress};
+use crate::policy;
+use crate::progress::Progress;
+use crate::rollback;
+use crate::subscription::{self, SUBSCRIPTIONS_SOURCE};
+use crate::target;
+use crate::transport;
+use crate::verify;
+use crate::{updater, CURRENT_VERSION, REPOSITORY_URL};
 
 use cursive::traits::*;
-use cursive::views::{Button, Dialog, DummyView, LinearLayout, TextView, EditView};
+use cursive::views::{
+    Button, Dialog, DummyView, EditView, LinearLayout, OnEventView, SelectView, TextView,
+};
 use cursive::Cursive;
 
-use crate::helpers::AppendableMap;
-use crate::updater::{backup, is_backed, is_installed};
-use std::fs;
+use crate::updater::is_installed;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// How to get administrator rights, which happens once the menu is closed
+#[derive(Clone, Copy, PartialEq)]
+pub enum Escalation {
+    None,
+    /// Restart the whole application as root
+    Relaunch,
+    /// Start the privileged helper for the hosts file, then come back to the menu
+    StartHelper,
+}
+
+static ESCALATION: Mutex<Escalation> = Mutex::new(Escalation::None);
+
+/// The escalation the user asked for, if any
+pub fn take_escalation() -> Escalation {
+    match ESCALATION.lock() {
+        Ok(mut escalation) => std::mem::replace(&mut *escalation, Escalation::None),
+        Err(_) => Escalation::None,
+    }
+}
+
+fn escalate(cursive: &mut Cursive, escalation: Escalation) {
+    if let Ok(mut requested) = ESCALATION.lock() {
+        *requested = escalation;
+    }
+    cursive.quit();
+}
+
+fn relaunch_as_admin(cursive: &mut Cursive) {
+    escalate(cursive, Escalation::Relaunch);
+}
+
+fn start_helper(cursive: &mut Cursive) {
+    escalate(cursive, Escalation::StartHelper);
+}
+
+/// Whether a change of the hosts file can go on, through the helper if need be.
+/// Otherwise offer to start the helper or to restart with administrator rights.
+fn require_helper(cursive: &mut Cursive) -> bool {
+    if is_has_admin_access() || helper::is_running() {
+        return true;
+    }
+
+    let layout = if cfg!(target_os = "windows") {
+        Dialog::text(
+            "Changing the hosts file needs administrator rights,\n\
+            right-click Bebasin and choose \"Run as administrator\"",
+        )
+    } else {
+        Dialog::text(
+            "Changing the hosts file needs root rights.\n\
+            Start a helper running as root for the hosts file only,\n\
+            or restart Bebasin as root?",
+        )
+        .button("Start the helper", start_helper)
+        .button("Restart as root", relaunch_as_admin)
+    };
+    cursive.add_layer(
+        layout
+            .title("Administrator rights needed")
+            .button("Cancel", |cursive| {
+                cursive.pop_layer();
+            }),
+    );
+    false
+}
+
+/// Whether an operation changing the system can go on,
+/// otherwise offer to restart with administrator rights
+fn require_admin(cursive: &mut Cursive) -> bool {
+    if is_has_admin_access() {
+        return true;
+    }
+
+    let layout = if cfg!(target_os = "windows") {
+        Dialog::text(
+            "This operation needs administrator rights,\n\
+            right-click Bebasin and choose \"Run as administrator\"",
+        )
+    } else {
+        Dialog::text("This operation needs root rights, restart Bebasin as root?")
+            .button("Restart as root", relaunch_as_admin)
+    };
+    cursive.add_layer(
+        layout
+            .title("Administrator rights needed")
+            .button("Cancel", |cursive| {
+                cursive.pop_layer();
+            }),
+    );
+    false
+}
+
+/// How many of the lines left out of an import are listed
+const UNREPRESENTABLE_SHOWN: usize = 10;
+
+const CUSTOM_QUESTION: &str = "Are you sure you want to\n\
+    merge your hosts file with\n\
+    your custom hosts?";
 
 fn clear_layer(cursive: &mut Cursive) {
     while cursive.pop_layer().is_some() {}
 }
 
-fn error(cursive: &mut Cursive, err: ErrorKind) {
-    cursive.pop_layer();
-
+/// Show `err` on top of the current layer
+fn show_error(cursive: &mut Cursive, err: ErrorKind) {
     cursive.add_layer(
         Dialog::text(err.to_string())
             .button("Ok", |cursive| {
@@ -27,118 +144,163 @@ fn error(cursive: &mut Cursive, err: ErrorKind) {
     );
 }
 
-fn install(cursive: &mut Cursive) {
-    let box_layout = Dialog::text("Parsing the file...").title("Loading...");
+/// Show `err` in place of the current layer
+fn error(cursive: &mut Cursive, err: ErrorKind) {
+    cursive.pop_layer();
+    show_error(cursive, err);
+}
 
-    cursive.add_layer(box_layout);
+/// Run `work` on a worker thread behind a dialog showing its progress, which can cancel it.
+/// `done` is then called with the result on the UI thread.
+fn run_in_background<T, W, D>(cursive: &mut Cursive, title: &str, work: W, done: D)
+where
+    T: Send + 'static,
+    W: FnOnce(&Progress) -> Result<T, ErrorKind> + Send + 'static,
+    D: FnOnce(&mut Cursive, T) + Send + 'static,
+{
+    let report_sink = cursive.cb_sink().clone();
+    let progress = Progress::new(move |message| {
+        report_sink
+            .send(Box::new(move |cursive: &mut Cursive| {
+                cursive.call_on_name("progress", |view: &mut TextView| view.set_content(message));
+            }))
+            .ok();
+    });
 
-    if !is_backed() {
-        let backup_result = backup();
-        if backup_result.is_err() {
-            error(cursive, backup_result.err().unwrap());
-            return;
-        }
-    }
+    let cancel_progress = progress.clone();
+    let progress_layer = Dialog::around(TextView::new(title).with_name("progress").min_width(40))
+        .title("Loading...")
+        .button("Cancel", move |cursive| {
+            cancel_progress.cancel();
+            cursive.call_on_name("progress", |view: &mut TextView| {
+                view.set_content("Cancelling...")
+            });
+        });
+    cursive.add_layer(progress_layer);
 
-    match parse_from_str(HOSTS_BEBASIN) {
-        Ok(mut hosts_bebasin) => {
-            match parse_from_file(HOSTS_BACKUP_PATH) {
-                Ok(hosts_backup) => {
-                    hosts_bebasin.append(hosts_backup);
+    let done_sink = cursive.cb_sink().clone();
+    thread::spawn(move || {
+        let result = work(&progress);
+        done_sink
+            .send(Box::new(move |cursive: &mut Cursive| match result {
+                Ok(value) => {
                     cursive.pop_layer();
-
-                    let box_layout = Dialog::text(
-                        "Are you sure you want to\n\
-                    merge your hosts file with\n\
-                    Bebasin hosts?",
-                    )
-                    .title("Confirmation")
-                    .button("Confirm", move |cursive| {
-                        match write_to_file(HOSTS_PATH, &hosts_bebasin, HOSTS_HEADER) {
-                            Err(err) => {
-                                cursive.add_layer(
-                                    Dialog::text(err.to_string()).title("Error").button(
-                                        "Ok",
-                                        |cursive| {
-                                            cursive.pop_layer();
-                                            cursive.pop_layer();
-                                        },
-                                    ),
-                                );
-                            }
-                            _ => {
-                                cursive.add_layer(
-                                    Dialog::text(
-                                        "The hosts file has been updated,\n\
-                        Please restart your machine",
-                                    )
-                                    .title("Done")
-                                    .button("Ok", |cursive| {
-                                        // Re-create the main menu
-                                        clear_layer(cursive);
-                                        main(cursive);
-                                    }),
-                                );
-                            }
-                        };
-                    })
-                    .button("Cancel", |cursive| {
-                        cursive.pop_layer();
-                    });
-
-                    cursive.add_layer(box_layout);
+                    done(cursive, value);
                 }
-                Err(err) => {
-                    error(cursive, err);
+                Err(ErrorKind::Cancelled) => {
+                    cursive.pop_layer();
                 }
+                Err(err) => error(cursive, err),
+            }))
+            .ok();
+    });
+}
+
+/// Flush the DNS caches and verify the hosts file in the background, then tell how it went
+fn finish(cursive: &mut Cursive, summary: String) {
+    run_in_background(
+        cursive,
+        "Flushing the DNS caches",
+        |progress| {
+            let flushed = helper::execute(Request::Reload)
+                .unwrap_or_else(|err| format!("The DNS caches could not be flushed: {}", err));
+            // The hosts file has been written already, the verification can't fail the change
+            let verification = match verify::verify(progress) {
+                Ok(report) => report.describe(),
+                Err(ErrorKind::Cancelled) => String::from("The verification has been cancelled"),
+                Err(err) => format!("The hosts file could not be verified: {}", err),
             };
-        }
-        Err(err) => {
-            error(cursive, err);
-        }
-    };
+            Ok((flushed, verification))
+        },
+        move |cursive, (flushed, verification)| {
+            let layer = Dialog::around(
+                TextView::new(format!("{}\n\n{}\n\n{}", summary, flushed, verification))
+                    .scrollable()
+                    .max_height(20),
+            )
+            .title("Done")
+            .button("Ok", |cursive| {
+                // Re-create the main menu
+                clear_layer(cursive);
+                main(cursive);
+            });
+            cursive.add_layer(layer);
+        },
+    );
 }
 
-fn uninstall_finish(cursive: &mut Cursive) {
-    let layer = Dialog::text(
-        "The hosts file has been updated,\n\
-        Please restart your network/machine",
-    )
-    .title("Done")
-    .button("Ok", |cursive| {
-        cursive.pop_layer();
+/// Ask before `hosts` gets merged with the original hosts file
+fn install_confirm(cursive: &mut Cursive, question: &str, request: Request) {
+    let box_layout = Dialog::text(question)
+        .title("Confirmation")
+        .button("Confirm", move |cursive| {
+            // Close the confirmation
+            cursive.pop_layer();
+            let request = request.clone();
+            run_in_background(
+                cursive,
+                "Installing Bebasin",
+                move |_| helper::execute(request),
+                finish,
+            );
+        })
+        .button("Cancel", |cursive| {
+            cursive.pop_layer();
+        });
 
-        // Re-create the main menu
-        clear_layer(cursive);
-        main(cursive);
-    });
+    cursive.add_layer(box_layout);
+}
 
-    cursive.add_layer(layer);
+fn install(cursive: &mut Cursive) {
+    if !require_helper(cursive) {
+        return;
+    }
+    run_in_background(
+        cursive,
+        "Parsing the file...",
+        |progress| {
+            progress.step("Parsing the hosts list");
+            // The helper reads the list again, this only catches a broken one before asking
+            parse_from_str_with_progress(&list::current(), progress)?;
+            Ok(())
+        },
+        |cursive, ()| {
+            install_confirm(
+                cursive,
+                "Are you sure you want to\n\
+                merge your hosts file with\n\
+                Bebasin hosts?",
+                Request::Install,
+            )
+        },
+    );
 }
 
 fn uninstall(cursive: &mut Cursive) {
+    if !require_helper(cursive) {
+        return;
+    }
     let box_layout = Dialog::text(
         "Are you sure you want to\n\
         uninstall Bebasin hosts?",
     )
     .title("Confirmation")
     .button("Confirm", move |cursive| {
+        // Close the confirmation
+        cursive.pop_layer();
         // 1. Copy the backup to the real hosts
         // 2. Delete the backup
         // 3, Remove all temporary file
-        match fs::copy(HOSTS_BACKUP_PATH, HOSTS_PATH) {
-            Ok(_) => {
+        run_in_background(
+            cursive,
+            "Uninstalling Bebasin",
+            |_| {
+                let summary = helper::execute(Request::Uninstall)?;
                 updater::remove_temp_file();
-
-                match fs::remove_file(HOSTS_BACKUP_PATH) {
-                    Err(err) => return error(cursive, ErrorKind::IOError(err)),
-                    _ => {}
-                };
-
-                uninstall_finish(cursive);
-            }
-            Err(err) => error(cursive, ErrorKind::IOError(err)),
-        };
+                Ok(summary)
+            },
+            finish,
+        );
     })
     .button("Cancel", |cursive| {
         cursive.pop_layer();
@@ -149,128 +311,631 @@ fn uninstall(cursive: &mut Cursive) {
 
 fn open_browser(cursive: &mut Cursive, url: &str) {
     if webbrowser::open(url).is_err() {
-        let layout = Dialog::text("Can't open any browser")
-            .title("Error")
-            .button("Ok", |cursive| {
-                cursive.pop_layer();
-            });
-
-        cursive.add_layer(layout);
+        show_error(
+            cursive,
+            ErrorKind::String(String::from("Can't open any browser")),
+        );
     }
 }
 
-
 fn install_custom_ui(cursive: &mut Cursive) {
+    if !require_helper(cursive) {
+        return;
+    }
+    let mut formats = SelectView::new().popup();
+    formats.add_item("Detect automatically", None);
+    for format in Format::ALL.iter() {
+        formats.add_item(format.as_str(), Some(*format));
+    }
     let box_layout = Dialog::new()
-    .title("Your custom hosts path")
-    .content(
-        EditView::new()
-            .on_submit(install_custom)
-            .with_name("custom_hosts")
-            .fixed_width(20),
+        .title("Import a custom hosts list")
+        .content(
+            LinearLayout::vertical()
+                .child(TextView::new("Path or URL"))
+                .child(
+                    EditView::new()
+                        .on_submit(|cursive, _| install_custom(cursive))
+                        .with_name("custom_hosts")
+                        .fixed_width(40),
+                )
+                .child(TextView::new("Format"))
+                .child(formats.with_name("custom_format"))
+                .child(TextView::new("Address of the blocked domains"))
+                .child(
+                    EditView::new()
+                        .content(DEFAULT_TARGET)
+                        .with_name("custom_target")
+                        .fixed_width(40),
+                ),
+        )
+        .button("Ok", install_custom)
+        .button("Cancel", |cursive| {
+            cursive.pop_layer();
+        });
+    cursive.add_layer(box_layout);
+}
+
+/// Describe the lines left out of an import, the first ones are enough to tell why
+fn describe_unrepresentable(unrepresentable: &[Unrepresentable]) -> String {
+    let mut description = format!(
+        "{} lines have no hosts equivalent and are left out:\n",
+        unrepresentable.len()
+    );
+    for line in unrepresentable.iter().take(UNREPRESENTABLE_SHOWN) {
+        description.push_str(&format!("{}\n", line.describe()));
+    }
+    if unrepresentable.len() > UNREPRESENTABLE_SHOWN {
+        description.push_str(&format!(
+            "and {} more\n",
+            unrepresentable.len() - UNREPRESENTABLE_SHOWN
+        ));
+    }
+    description
+}
+
+fn install_custom(cursive: &mut Cursive) {
+    let (path, format, target) = match (
+        cursive.call_on_name("custom_hosts", |view: &mut EditView| view.get_content()),
+        cursive.call_on_name("custom_format", |view: &mut SelectView<Option<Format>>| {
+            view.selection()
+        }),
+        cursive.call_on_name("custom_target", |view: &mut EditView| view.get_content()),
+    ) {
+        (Some(path), Some(format), Some(target)) => (
+            path.trim().to_owned(),
+            format.and_then(|format| *format),
+            target.trim().to_owned(),
+        ),
+        _ => return,
+    };
+    run_in_background(
+        cursive,
+        "Parsing the file...",
+        move |progress| {
+            let config = Config::load()?;
+            progress.step(&format!("Reading {}", path));
+            let transport = transport::from_config(&config);
+            let content = list::read(transport.as_ref(), &path, progress)?;
+            progress.step(&format!("Parsing {}", path));
+            let format = format.unwrap_or_else(|| Format::detect(&content));
+            let imported = import::import(&content, format, &target, progress)?;
+            if imported.hosts.is_empty() {
+                return Err(ErrorKind::String(format!(
+                    "{} contains no entry as a {} list",
+                    path,
+                    format.as_str()
+                )));
+            }
+
+            // The privileged side refuses them anyway
+            let violations = policy::check(&imported.hosts, &config);
+            let hosts_custom = parser::to_string(&policy::without(imported.hosts, &violations), "");
+            let question = if imported.unrepresentable.is_empty() {
+                String::from(CUSTOM_QUESTION)
+            } else {
+                format!(
+                    "{}\n{}",
+                    describe_unrepresentable(&imported.unrepresentable),
+                    CUSTOM_QUESTION
+                )
+            };
+            Ok((question, hosts_custom, violations, path))
+        },
+        |cursive, (question, hosts_custom, violations, source)| {
+            // Close the path dialog
+            cursive.pop_layer();
+            if violations.is_empty() {
+                install_confirm(
+                    cursive,
+                    &question,
+                    Request::CustomImport {
+                        source,
+                        hosts: hosts_custom,
+                    },
+                );
+            } else {
+                policy_confirm(cursive, question, hosts_custom, violations, source);
+            }
+        },
+    );
+}
+
+/// Tell which entries redirect protected hostnames, the install goes on without them
+fn policy_confirm(
+    cursive: &mut Cursive,
+    question: String,
+    hosts_custom: String,
+    violations: Vec<policy::Violation>,
+    source: String,
+) {
+    let layout = Dialog::around(
+        LinearLayout::vertical()
+            .child(TextView::new(format!(
+                "{} of the custom hosts could redirect sensitive hostnames:",
+                violations.len()
+            )))
+            .child(DummyView)
+            .child(
+                TextView::new(
+                    violations
+                        .iter()
+                        .map(policy::Violation::describe)
+                        .collect::<Vec<_>>()
+                        .join("\n"),
+                )
+                .scrollable()
+                .max_height(15),
+            )
+            .child(DummyView)
+            .child(TextView::new(
+                "They can't be installed, install the others without them?",
+            )),
     )
-    .button("Ok", |x|{
-        let custom_hosts = x
-            .call_on_name("custom_hosts", |view: &mut EditView| {
-                view.get_content()
-            })
-            .unwrap();
-        install_custom(x, custom_hosts.as_str());
+    .title("Protected hostnames")
+    .button("Leave them out", move |cursive| {
+        cursive.pop_layer();
+        install_confirm(
+            cursive,
+            &question,
+            Request::CustomImport {
+                source: source.clone(),
+                hosts: hosts_custom.clone(),
+            },
+        );
+    })
+    .button("Cancel", |cursive| {
+        cursive.pop_layer();
     });
-    cursive.add_layer(box_layout);
+    cursive.add_layer(layout);
+}
+
+fn update_list(cursive: &mut Cursive) {
+    run_in_background(
+        cursive,
+        "Retrieving the latest hosts list",
+        |progress| {
+            let config = Config::load()?;
+            let fetched = match list::fetch(&config, progress) {
+                Ok(fetched) => fetched,
+                Err(ErrorKind::Cancelled) => return Err(ErrorKind::Cancelled),
+                Err(err) => {
+                    return Err(ErrorKind::String(format!(
+                        "{}\n\nThe hosts list from {} is still in use",
+                        err,
+                        list::current_source()
+                    )))
+                }
+            };
+            let changes = list::changes(&fetched)?;
+            Ok((fetched, changes))
+        },
+        |cursive, (fetched, changes)| update_list_confirm(cursive, fetched, changes),
+    );
 }
 
-fn install_custom(cursive: &mut Cursive, path: &str) {
-    use std::io::Read;
-    let mut f = fs::File::open(path).expect("Unable to open file");
-    let mut contents = String::new();
-    f.read_to_string(&mut contents).expect("Error");
-    let hosts_custom = contents.as_str();
+fn update_list_confirm(cursive: &mut Cursive, fetched: list::Fetched, changes: list::Changes) {
+    if changes.is_empty() {
+        let warning_layer = Dialog::text("You have been using the latest hosts list")
+            .button("Ok", |cursive| {
+                cursive.pop_layer();
+            })
+            .title("Warning");
+        cursive.add_layer(warning_layer);
+        return;
+    }
+
+    let current_version = list::installed_info()
+        .and_then(|info| info.version)
+        .unwrap_or_else(|| String::from("embedded"));
+    let latest_version = fetched
+        .info
+        .version
+        .clone()
+        .unwrap_or_else(|| String::from("unknown"));
 
-    if !is_backed() {
-        let backup_result = backup();
-        if backup_result.is_err() {
-            error(cursive, backup_result.err().unwrap());
+    let confirmation_layer = Dialog::around(
+        LinearLayout::vertical()
+            .child(TextView::new(format!(
+                "Update the hosts list from version {} to {}?",
+                current_version, latest_version
+            )))
+            .child(DummyView)
+            .child(
+                TextView::new(changes.describe())
+                    .scrollable()
+                    .max_height(15),
+            ),
+    )
+    .title("Confirmation")
+    .button("Cancel", |cursive| {
+        cursive.pop_layer();
+    })
+    .button("Apply", move |cursive| {
+        if !require_helper(cursive) {
             return;
         }
+        // Close the confirmation
+        cursive.pop_layer();
+        let request = Request::ApplyList {
+            fetched: fetched.clone(),
+        };
+        run_in_background(
+            cursive,
+            "Updating the hosts list",
+            move |_| helper::execute(request),
+            |cursive, summary| {
+                // Only the cached list changed when Bebasin isn't installed
+                if is_installed() {
+                    return finish(cursive, summary);
+                }
+                let updated_layer = Dialog::text("The hosts list has been updated")
+                    .title("Done")
+                    .button("Ok", |cursive| {
+                        clear_layer(cursive);
+                        main(cursive);
+                    });
+                cursive.add_layer(updated_layer);
+            },
+        );
+    });
+    cursive.add_layer(confirmation_layer);
+}
+
+fn export_log_ui(cursive: &mut Cursive) {
+    let box_layout = Dialog::new()
+        .title("Export the log to")
+        .content(
+            EditView::new()
+                .on_submit(export_log)
+                .with_name("export_path")
+                .fixed_width(20),
+        )
+        .button("Ok", |cursive| {
+            let export_path = cursive
+                .call_on_name("export_path", |view: &mut EditView| view.get_content())
+                .unwrap();
+            export_log(cursive, export_path.as_str());
+        });
+    cursive.add_layer(box_layout);
+}
+
+fn export_log(cursive: &mut Cursive, path: &str) {
+    match audit::export_to_file(path) {
+        Ok(_) => {
+            cursive.pop_layer();
+            cursive.add_layer(
+                Dialog::text(format!("The log has been exported to {}", path))
+                    .title("Done")
+                    .button("Ok", |cursive| {
+                        cursive.pop_layer();
+                    }),
+            );
+        }
+        Err(err) => error(cursive, err),
     }
-    match parse_from_str(hosts_custom) {
-        Ok(mut hosts_custom) => {
-            match parse_from_file(HOSTS_BACKUP_PATH) {
-                Ok(hosts_backup) => {
-                    hosts_custom.append(hosts_backup);
+}
+
+fn export_sections(cursive: &mut Cursive) {
+    match export::section_names() {
+        Ok(names) => cursive.add_layer(
+            Dialog::around(TextView::new(names.join("\n")).scrollable().max_height(20))
+                .title("Sections")
+                .button("Back", |cursive| {
                     cursive.pop_layer();
+                }),
+        ),
+        Err(err) => show_error(cursive, err),
+    }
+}
 
-                    let box_layout = Dialog::text(
-                        "Are you sure you want to\n\
-                    merge your hosts file with\n\
-                    your custom hosts?",
-                    )
-                        .title("Confirmation")
-                        .button("Confirm", move |cursive| {
-                            match write_to_file(HOSTS_PATH, &hosts_custom, HOSTS_HEADER) {
-                                Err(err) => {
-                                    cursive.add_layer(
-                                        Dialog::text(err.to_string()).title("Error").button(
-                                            "Ok",
-                                            |cursive| {
-                                                cursive.pop_layer();
-                                                cursive.pop_layer();
-                                            },
-                                        ),
-                                    );
-                                }
-                                _ => {
-                                    cursive.add_layer(
-                                        Dialog::text(
-                                            "The hosts file has been updated,\n\
-                        Please restart your machine",
-                                        )
-                                            .title("Done")
-                                            .button("Ok", |cursive| {
-                                                // Re-create the main menu
-                                                clear_layer(cursive);
-                                                main(cursive);
-                                            }),
-                                    );
-                                }
-                            };
-                        })
-                        .button("Cancel", |cursive| {
-                            cursive.pop_layer();
-                        });
+fn export(cursive: &mut Cursive) {
+    let (format, sections, path) = match (
+        cursive.call_on_name("export_format", |view: &mut SelectView<export::Format>| {
+            view.selection()
+        }),
+        cursive.call_on_name("export_sections", |view: &mut EditView| view.get_content()),
+        cursive.call_on_name("export_output", |view: &mut EditView| view.get_content()),
+    ) {
+        (Some(Some(format)), Some(sections), Some(path)) => (*format, sections, path),
+        _ => return,
+    };
+    // Section names may contain spaces, not commas
+    let sections = sections
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(str::to_owned)
+        .collect::<Vec<_>>();
 
-                    cursive.add_layer(box_layout);
-                }
-                Err(err) => {
-                    error(cursive, err);
-                }
-            };
-        }
-        Err(err) => {
-            error(cursive, err);
+    match export::mapping(&sections)
+        .and_then(|hosts| export::export_to_file(path.trim(), &hosts, format))
+    {
+        Ok(_) => {
+            cursive.pop_layer();
+            cursive.add_layer(
+                Dialog::text(format!(
+                    "The {} configuration has been exported to {}",
+                    format.as_str(),
+                    path.trim()
+                ))
+                .title("Done")
+                .button("Ok", |cursive| {
+                    cursive.pop_layer();
+                }),
+            );
         }
+        Err(err) => error(cursive, err),
+    }
+}
+
+fn export_ui(cursive: &mut Cursive) {
+    let mut formats = SelectView::new().popup();
+    for format in export::Format::ALL.iter() {
+        formats.add_item(format.as_str(), *format);
+    }
+    let layout = Dialog::around(
+        LinearLayout::vertical()
+            .child(TextView::new("Format"))
+            .child(formats.with_name("export_format"))
+            .child(TextView::new(
+                "Sections, separated by commas, all when empty",
+            ))
+            .child(EditView::new().with_name("export_sections").fixed_width(40))
+            .child(TextView::new("Export to"))
+            .child(
+                EditView::new()
+                    .on_submit(|cursive, _| export(cursive))
+                    .with_name("export_output")
+                    .fixed_width(40),
+            ),
+    )
+    .title("Export for a DNS server")
+    .button("Export", export)
+    .button("Sections", export_sections)
+    .button("Cancel", |cursive| {
+        cursive.pop_layer();
+    });
+    cursive.add_layer(layout);
+}
+
+fn log(cursive: &mut Cursive) {
+    let entries = match audit::entries() {
+        Ok(entries) => entries,
+        Err(err) => return show_error(cursive, err),
     };
+
+    let content = if entries.is_empty() {
+        String::from("The hosts file has not been changed by Bebasin yet")
+    } else {
+        entries
+            .iter()
+            .rev()
+            .map(audit::Entry::describe)
+            .collect::<Vec<String>>()
+            .join("\n")
+    };
+
+    let layout = Dialog::around(TextView::new(content).scrollable().max_height(20))
+        .title("Log")
+        .button("Export", export_log_ui)
+        .button("Back", |cursive| {
+            cursive.pop_layer();
+        });
+
+    cursive.add_layer(layout);
 }
 
-fn update(cursive: &mut Cursive) {
-    let mut updater_instance = updater::Updater::new();
+/// Show the subscriptions screen again, once it changed
+fn reopen_subscriptions(cursive: &mut Cursive) {
+    cursive.pop_layer();
+    subscriptions(cursive);
+}
 
-    let loading_layer =
-        Dialog::text("Retrieving latest application information").title("Loading...");
-    cursive.add_layer(loading_layer);
+/// Tell how changing the subscriptions went. The hosts file is only rewritten when
+/// Bebasin is installed, the DNS caches are flushed then.
+fn subscriptions_done(cursive: &mut Cursive, summary: String, is_rewritten: bool) {
+    if is_rewritten {
+        finish(cursive, summary);
+        return;
+    }
+    reopen_subscriptions(cursive);
+    cursive.add_layer(
+        Dialog::around(TextView::new(summary).scrollable().max_height(20))
+            .title("Done")
+            .button("Ok", |cursive| {
+                cursive.pop_layer();
+            }),
+    );
+}
 
-    let latest = match updater_instance.get_latest_info() {
-        Ok(latest) => latest,
-        Err(err) => {
-            return {
-                error(cursive, err);
-            };
-        }
+fn subscriptions_refresh(cursive: &mut Cursive) {
+    if !require_admin(cursive) {
+        return;
+    }
+    run_in_background(
+        cursive,
+        "Downloading the subscriptions",
+        |progress| {
+            let refreshed = subscription::refresh(&Config::load()?, progress)?;
+            let is_rewritten = refreshed.is_changed && is_installed();
+            if is_rewritten {
+                list::reapply(SUBSCRIPTIONS_SOURCE)?;
+            }
+            Ok((refreshed.describe(), is_rewritten))
+        },
+        |cursive, (summary, is_rewritten)| subscriptions_done(cursive, summary, is_rewritten),
+    );
+}
+
+fn subscription_add(cursive: &mut Cursive) {
+    let (name, location) = match (
+        cursive.call_on_name("subscription_name", |view: &mut EditView| {
+            view.get_content()
+        }),
+        cursive.call_on_name("subscription_location", |view: &mut EditView| {
+            view.get_content()
+        }),
+    ) {
+        (Some(name), Some(location)) => (name, location),
+        _ => return,
     };
+    let added =
+        Config::load().and_then(|mut config| subscription::add(&mut config, &name, &location));
+    match added {
+        Ok(_) => {
+            // Close the form
+            cursive.pop_layer();
+            subscriptions_refresh(cursive);
+        }
+        Err(err) => error(cursive, err),
+    }
+}
 
+fn subscription_add_ui(cursive: &mut Cursive) {
+    if !require_admin(cursive) {
+        return;
+    }
+    let layout = Dialog::around(
+        LinearLayout::vertical()
+            .child(TextView::new("Name"))
+            .child(
+                EditView::new()
+                    .with_name("subscription_name")
+                    .fixed_width(40),
+            )
+            .child(TextView::new("URL or path"))
+            .child(
+                EditView::new()
+                    .on_submit(|cursive, _| subscription_add(cursive))
+                    .with_name("subscription_location")
+                    .fixed_width(40),
+            ),
+    )
+    .title("Add a subscription")
+    .button("Ok", subscription_add)
+    .button("Cancel", |cursive| {
+        cursive.pop_layer();
+    });
+    cursive.add_layer(layout);
+}
+
+fn subscription_remove(cursive: &mut Cursive, name: &str) {
+    if !require_admin(cursive) {
+        return;
+    }
+    // Close the confirmation
     cursive.pop_layer();
+    let name = name.to_owned();
+    run_in_background(
+        cursive,
+        "Unsubscribing",
+        move |progress| {
+            let had_entries = subscription::remove(&mut Config::load()?, &name)?;
+            let is_rewritten = had_entries && is_installed();
+            if is_rewritten {
+                progress.step("Rewriting the hosts");
+                list::reapply(SUBSCRIPTIONS_SOURCE)?;
+            }
+            Ok((name, is_rewritten))
+        },
+        |cursive, (name, is_rewritten)| {
+            subscriptions_done(cursive, format!("Unsubscribed from {}", name), is_rewritten)
+        },
+    );
+}
+
+fn subscription_remove_confirm(cursive: &mut Cursive, name: &str) {
+    let name = name.to_owned();
+    let layout = Dialog::text(format!("Unsubscribe from {}?", name))
+        .title("Confirmation")
+        .button("No", |cursive| {
+            cursive.pop_layer();
+        })
+        .button("Yes", move |cursive| subscription_remove(cursive, &name));
+    cursive.add_layer(layout);
+}
+
+fn subscriptions(cursive: &mut Cursive) {
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(err) => return show_error(cursive, err),
+    };
 
+    let content = if config.subscriptions.is_empty() {
+        LinearLayout::vertical().child(TextView::new(
+            "There is no subscription yet.\n\
+            Custom hosts lists added here are merged with the Bebasin one",
+        ))
+    } else {
+        let statuses = subscription::statuses();
+        let mut names = SelectView::new();
+        for subscription in config.subscriptions {
+            let status = statuses
+                .get(&subscription.name)
+                .cloned()
+                .unwrap_or_default();
+            names.add_item(
+                format!(
+                    "{} ({}): {}",
+                    subscription.name,
+                    subscription.location,
+                    status.describe().replace('\n', ", ")
+                ),
+                subscription.name,
+            );
+        }
+        LinearLayout::vertical()
+            .child(
+                names
+                    .on_submit(|cursive, name: &String| subscription_remove_confirm(cursive, name))
+                    .scrollable()
+                    .max_height(15),
+            )
+            .child(DummyView)
+            .child(TextView::new("Select a subscription to remove it"))
+    };
+
+    let layout = Dialog::around(content)
+        .title("Subscriptions")
+        .button("Add", subscription_add_ui)
+        .button("Refresh", subscriptions_refresh)
+        .button("Back", |cursive| {
+            cursive.pop_layer();
+        });
+    cursive.add_layer(layout);
+}
+
+fn update(cursive: &mut Cursive) {
+    run_in_background(
+        cursive,
+        "Retrieving latest application information",
+        |progress| {
+            let mut updater_instance = updater::Updater::new();
+            let latest = updater_instance.get_latest_info()?;
+            if !updater_instance.is_updatable() {
+                return Ok((updater_instance, latest, String::new()));
+            }
+
+            progress.check()?;
+            progress.step("Retrieving the release notes");
+            // The release notes are nice to have, they shouldn't prevent the update
+            let changelog = updater_instance
+                .changelog()
+                .unwrap_or_else(|err| format!("The release notes could not be retrieved: {}", err));
+            Ok((updater_instance, latest, changelog))
+        },
+        |cursive, (updater_instance, latest, changelog)| {
+            update_confirm(cursive, updater_instance, latest, changelog)
+        },
+    );
+}
+
+fn update_confirm(
+    cursive: &mut Cursive,
+    updater_instance: updater::Updater,
+    latest: updater::ChannelRelease,
+    changelog: String,
+) {
     if !updater_instance.is_updatable() {
         let warning_layer = Dialog::text("You have been using the latest update application")
             .button("Ok", |cursive| {
@@ -281,30 +946,377 @@ fn update(cursive: &mut Cursive) {
         return;
     }
 
-    let confirmation_layer = Dialog::text(format!(
+    let mut message = format!(
         "Are you sure you want to update to version {}?",
         latest.version
+    );
+    if latest.critical {
+        message = format!(
+            "Version {} is a critical update, it is strongly recommended to install it.\n\n{}",
+            latest.version, message
+        );
+    }
+    if !updater_instance.is_supported() {
+        message = format!(
+            "Version {} is no longer supported, please update.\n\n{}",
+            CURRENT_VERSION, message
+        );
+    }
+
+    let updater_instance = Arc::new(updater_instance);
+    let confirmation_layer = Dialog::around(
+        LinearLayout::vertical()
+            .child(TextView::new(message))
+            .child(DummyView)
+            .child(TextView::new(changelog).scrollable().max_height(15)),
+    )
+    .title(if latest.critical {
+        "Critical Update"
+    } else {
+        "Confirmation"
+    })
+    .button("No", |cursive| {
+        cursive.pop_layer();
+    })
+    .button("Yes", move |cursive| {
+        if !require_admin(cursive) {
+            return;
+        }
+        cursive.pop_layer();
+        let updater_instance = Arc::clone(&updater_instance);
+        run_in_background(
+            cursive,
+            "Updating the application",
+            move |progress| updater_instance.update(progress),
+            |cursive, _| {
+                let updated_layer =
+                    Dialog::text("The application has been updated, please re-run the application")
+                        .button("Ok", |cursive| {
+                            cursive.quit();
+                        });
+                cursive.add_layer(updated_layer);
+            },
+        );
+    });
+    cursive.add_layer(confirmation_layer);
+}
+
+fn rollback_confirm(cursive: &mut Cursive, version: &str) {
+    let version = version.to_owned();
+    let confirmation_layer = Dialog::text(format!(
+        "Are you sure you want to roll back to version {}?",
+        version
     ))
     .title("Confirmation")
     .button("No", |cursive| {
         cursive.pop_layer();
     })
-    .button("Yes", move |cursive| match updater_instance.update() {
-        Ok(_) => {
-            let updated_layer =
-                Dialog::text("The application has been updated, please re-run the application")
-                    .button("Ok", |cursive| {
-                        cursive.quit();
-                    });
-            cursive.add_layer(updated_layer);
+    .button("Yes", move |cursive| {
+        if !require_admin(cursive) {
+            return;
         }
-        Err(err) => error(cursive, err),
+        // Close the confirmation
+        cursive.pop_layer();
+        let version = version.clone();
+        run_in_background(
+            cursive,
+            "Rolling back the application",
+            move |_| {
+                let keep_binaries = Config::load().unwrap_or_default().keep_binaries;
+                rollback::restore(&version, keep_binaries)
+            },
+            |cursive, _| {
+                let rolled_back_layer = Dialog::text(
+                    "The application has been rolled back, please re-run the application",
+                )
+                .button("Ok", |cursive| {
+                    cursive.quit();
+                });
+                cursive.add_layer(rolled_back_layer);
+            },
+        );
     });
     cursive.add_layer(confirmation_layer);
 }
 
+fn rollback(cursive: &mut Cursive) {
+    let kept = match rollback::kept() {
+        Ok(kept) => kept,
+        Err(err) => return show_error(cursive, err),
+    };
+
+    if kept.is_empty() {
+        let warning_layer = Dialog::text("There is no previous version to roll back to")
+            .button("Ok", |cursive| {
+                cursive.pop_layer();
+            })
+            .title("Warning");
+        cursive.add_layer(warning_layer);
+        return;
+    }
+
+    let mut versions = SelectView::new();
+    for kept in kept {
+        versions.add_item(kept.version.clone(), kept.version);
+    }
+
+    let layout = Dialog::around(
+        versions
+            .on_submit(|cursive, version: &String| rollback_confirm(cursive, version))
+            .scrollable()
+            .max_height(10),
+    )
+    .title("Roll back to")
+    .button("Back", |cursive| {
+        cursive.pop_layer();
+    });
+    cursive.add_layer(layout);
+}
+
+/// What the startup checks found, kept as the user data to be shown by every main menu
+#[derive(Default)]
+struct Notices {
+    status: Option<check::Status>,
+    doh: Vec<doh::Finding>,
+}
+
+fn banner(notices: &Notices) -> String {
+    let mut lines = Vec::new();
+    if let Some(status) = &notices.status {
+        if let Some(minimum_supported_version) = status.unsupported() {
+            lines.push(format!(
+                "This version is no longer supported, {} or newer is required, press u to update",
+                minimum_supported_version
+            ));
+        }
+        match status.application_update() {
+            Some(version) if status.is_critical() => lines.push(format!(
+                "Version {} is a critical update, press u to install it now",
+                version
+            )),
+            Some(version) => lines.push(format!(
+                "Version {} is available, press u to update",
+                version
+            )),
+            None => {}
+        }
+        if status.hosts_list_update() {
+            lines.push(String::from(
+                "A new hosts list is available, press l to update it",
+            ));
+        }
+    }
+    if !notices.doh.is_empty() {
+        lines.push(String::from(
+            "A browser bypasses the hosts file with DNS over HTTPS, press d for details",
+        ));
+    }
+    lines.join("\n")
+}
+
+fn set_notices(cursive: &mut Cursive, notices: Notices) {
+    let content = banner(&notices);
+    cursive.set_user_data(notices);
+    cursive.call_on_name("banner", |view: &mut TextView| view.set_content(content));
+}
+
+/// Check for updates and for browsers bypassing the hosts file in the background,
+/// the main menu shows a banner when there is something to do
+pub fn run_startup_checks(cursive: &mut Cursive) {
+    let sink = cursive.cb_sink().clone();
+    thread::spawn(move || {
+        let notices = Notices {
+            doh: doh::scan(Path::new(doh::SYSTEM_ROOT)),
+            status: check::run(),
+        };
+        sink.send(Box::new(move |cursive: &mut Cursive| {
+            set_notices(cursive, notices)
+        }))
+        .ok();
+    });
+}
+
+/// Show the main menu again after the helper has been started, or failed to
+pub fn resume(cursive: &mut Cursive, started: Result<(), ErrorKind>) {
+    main(cursive);
+    run_startup_checks(cursive);
+    if let Err(err) = started {
+        show_error(cursive, err);
+    }
+}
+
+fn doh_disable(cursive: &mut Cursive) {
+    if !require_admin(cursive) {
+        return;
+    }
+    let root = Path::new(doh::SYSTEM_ROOT);
+    match doh::write_policies(root, &doh::scan(root)) {
+        Ok(written) => {
+            let status = cursive
+                .take_user_data::<Notices>()
+                .and_then(|notices| notices.status);
+            set_notices(
+                cursive,
+                Notices {
+                    status,
+                    doh: doh::scan(root),
+                },
+            );
+
+            let written = written
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>()
+                .join("\n");
+            cursive.pop_layer();
+            cursive.add_layer(
+                Dialog::text(format!(
+                    "The policies have been written to\n{}\n\n\
+                    Restart the browsers to apply them",
+                    written
+                ))
+                .title("Done")
+                .button("Ok", |cursive| {
+                    cursive.pop_layer();
+                }),
+            );
+        }
+        Err(err) => error(cursive, err),
+    }
+}
+
+fn doh(cursive: &mut Cursive) {
+    let findings = doh::scan(Path::new(doh::SYSTEM_ROOT));
+    if findings.is_empty() {
+        cursive.add_layer(
+            Dialog::text("No browser uses DNS over HTTPS")
+                .title("DNS over HTTPS")
+                .button("Ok", |cursive| {
+                    cursive.pop_layer();
+                }),
+        );
+        return;
+    }
+
+    let layout = Dialog::around(
+        TextView::new(format!(
+            "These browsers resolve hostnames through DNS over HTTPS,\n\
+            which bypasses the hosts file:\n{}\n\n\
+            Do you want to turn it off with browser policies?",
+            findings
+                .iter()
+                .map(doh::Finding::describe)
+                .collect::<Vec<_>>()
+                .join("\n")
+        ))
+        .scrollable()
+        .max_height(20),
+    )
+    .title("DNS over HTTPS")
+    .button("Yes", doh_disable)
+    .button("No", |cursive| {
+        cursive.pop_layer();
+    });
+    cursive.add_layer(layout);
+}
+
+fn select_target(cursive: &mut Cursive, kind: target::Kind) {
+    if !require_admin(cursive) {
+        return;
+    }
+    match target::select(kind) {
+        Ok(_) => {
+            // Re-create the main menu, what can be done depends on the target
+            clear_layer(cursive);
+            main(cursive);
+            cursive.add_layer(
+                Dialog::text(format!(
+                    "The hosts list will be installed to {}",
+                    target::from_kind(kind).path().display()
+                ))
+                .title("Done")
+                .button("Ok", |cursive| {
+                    cursive.pop_layer();
+                }),
+            );
+        }
+        Err(err) => show_error(cursive, err),
+    }
+}
+
+fn targets(cursive: &mut Cursive) {
+    let configured = target::configured().kind();
+    let mut kinds = SelectView::new();
+    for kind in target::Kind::ALL.iter().filter(|kind| kind.is_available()) {
+        let marker = if *kind == configured { "*" } else { " " };
+        kinds.add_item(
+            format!("{} {}", marker, target::from_kind(*kind).status()),
+            *kind,
+        );
+    }
+
+    let layout = Dialog::around(
+        LinearLayout::vertical()
+            .child(TextView::new(
+                "Where should the hosts list be installed?\n\
+                Bebasin has to be uninstalled before changing it",
+            ))
+            .child(DummyView)
+            .child(kinds.on_submit(|cursive, kind: &target::Kind| select_target(cursive, *kind))),
+    )
+    .title("Install target")
+    .button("Back", |cursive| {
+        cursive.pop_layer();
+    });
+    cursive.add_layer(layout);
+}
+
+fn doctor(cursive: &mut Cursive) {
+    run_in_background(
+        cursive,
+        "Looking for problems",
+        |_| Ok(doctor::run()),
+        |cursive, report| {
+            let layout =
+                Dialog::around(TextView::new(report.describe()).scrollable().max_height(20))
+                    .title("Doctor")
+                    .button("Back", |cursive| {
+                        cursive.pop_layer();
+                    });
+            cursive.add_layer(layout);
+        },
+    );
+}
+
 pub fn main(cursive: &mut Cursive) {
     let text_header = TextView::new(format!("Bebasin version {}", CURRENT_VERSION));
+    let text_banner = TextView::new(
+        cursive
+            .user_data::<Notices>()
+            .map(|notices| banner(notices))
+            .unwrap_or_default(),
+    )
+    .with_name("banner");
+    // Browsing, diffing and exporting work without administrator rights
+    let text_privileges = if is_has_admin_access() {
+        String::new()
+    } else if cfg!(target_os = "windows") {
+        String::from(
+            "Without administrator rights, installing, updating and rolling back are unavailable.\n\
+            Right-click Bebasin and choose \"Run as administrator\" to use them",
+        )
+    } else if helper::is_running() {
+        String::from(
+            "The hosts file is changed by the helper running as root.\n\
+            Updating and rolling back need Bebasin to run as root, press r to restart it",
+        )
+    } else {
+        String::from(
+            "Without root rights, installing, updating and rolling back are unavailable.\n\
+            Press h to start a helper changing the hosts file as root,\n\
+            or r to restart Bebasin as root",
+        )
+    };
     let mut menu_buttons = LinearLayout::vertical();
 
     if is_installed() {
@@ -316,6 +1328,13 @@ pub fn main(cursive: &mut Cursive) {
 
     menu_buttons = menu_buttons
         .child(Button::new("Update", update))
+        .child(Button::new("Update Hosts List", update_list))
+        .child(Button::new("Subscriptions", subscriptions))
+        .child(Button::new("Export", export_ui))
+        .child(Button::new("Install Target", targets))
+        .child(Button::new("Rollback", rollback))
+        .child(Button::new("Log", log))
+        .child(Button::new("Doctor", doctor))
         .child(Button::new("Repository", |cursive| {
             open_browser(cursive, REPOSITORY_URL);
         }))
@@ -329,10 +1348,27 @@ pub fn main(cursive: &mut Cursive) {
     let layout = Dialog::around(
         LinearLayout::vertical()
             .child(text_header)
+            .child(TextView::new(text_privileges))
+            .child(text_banner)
             .child(DummyView)
             .child(menu_buttons),
     )
     .title("Menu");
 
-    cursive.add_layer(layout);
+    cursive.add_layer(
+        OnEventView::new(layout)
+            .on_event('u', update)
+            .on_event('l', update_list)
+            .on_event('d', doh)
+            .on_event('h', |cursive| {
+                if !cfg!(target_os = "windows") && !is_has_admin_access() && !helper::is_running() {
+                    start_helper(cursive);
+                }
+            })
+            .on_event('r', |cursive| {
+                if !cfg!(target_os = "windows") && !is_has_admin_access() {
+                    relaunch_as_admin(cursive);
+                }
+            }),
+    );
 }
@@ -1,24 +1,44 @@
+use crate::config::{Channel, Config};
 use crate::error::ErrorKind;
-use crate::os::{HOSTS_BACKUP_PATH, HOSTS_PATH};
+use crate::os::{
+    create_new_file, replace_executable, EXECUTABLE_NAME, HOSTS_BACKUP_PATH, HOSTS_PATH,
+    OLD_EXECUTABLE_NAME,
+};
 use crate::parser::{parse_from_file, write_to_file};
-use crate::{CURRENT_VERSION, LATEST_VERSION_URL, UPDATE_URL};
+use crate::progress::Progress;
+use crate::rollback;
+use crate::signature::{self, SIGNATURE_EXTENSION};
+use crate::target;
+use crate::transport::{self, Transport};
+use crate::version::Version;
+use crate::{LATEST_VERSION_URL, RELEASES_URL, RELEASE_TAG_URL, TARGET, UPDATE_URL};
 use serde::Deserialize;
-use std::env::{current_dir, current_exe};
+use std::collections::HashMap;
+use std::env::current_exe;
 use std::fs;
-use std::io::Read;
-use std::io::Write as _;
-use std::path::Path;
+use std::io::{Read, Write as _};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+const ARCHIVE_EXTENSIONS: [&str; 2] = [".tar.gz", ".zip"];
+// Name of the new executable while it is being checked, next to the current one
+const STAGED_EXECUTABLE_NAME: &str = ".bebasin_new";
+const EXECUTABLE_MODE: u32 = 0o755;
+const SMOKE_TEST_TIMEOUT: Duration = Duration::from_secs(10);
 
 pub fn is_installed() -> bool {
-    // Maybe there are another condition that can be checked
-    is_backed()
+    target::configured().is_installed()
 }
 
 pub fn remove_temp_file() {
-    let mut tmp_file = current_dir().unwrap();
-    tmp_file.push(".bebasin_tmp");
-    if tmp_file.exists() {
-        fs::remove_file(tmp_file);
+    // The staged executable is left alone, it may be the one running the smoke test
+    if let Ok(exe_path) = current_exe() {
+        let tmp_file = exe_path.with_file_name(OLD_EXECUTABLE_NAME);
+        if tmp_file.exists() {
+            fs::remove_file(tmp_file).ok();
+        }
     }
 }
 
@@ -40,17 +60,71 @@ pub fn backup() -> Result<(), ErrorKind> {
     }
 }
 
+/// A release published in `latest.json`
 #[derive(Deserialize, Clone)]
-pub struct Checksum {
-    linux: String,
-    windows: String,
-    macos: String,
+pub struct ChannelRelease {
+    pub version: Version,
+    #[serde(default)]
+    pub critical: bool,
+    /// Tag of the GitHub release holding the assets, the latest release when missing
+    #[serde(default)]
+    tag: Option<String>,
+    /// Checksum of every release asset, by asset name
+    #[serde(default)]
+    sha256: HashMap<String, String>,
 }
 
 #[derive(Deserialize, Clone)]
 pub struct Latest {
-    pub version: u64,
-    checksum: Checksum,
+    // The stable release stays at the top level, where older releases look for it.
+    // There, `checksum` only holds the MD5 sums that older releases still rely on.
+    #[serde(flatten)]
+    stable: ChannelRelease,
+    #[serde(default)]
+    channels: HashMap<Channel, ChannelRelease>,
+    /// Older releases that can still be pinned
+    #[serde(default)]
+    releases: Vec<ChannelRelease>,
+    #[serde(default)]
+    minimum_supported_version: Option<Version>,
+}
+
+impl Latest {
+    /// The release to update to, the pinned version wins over the channel
+    fn select(&self, channel: Channel, pin: Option<&str>) -> Result<ChannelRelease, ErrorKind> {
+        if let Some(pin) = pin {
+            let pinned_version = Version::parse(pin)?;
+            return match std::iter::once(&self.stable)
+                .chain(self.channels.values())
+                .chain(self.releases.iter())
+                .find(|release| release.version == pinned_version)
+            {
+                Some(release) => Ok(release.clone()),
+                None => Err(ErrorKind::String(format!(
+                    "The pinned version {} is not available",
+                    pin
+                ))),
+            };
+        }
+
+        // Less stable channels also get the more stable releases when those are newer
+        let channels: &[Channel] = match channel {
+            Channel::Stable => &[],
+            Channel::Beta => &[Channel::Beta],
+            Channel::Nightly => &[Channel::Beta, Channel::Nightly],
+        };
+        let newest = channels
+            .iter()
+            .filter_map(|channel| self.channels.get(channel))
+            .fold(&self.stable, |newest, release| {
+                if release.version > newest.version {
+                    release
+                } else {
+                    newest
+                }
+            });
+        Ok(newest.clone())
+    }
 }
 
 #[derive(Deserialize)]
@@ -62,368 +136,715 @@ struct ReleaseAssets {
 
 #[derive(Deserialize)]
 struct Release {
+    #[serde(default)]
+    tag_name: String,
+    #[serde(default)]
+    body: Option<String>,
     assets: Vec<ReleaseAssets>,
 }
 
-fn md5_digest<R: std::io::Read>(mut reader: R) -> Result<md5::Digest, std::io::Error> {
-    let mut context = md5::Context::new();
-    let mut buffer = [0; 1024];
+/// Other names the release assets may use for the architecture
+fn architecture_aliases() -> &'static [&'static str] {
+    match std::env::consts::ARCH {
+        "x86_64" => &["x86_64", "amd64"],
+        "aarch64" => &["aarch64", "arm64"],
+        "arm" => &["armv7", "armhf"],
+        _ => &[std::env::consts::ARCH],
+    }
+}
 
-    loop {
-        let count = match reader.read(&mut buffer) {
-            Ok(size) => {
-                if size == 0 {
-                    break;
-                } else {
-                    size
-                }
+/// Pick the release asset built for this machine.
+/// The target triple is preferred, then the OS and architecture,
+/// then the legacy OS only archive that is always built for x86_64.
+fn select_asset(assets: &[ReleaseAssets]) -> Option<&ReleaseAssets> {
+    let os = std::env::consts::OS;
+    let archives: Vec<&ReleaseAssets> = assets
+        .iter()
+        .filter(|asset| {
+            ARCHIVE_EXTENSIONS
+                .iter()
+                .any(|extension| asset.name.ends_with(extension))
+        })
+        .collect();
+
+    archives
+        .iter()
+        .find(|asset| asset.name.contains(TARGET))
+        .or_else(|| {
+            archives.iter().find(|asset| {
+                asset.name.contains(os)
+                    && architecture_aliases()
+                        .iter()
+                        .any(|arch| asset.name.contains(arch))
+            })
+        })
+        .or_else(|| {
+            if std::env::consts::ARCH != "x86_64" {
+                return None;
             }
-            Err(err) => return Err(err),
-        };
-        context.consume(&buffer[..count]);
-    }
+            let legacy_name = format!("bebasin-{}.zip", os);
+            archives.iter().find(|asset| asset.name == legacy_name)
+        })
+        .copied()
+}
 
-    Ok(context.compute())
+fn is_executable_entry(path: &Path) -> bool {
+    path.file_name()
+        .map(|name| name == EXECUTABLE_NAME)
+        .unwrap_or(false)
 }
 
-fn get_md5_digest<P: AsRef<Path>>(path: &P) -> Result<md5::Digest, ErrorKind> {
-    match std::fs::File::open(path) {
-        Ok(file) => match md5_digest(file) {
-            Ok(x) => Ok(x),
-            Err(err) => Err(ErrorKind::IOError(err)),
-        },
-        Err(err) => Err(ErrorKind::IOError(err)),
+/// Take the executable out of a zip or tar.gz release archive
+fn extract(asset_name: &str, archive: &[u8]) -> Result<Vec<u8>, ErrorKind> {
+    let mut buf = Vec::new();
+
+    if asset_name.ends_with(".tar.gz") {
+        let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(archive));
+        let entries = match archive.entries() {
+            Ok(entries) => entries,
+            Err(err) => return Err(ErrorKind::IOError(err)),
+        };
+        for entry in entries {
+            let mut entry = match entry {
+                Ok(entry) => entry,
+                Err(err) => return Err(ErrorKind::IOError(err)),
+            };
+            let is_executable = match entry.path() {
+                Ok(path) => is_executable_entry(&path),
+                Err(err) => return Err(ErrorKind::IOError(err)),
+            };
+            if is_executable {
+                return match entry.read_to_end(&mut buf) {
+                    Ok(_) => Ok(buf),
+                    Err(err) => Err(ErrorKind::IOError(err)),
+                };
+            }
+        }
+    } else {
+        let mut archive = match zip::ZipArchive::new(std::io::Cursor::new(archive)) {
+            Ok(archive) => archive,
+            Err(err) => return Err(ErrorKind::ZipError(err)),
+        };
+        for index in 0..archive.len() {
+            let mut file = match archive.by_index(index) {
+                Ok(file) => file,
+                Err(err) => return Err(ErrorKind::ZipError(err)),
+            };
+            if is_executable_entry(Path::new(file.name())) {
+                return match file.read_to_end(&mut buf) {
+                    Ok(_) => Ok(buf),
+                    Err(err) => Err(ErrorKind::IOError(err)),
+                };
+            }
+        }
     }
-}
 
-#[cfg(any(target_os = "linux", target_os = "macos"))]
-fn set_as_executable<P: AsRef<Path> + nix::NixPath>(path: &P) -> Result<(), ErrorKind> {
-    use std::os::unix::io::IntoRawFd as _;
+    Err(ErrorKind::String(format!(
+        "{} doesn't contain {}",
+        asset_name, EXECUTABLE_NAME
+    )))
+}
 
-    // Get file permission
-    let permission = match nix::sys::stat::stat(path) {
-        Ok(stat) => stat.st_mode,
-        Err(err) => return Err(ErrorKind::NixError(err)),
+/// Make sure the new executable actually runs on this machine before using it
+fn smoke_test(path: &Path) -> Result<(), ErrorKind> {
+    let mut child = match Command::new(path)
+        .arg("--version")
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(err) => return Err(ErrorKind::IOError(err)),
     };
-    let mut permission_mode = nix::sys::stat::Mode::from_bits_truncate(permission);
-    // Add user executable permission
-    permission_mode.insert(nix::sys::stat::Mode::S_IRWXU);
 
-    // Set the file permission
-    let file_descriptor = match std::fs::File::open(path) {
-        Ok(file) => file.into_raw_fd(),
-        Err(err) => return Err(ErrorKind::IOError(err)),
+    let started_at = Instant::now();
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if started_at.elapsed() > SMOKE_TEST_TIMEOUT => {
+                child.kill().ok();
+                return Err(ErrorKind::String(String::from(
+                    "The downloaded application did not respond",
+                )));
+            }
+            Ok(None) => sleep(Duration::from_millis(100)),
+            Err(err) => return Err(ErrorKind::IOError(err)),
+        }
     };
-    match nix::sys::stat::fchmod(file_descriptor, permission_mode) {
-        Ok(_) => Ok(()),
-        Err(err) => Err(ErrorKind::NixError(err)),
+
+    let mut output = String::new();
+    if let Some(mut stdout) = child.stdout.take() {
+        stdout.read_to_string(&mut output).ok();
+    }
+
+    if status.success() && output.starts_with("bebasin") {
+        Ok(())
+    } else {
+        Err(ErrorKind::String(String::from(
+            "The downloaded application failed to run",
+        )))
     }
 }
 
+/// Write the new executable next to the current one, so the final rename stays on one filesystem
+pub fn stage(exe_path: &Path, binary: &[u8]) -> Result<PathBuf, ErrorKind> {
+    let staged_path = exe_path.with_file_name(STAGED_EXECUTABLE_NAME);
+    // The directory may be writable by others, nothing they left there gets written through
+    if fs::symlink_metadata(&staged_path).is_ok() {
+        if let Err(err) = fs::remove_file(&staged_path) {
+            return Err(ErrorKind::IOError(err));
+        }
+    }
+    let mut file = create_new_file(&staged_path, EXECUTABLE_MODE)?;
+    if let Err(err) = file.write_all(binary) {
+        fs::remove_file(&staged_path).ok();
+        return Err(ErrorKind::IOError(err));
+    }
+    Ok(staged_path)
+}
+
 pub struct Updater {
-    pub latest: Option<Latest>,
+    pub latest: Option<ChannelRelease>,
+    minimum_supported_version: Option<Version>,
+    channel: Channel,
+    pin: Option<String>,
+    allow_unsigned: bool,
+    keep_binaries: usize,
+    transport: Box<dyn Transport>,
 }
 
 impl Updater {
     pub fn new() -> Updater {
-        Updater { latest: None }
+        let config = Config::load().unwrap_or_default();
+        let transport = transport::from_config(&config);
+        Updater::with_transport(config, transport)
     }
 
-    pub fn get_latest_info(&mut self) -> Result<Latest, ErrorKind> {
-        let mut byte_data = Vec::new();
-        let mut curl_instance = curl::easy::Easy::new();
-        curl_instance.url(LATEST_VERSION_URL).unwrap();
-        {
-            let mut handler = curl_instance.transfer();
-            handler
-                .write_function(|data| {
-                    byte_data.extend_from_slice(data);
-                    Ok(data.len())
-                })
-                .unwrap();
-            handler.perform().unwrap();
+    /// An updater going through `transport` instead of the configured one
+    pub fn with_transport(config: Config, transport: Box<dyn Transport>) -> Updater {
+        Updater {
+            latest: None,
+            minimum_supported_version: None,
+            channel: config.channel,
+            allow_unsigned: config.allow_unsigned,
+            keep_binaries: config.keep_binaries,
+            transport,
+            pin: config.pin,
         }
+    }
+
+    pub fn get_latest_info(&mut self) -> Result<ChannelRelease, ErrorKind> {
+        let byte_data = transport::download_cached(
+            self.transport.as_ref(),
+            LATEST_VERSION_URL,
+            &Progress::none(),
+        )?;
         let string_data = String::from_utf8_lossy(&byte_data);
 
-        self.latest = match serde_json::from_str::<Latest>(&string_data) {
-            Ok(latest_data) => Some(latest_data),
+        let latest_data = match serde_json::from_str::<Latest>(&string_data) {
+            Ok(latest_data) => latest_data,
             Err(err) => return Err(ErrorKind::SerdeJSONError(err)),
         };
+        let release = latest_data.select(self.channel, self.pin.as_deref())?;
 
-        Ok(self.latest.clone().unwrap())
+        self.minimum_supported_version = latest_data.minimum_supported_version;
+        self.latest = Some(release.clone());
+        Ok(release)
     }
 
     pub fn is_updatable(&self) -> bool {
-        // Bruh unsafe
-        let latest = &self.latest.as_ref().unwrap();
+        match &self.latest {
+            // A pinned version may as well be older than the current one
+            Some(latest) if self.pin.is_some() => latest.version != Version::current(),
+            Some(latest) => Version::current() < latest.version,
+            None => false,
+        }
+    }
 
-        CURRENT_VERSION < latest.version
+    /// The oldest version the project still supports, once the latest information is retrieved
+    pub fn minimum_supported_version(&self) -> Option<&Version> {
+        self.minimum_supported_version.as_ref()
     }
 
-    pub fn update(&self) -> Result<(), ErrorKind> {
-        let mut byte_data = Vec::new();
-        let mut curl_instance = curl::easy::Easy::new();
-        curl_instance.url(UPDATE_URL).unwrap();
-        curl_instance
-            .useragent("User-Agent: Awesome-Octocat-App")
-            .unwrap();
-        {
-            let mut handler = curl_instance.transfer();
-            handler
-                .write_function(|data| {
-                    byte_data.extend_from_slice(data);
-                    Ok(data.len())
-                })
-                .unwrap();
-            handler.perform().unwrap();
+    /// Whether the running version is still supported by the project
+    pub fn is_supported(&self) -> bool {
+        match &self.minimum_supported_version {
+            Some(minimum_supported_version) => Version::current() >= *minimum_supported_version,
+            None => true,
         }
+    }
+
+    /// The release notes of every version after the current one up to the latest, newest first
+    pub fn changelog(&self) -> Result<String, ErrorKind> {
+        let latest = match &self.latest {
+            Some(latest) => latest,
+            None => {
+                return Err(ErrorKind::String(String::from(
+                    "The latest application information has not been retrieved",
+                )))
+            }
+        };
+
+        let byte_data =
+            transport::download_cached(self.transport.as_ref(), RELEASES_URL, &Progress::none())?;
         let string_data = String::from_utf8_lossy(&byte_data);
-        let release_data = match serde_json::from_str::<Release>(&string_data) {
-            Ok(release_data) => release_data,
+        let releases = match serde_json::from_str::<Vec<Release>>(&string_data) {
+            Ok(releases) => releases,
             Err(err) => return Err(ErrorKind::SerdeJSONError(err)),
         };
 
-        self.process_update(release_data)
-    }
-
-    #[cfg(target_os = "windows")]
-    fn process_update(&self, release: Release) -> Result<(), ErrorKind> {
-        // Bruh unsafe
-        let latest = &self.latest.as_ref().unwrap();
-
-        for asset in release.assets {
-            if asset.name.contains("windows") {
-                let mut byte_data = Vec::new();
-                let mut curl_instance = curl::easy::Easy::new();
-                curl_instance.url(&asset.browser_download_url).unwrap();
-                curl_instance.follow_location(true).unwrap();
-                curl_instance.cookie_file("cookie").unwrap();
-                curl_instance.cookie_session(true).unwrap();
-                {
-                    let mut handler = curl_instance.transfer();
-                    handler
-                        .write_function(|data| {
-                            byte_data.extend_from_slice(data);
-                            Ok(data.len())
-                        })
-                        .unwrap();
-                    handler.perform().unwrap();
+        let current_version = Version::current();
+        let mut notes = releases
+            .into_iter()
+            .filter_map(|release| match Version::parse(&release.tag_name) {
+                Ok(version) if version > current_version && version <= latest.version => {
+                    Some((version, release.body.unwrap_or_default()))
                 }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        notes.sort_by(|a, b| b.0.cmp(&a.0));
 
-                let mut updated_exe_path = current_dir().unwrap();
-                updated_exe_path.push(".bebasin_tmp");
-                let mut tmp_exe_path = current_dir().unwrap();
-                tmp_exe_path.push(".bebasin_tmp2");
-                // Bruh unsafe
-                let current_exe_path = &current_exe().unwrap();
-
-                {
-                    let mut file_created = fs::File::create(&updated_exe_path).unwrap();
-                    file_created.write(byte_data.as_slice());
+        if notes.is_empty() {
+            return Ok(String::from("There are no release notes for this version"));
+        }
+        Ok(notes
+            .into_iter()
+            .map(|(version, body)| {
+                let body = body.trim().replace("\r\n", "\n");
+                if body.is_empty() {
+                    format!("Version {}\n\nNo release notes", version)
+                } else {
+                    format!("Version {}\n\n{}", version, body)
                 }
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n"))
+    }
 
-                match get_md5_digest(&updated_exe_path) {
-                    Ok(digest) => {
-                        if format!("{:x}", digest) != latest.checksum.windows {
-                            return Err(ErrorKind::String(String::from("Download corrupt")));
-                        }
-                    }
-                    Err(err) => return Err(err),
-                };
-
-                let mut buf = Vec::new();
+    pub fn update(&self, progress: &Progress) -> Result<(), ErrorKind> {
+        let binary = self.download(progress)?;
+        self.install(&binary, progress)
+    }
 
-                {
-                    let zipfile = std::fs::File::open(&updated_exe_path).unwrap();
+    /// Download the release asset for this machine, verify it and take the executable out of it
+    fn download(&self, progress: &Progress) -> Result<Vec<u8>, ErrorKind> {
+        progress.step("Retrieving the release");
+        let release_url = match self.latest.as_ref().and_then(|latest| latest.tag.as_ref()) {
+            Some(tag) => format!("{}{}", RELEASE_TAG_URL, tag),
+            None => String::from(UPDATE_URL),
+        };
+        let byte_data = self
+            .transport
+            .download_with_progress(&release_url, progress)?;
+        let string_data = String::from_utf8_lossy(&byte_data);
+        let release_data = match serde_json::from_str::<Release>(&string_data) {
+            Ok(release_data) => release_data,
+            Err(err) => return Err(ErrorKind::SerdeJSONError(err)),
+        };
 
-                    let mut archive = zip::ZipArchive::new(zipfile).unwrap();
+        self.download_asset(release_data, progress)
+    }
 
-                    let mut file = match archive.by_name("bebasin.exe") {
-                        Ok(file) => file,
-                        Err(err) => return Err(ErrorKind::ZipError(err)),
-                    };
+    /// Check the SHA-256 checksum and the detached signature of a downloaded release asset
+    fn verify_asset(
+        &self,
+        release: &Release,
+        asset: &ReleaseAssets,
+        data: &[u8],
+    ) -> Result<(), ErrorKind> {
+        let latest = match &self.latest {
+            Some(latest) => latest,
+            None => {
+                return Err(ErrorKind::String(String::from(
+                    "The latest application information has not been retrieved",
+                )))
+            }
+        };
+        match latest.sha256.get(&asset.name) {
+            Some(checksum) if *checksum == signature::sha256(data) => {}
+            Some(_) => return Err(ErrorKind::String(String::from("Download corrupt"))),
+            None => {
+                return Err(ErrorKind::String(format!(
+                    "There is no checksum for {}",
+                    asset.name
+                )))
+            }
+        };
 
-                    file.read_to_end(&mut buf);
-                }
+        let signature_name = format!("{}{}", asset.name, SIGNATURE_EXTENSION);
+        let signature = match release
+            .assets
+            .iter()
+            .find(|signature_asset| signature_asset.name == signature_name)
+        {
+            Some(signature_asset) => match self
+                .transport
+                .download(&signature_asset.browser_download_url)
+            {
+                Ok(bytes) => Some(String::from_utf8_lossy(&bytes).into_owned()),
+                Err(err) => return Err(err),
+            },
+            None => None,
+        };
 
-                std::fs::File::create(&updated_exe_path)
-                    .unwrap()
-                    .write(&buf);
+        signature::verify_or_refuse(data, signature.as_deref(), self.allow_unsigned, &asset.name)
+    }
 
-                if let Err(err) = fs::rename(&current_exe_path, &tmp_exe_path) {
-                    return Err(ErrorKind::IOError(err));
-                }
+    fn download_asset(&self, release: Release, progress: &Progress) -> Result<Vec<u8>, ErrorKind> {
+        let asset = match select_asset(&release.assets) {
+            Some(asset) => asset,
+            None => {
+                return Err(ErrorKind::String(format!(
+                    "There is no release for {}",
+                    TARGET
+                )))
+            }
+        };
 
-                if let Err(err) = fs::rename(&updated_exe_path, &current_exe_path) {
-                    return Err(ErrorKind::IOError(err));
-                }
+        progress.step(&format!("Downloading {}", asset.name));
+        progress.expect_size(u64::from(asset.size));
+        let archive = self
+            .transport
+            .download_with_progress(&asset.browser_download_url, progress)?;
+        progress.step(&format!("Verifying {}", asset.name));
+        self.verify_asset(&release, asset, &archive)?;
+        extract(&asset.name, &archive)
+    }
 
-                if let Err(err) = fs::rename(&tmp_exe_path, &updated_exe_path) {
-                    return Err(ErrorKind::IOError(err));
-                }
+    /// Update from a release archive downloaded beforehand, for machines that can't reach
+    /// the releases. The signature is looked for next to the archive.
+    pub fn update_from_archive(&self, path: &Path, progress: &Progress) -> Result<(), ErrorKind> {
+        let name = match path.file_name() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => {
+                return Err(ErrorKind::String(format!(
+                    "{} is not a release archive",
+                    path.display()
+                )))
             }
+        };
+        if !ARCHIVE_EXTENSIONS
+            .iter()
+            .any(|extension| name.ends_with(extension))
+        {
+            return Err(ErrorKind::String(format!(
+                "{} is not a release archive, expected a .tar.gz or .zip file",
+                name
+            )));
         }
-        Ok(())
-    }
 
-    #[cfg(target_os = "linux")]
-    fn process_update(&self, release: Release) -> Result<(), ErrorKind> {
-        // Bruh unsafe
-        let latest = &self.latest.as_ref().unwrap();
-
-        for asset in release.assets {
-            if asset.name.contains("linux") {
-                let mut byte_data = Vec::new();
-                let mut curl_instance = curl::easy::Easy::new();
-                println!("{}", asset.browser_download_url);
-                curl_instance.url(&asset.browser_download_url).unwrap();
-                curl_instance.follow_location(true).unwrap();
-                curl_instance.cookie_file("cookie").unwrap();
-                curl_instance.cookie_session(true).unwrap();
-                {
-                    println!("Running");
-                    let mut handler = curl_instance.transfer();
-                    handler
-                        .write_function(|data| {
-                            byte_data.extend_from_slice(data);
-                            Ok(data.len())
-                        })
-                        .unwrap();
-                    handler.perform().unwrap();
-                }
+        progress.step(&format!("Verifying {}", name));
+        let archive = match fs::read(path) {
+            Ok(archive) => archive,
+            Err(err) => return Err(ErrorKind::IOError(err)),
+        };
+        let mut signature_path = path.as_os_str().to_owned();
+        signature_path.push(SIGNATURE_EXTENSION);
+        let signature = fs::read_to_string(&signature_path).ok();
+        signature::verify_or_refuse(&archive, signature.as_deref(), self.allow_unsigned, &name)?;
+        let binary = extract(&name, &archive)?;
 
-                let mut updated_exe_path = std::env::current_exe().unwrap();
-                updated_exe_path.pop();
-                updated_exe_path.push(".bebasin_tmp");
-                // Bruh unsafe
-                let current_exe_path = &std::env::current_exe().unwrap();
+        self.install(&binary, progress)
+    }
 
-                {
-                    let mut file_created = fs::File::create(&updated_exe_path).unwrap();
-                    file_created.write(byte_data.as_slice());
-                }
+    /// Releases versioned by their build time predate `--version`, they open the menu instead
+    fn is_legacy_release(&self) -> bool {
+        match &self.latest {
+            Some(latest) => latest.version.is_legacy(),
+            None => false,
+        }
+    }
 
-                match get_md5_digest(&updated_exe_path) {
-                    Ok(digest) => {
-                        if format!("{:x}", digest) != latest.checksum.linux {
-                            return Err(ErrorKind::String(String::from("Download corrupt")));
-                        }
-                    }
-                    Err(err) => return Err(err),
-                };
+    fn install(&self, binary: &[u8], progress: &Progress) -> Result<(), ErrorKind> {
+        // Past this point the update has to run until the end
+        progress.check()?;
+        progress.step("Installing the new version");
 
-                let mut buf = Vec::new();
+        let exe_path = match current_exe() {
+            Ok(exe_path) => exe_path,
+            Err(err) => return Err(ErrorKind::IOError(err)),
+        };
+        let staged_path = stage(&exe_path, binary)?;
 
-                {
-                    let zipfile = std::fs::File::open(&updated_exe_path).unwrap();
+        let result = if self.is_legacy_release() {
+            Ok(())
+        } else {
+            smoke_test(&staged_path)
+        };
+        let result = result
+            .and_then(|_| rollback::keep_current(self.keep_binaries))
+            .and_then(|_| replace_executable(&staged_path, &exe_path));
+        if result.is_err() {
+            fs::remove_file(&staged_path).ok();
+        }
+        result
+    }
+}
 
-                    let mut archive = zip::ZipArchive::new(zipfile).unwrap();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, HttpServer, Redirect, Reply};
+    use serde_json::json;
+
+    const BINARY: &[u8] = b"#!/bin/sh\necho bebasin\n";
+
+    fn tar_gz(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        let mut builder = tar::Builder::new(encoder);
+        for (name, content) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(EXECUTABLE_MODE);
+            header.set_cksum();
+            builder.append_data(&mut header, name, *content).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap()
+    }
 
-                    let mut file = match archive.by_name("bebasin") {
-                        Ok(file) => file,
-                        Err(err) => return Err(ErrorKind::ZipError(err)),
-                    };
+    fn zip(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        for (name, content) in entries {
+            writer
+                .start_file(*name, zip::write::FileOptions::default())
+                .unwrap();
+            writer.write_all(content).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
 
-                    file.read_to_end(&mut buf);
-                }
+    /// Publish `archive` as the latest release, along with its signature when there is one
+    fn publish(
+        server: &HttpServer,
+        asset_name: &str,
+        archive: &[u8],
+        checksum: &str,
+        signature: Option<&[u8]>,
+    ) {
+        server.route(
+            transport::url_path(LATEST_VERSION_URL),
+            vec![Reply::ok(
+                json!({
+                    "version": "99.0.0",
+                    "sha256": { asset_name: checksum },
+                })
+                .to_string()
+                .as_bytes(),
+            )],
+        );
+
+        let mut assets = vec![json!({
+            "name": asset_name,
+            "size": archive.len(),
+            "browser_download_url": server.url(&format!("download/{}", asset_name)),
+        })];
+        server.route(
+            &format!("download/{}", asset_name),
+            vec![Reply::ok(archive)],
+        );
+        if let Some(signature) = signature {
+            let signature_name = format!("{}{}", asset_name, SIGNATURE_EXTENSION);
+            assets.push(json!({
+                "name": signature_name,
+                "size": signature.len(),
+                "browser_download_url": server.url(&format!("download/{}", signature_name)),
+            }));
+            server.route(
+                &format!("download/{}", signature_name),
+                vec![Reply::ok(signature)],
+            );
+        }
+        server.route(
+            transport::url_path(UPDATE_URL),
+            vec![Reply::ok(
+                json!({ "tag_name": "v99.0.0", "assets": assets })
+                    .to_string()
+                    .as_bytes(),
+            )],
+        );
+    }
 
-                std::fs::File::create(&updated_exe_path)
-                    .unwrap()
-                    .write(&buf);
+    fn updater(server: &HttpServer, config: Config) -> Updater {
+        let transport = Box::new(Redirect::new(server, &config));
+        Updater::with_transport(config, transport)
+    }
 
-                if let Err(err) = set_as_executable(&std::path::PathBuf::from(&updated_exe_path)) {
-                    return Err(err);
-                }
+    fn unsigned_updater(server: &HttpServer) -> Updater {
+        updater(
+            server,
+            Config {
+                allow_unsigned: true,
+                ..testing::config()
+            },
+        )
+    }
 
-                if let Err(err) = nix::unistd::unlink(current_exe_path) {
-                    return Err(ErrorKind::NixError(err));
-                }
+    fn download(updater: &mut Updater) -> Result<Vec<u8>, ErrorKind> {
+        updater.get_latest_info()?;
+        updater.download(&Progress::none())
+    }
 
-                if let Err(err) = fs::rename(&updated_exe_path, current_exe_path) {
-                    return Err(ErrorKind::IOError(err));
-                }
-            }
-        }
-        Ok(())
+    fn asset_name(extension: &str) -> String {
+        format!("bebasin-{}{}", TARGET, extension)
     }
 
-    #[cfg(target_os = "macos")]
-    fn process_update(&self, release: Release) -> Result<(), ErrorKind> {
-        // Bruh unsafe
-        let latest = &self.latest.as_ref().unwrap();
-
-        for asset in release.assets {
-            if asset.name.contains("macos") {
-                let mut byte_data = Vec::new();
-                let mut curl_instance = curl::easy::Easy::new();
-                curl_instance.url(&asset.browser_download_url).unwrap();
-                curl_instance.follow_location(true).unwrap();
-                curl_instance.cookie_file("cookie").unwrap();
-                curl_instance.cookie_session(true).unwrap();
-                {
-                    let mut handler = curl_instance.transfer();
-                    handler
-                        .write_function(|data| {
-                            byte_data.extend_from_slice(data);
-                            Ok(data.len())
-                        })
-                        .unwrap();
-                    handler.perform().unwrap();
-                }
+    #[test]
+    fn selects_the_release_of_the_channel() {
+        let server = HttpServer::start();
+        server.route(
+            transport::url_path(LATEST_VERSION_URL),
+            vec![Reply::ok(
+                json!({
+                    "version": "1.0.0",
+                    "channels": {
+                        "beta": { "version": "1.1.0-beta.1" },
+                        "nightly": { "version": "1.0.1-nightly.1" },
+                    },
+                    "releases": [{ "version": 202011120913u64 }],
+                    "minimum_supported_version": "1.0.0",
+                })
+                .to_string()
+                .as_bytes(),
+            )],
+        );
+
+        let mut stable = updater(&server, testing::config());
+        assert!(stable.get_latest_info().unwrap().version == Version::parse("1.0.0").unwrap());
+        assert!(stable.is_updatable());
+        assert!(!stable.is_supported());
+
+        let mut nightly = updater(
+            &server,
+            Config {
+                channel: Channel::Nightly,
+                ..testing::config()
+            },
+        );
+        let release = nightly.get_latest_info().unwrap();
+        assert!(release.version == Version::parse("1.1.0-beta.1").unwrap());
+
+        let mut pinned = updater(
+            &server,
+            Config {
+                pin: Some(String::from("202011120913")),
+                ..testing::config()
+            },
+        );
+        let release = pinned.get_latest_info().unwrap();
+        assert!(release.version == Version::Timestamp(202011120913));
+        // Going back to a pinned version is an update too
+        assert!(pinned.is_updatable());
+        // Probing it would open its menu and time out
+        assert!(pinned.is_legacy_release());
+        assert!(!stable.is_legacy_release());
+    }
 
-                let mut updated_exe_path = std::env::current_exe().unwrap();
-                updated_exe_path.pop();
-                updated_exe_path.push(".bebasin_tmp");
-                // Bruh unsafe
-                let current_exe_path = &std::env::current_exe().unwrap();
+    #[test]
+    fn downloads_and_extracts_the_release() {
+        let server = HttpServer::start();
+        let name = asset_name(".tar.gz");
+        let archive = tar_gz(&[("README.md", b"readme"), (EXECUTABLE_NAME, BINARY)]);
+        publish(&server, &name, &archive, &signature::sha256(&archive), None);
 
-                {
-                    let mut file_created = fs::File::create(&updated_exe_path).unwrap();
-                    file_created.write(byte_data.as_slice());
-                }
+        assert_eq!(download(&mut unsigned_updater(&server)).unwrap(), BINARY);
+    }
 
-                match get_md5_digest(&updated_exe_path) {
-                    Ok(digest) => {
-                        if format!("{:x}", digest) != latest.checksum.macos {
-                            return Err(ErrorKind::String(String::from("Download corrupt")));
-                        }
-                    }
-                    Err(err) => return Err(err),
-                };
+    #[test]
+    fn extracts_zip_releases() {
+        let server = HttpServer::start();
+        let name = asset_name(".zip");
+        let archive = zip(&[(&format!("bebasin/{}", EXECUTABLE_NAME), BINARY)]);
+        publish(&server, &name, &archive, &signature::sha256(&archive), None);
 
-                let mut buf = Vec::new();
+        assert_eq!(download(&mut unsigned_updater(&server)).unwrap(), BINARY);
+    }
 
-                {
-                    let zipfile = std::fs::File::open(&updated_exe_path).unwrap();
+    #[test]
+    fn refuses_unsigned_releases() {
+        let server = HttpServer::start();
+        let name = asset_name(".tar.gz");
+        let archive = tar_gz(&[(EXECUTABLE_NAME, BINARY)]);
+        publish(&server, &name, &archive, &signature::sha256(&archive), None);
 
-                    let mut archive = zip::ZipArchive::new(zipfile).unwrap();
+        match download(&mut updater(&server, testing::config())) {
+            Err(ErrorKind::String(message)) => assert!(message.contains("is not signed")),
+            result => panic!("unexpected result {:?}", result.map(|_| ())),
+        }
+    }
 
-                    let mut file = match archive.by_name("bebasin") {
-                        Ok(file) => file,
-                        Err(err) => return Err(ErrorKind::ZipError(err)),
-                    };
+    #[test]
+    fn refuses_invalid_signatures() {
+        let server = HttpServer::start();
+        let name = asset_name(".tar.gz");
+        let archive = tar_gz(&[(EXECUTABLE_NAME, BINARY)]);
+        let signature = b"untrusted comment: forged\nRWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3\n";
+        publish(
+            &server,
+            &name,
+            &archive,
+            &signature::sha256(&archive),
+            Some(signature),
+        );
+
+        match download(&mut updater(&server, testing::config())) {
+            Err(ErrorKind::SignatureError(_)) => {}
+            result => panic!("unexpected result {:?}", result.map(|_| ())),
+        }
+    }
 
-                    file.read_to_end(&mut buf);
-                }
+    #[test]
+    fn refuses_corrupt_downloads() {
+        let server = HttpServer::start();
+        let name = asset_name(".tar.gz");
+        let archive = tar_gz(&[(EXECUTABLE_NAME, BINARY)]);
+        publish(&server, &name, &archive, &signature::sha256(b"other"), None);
 
-                std::fs::File::create(&updated_exe_path)
-                    .unwrap()
-                    .write(&buf);
+        match download(&mut unsigned_updater(&server)) {
+            Err(ErrorKind::String(message)) => assert_eq!(message, "Download corrupt"),
+            result => panic!("unexpected result {:?}", result.map(|_| ())),
+        }
+    }
 
-                if let Err(err) = set_as_executable(&std::path::PathBuf::from(&updated_exe_path)) {
-                    return Err(err);
-                }
+    #[test]
+    fn refuses_corrupt_archives() {
+        let server = HttpServer::start();
+        let name = asset_name(".tar.gz");
+        // The checksum matches, the archive itself is broken
+        let archive = b"not a gzip stream";
+        publish(&server, &name, archive, &signature::sha256(archive), None);
+        assert!(download(&mut unsigned_updater(&server)).is_err());
+
+        let name = asset_name(".zip");
+        let archive = zip(&[("README.md", b"readme")]);
+        publish(&server, &name, &archive, &signature::sha256(&archive), None);
+        match download(&mut unsigned_updater(&server)) {
+            Err(ErrorKind::String(message)) => assert!(message.contains("doesn't contain")),
+            result => panic!("unexpected result {:?}", result.map(|_| ())),
+        }
+    }
 
-                if let Err(err) = nix::unistd::unlink(current_exe_path) {
-                    return Err(ErrorKind::NixError(err));
-                }
+    #[test]
+    fn reports_http_errors() {
+        let server = HttpServer::start();
+        server.route(
+            transport::url_path(LATEST_VERSION_URL),
+            vec![Reply::status(500)],
+        );
+        match unsigned_updater(&server).get_latest_info() {
+            Err(ErrorKind::HTTPError(500, _)) => {}
+            result => panic!("unexpected result {:?}", result.map(|_| ())),
+        }
 
-                match fs::rename(&updated_exe_path, current_exe_path) {
-                    Err(err) => return Err(ErrorKind::IOError(err)),
-                    _ => (),
-                };
-            }
+        // The release itself is missing
+        let name = asset_name(".tar.gz");
+        let archive = tar_gz(&[(EXECUTABLE_NAME, BINARY)]);
+        publish(&server, &name, &archive, &signature::sha256(&archive), None);
+        server.route(transport::url_path(UPDATE_URL), vec![Reply::status(404)]);
+        match download(&mut unsigned_updater(&server)) {
+            Err(ErrorKind::HTTPError(404, _)) => {}
+            result => panic!("unexpected result {:?}", result.map(|_| ())),
         }
-        Ok(())
     }
 }
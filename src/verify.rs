@@ -0,0 +1,132 @@
+use crate::error::ErrorKind;
+use crate::progress::Progress;
+use crate::target;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::net::{IpAddr, ToSocketAddrs};
+
+/// How many hostnames are resolved, each one may wait for a DNS server
+const SAMPLE_SIZE: usize = 20;
+
+/// A hostname that doesn't resolve to the address of the install target
+struct Mismatch {
+    hostname: String,
+    expected: Vec<IpAddr>,
+    resolved: Vec<IpAddr>,
+}
+
+pub struct Report {
+    /// The file of the install target the mapping comes from
+    path: String,
+    checked: usize,
+    mismatches: Vec<Mismatch>,
+}
+
+fn join(addresses: &[IpAddr]) -> String {
+    addresses
+        .iter()
+        .map(IpAddr::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl Report {
+    pub fn describe(&self) -> String {
+        if self.checked == 0 {
+            return String::from("There is no hostname to verify");
+        }
+        if self.mismatches.is_empty() {
+            return format!(
+                "All {} sampled hostnames resolve to the addresses of {}",
+                self.checked, self.path
+            );
+        }
+
+        let mut description = format!(
+            "{} of {} sampled hostnames are overridden by something else\n\
+            (a DNS cache, the nsswitch order or another resolver):",
+            self.mismatches.len(),
+            self.checked
+        );
+        for mismatch in &self.mismatches {
+            if mismatch.resolved.is_empty() {
+                description.push_str(&format!(
+                    "\n{} can't be resolved instead of {}",
+                    mismatch.hostname,
+                    join(&mismatch.expected)
+                ));
+            } else {
+                description.push_str(&format!(
+                    "\n{} resolves to {} instead of {}",
+                    mismatch.hostname,
+                    join(&mismatch.resolved),
+                    join(&mismatch.expected)
+                ));
+            }
+        }
+        description
+    }
+}
+
+/// Names that resolve without the hosts file, or that the system handles specially
+fn is_verifiable(hostname: &str) -> bool {
+    hostname.contains('.') && !hostname.starts_with("localhost") && !hostname.ends_with(".local")
+}
+
+/// Resolve a sample of the hostnames of the install target through the system resolver,
+/// and check that they resolve to the addresses it maps them to
+pub fn verify(progress: &Progress) -> Result<Report, ErrorKind> {
+    let target = target::configured();
+    let content = match fs::read_to_string(target.path()) {
+        Ok(content) => content,
+        Err(err) => return Err(ErrorKind::IOError(err)),
+    };
+    let hosts = target.parse(&content)?;
+
+    let mut expected: BTreeMap<&str, Vec<IpAddr>> = BTreeMap::new();
+    for (ip, hostnames) in &hosts {
+        let ip = match ip.parse::<IpAddr>() {
+            Ok(ip) => ip,
+            Err(_) => continue,
+        };
+        for hostname in hostnames.iter().filter(|hostname| is_verifiable(hostname)) {
+            expected.entry(hostname).or_default().push(ip);
+        }
+    }
+
+    // Spread the sample over the whole list rather than taking the first names
+    let step = (expected.len() / SAMPLE_SIZE).max(1);
+    let sample = expected
+        .into_iter()
+        .step_by(step)
+        .take(SAMPLE_SIZE)
+        .collect::<Vec<_>>();
+
+    let mut mismatches = Vec::new();
+    for (hostname, addresses) in &sample {
+        progress.check()?;
+        progress.step(&format!("Resolving {}", hostname));
+
+        let resolved = match (*hostname, 0).to_socket_addrs() {
+            Ok(resolved) => resolved
+                .map(|address| address.ip())
+                .collect::<HashSet<_>>()
+                .into_iter()
+                .collect::<Vec<_>>(),
+            Err(_) => Vec::new(),
+        };
+        if !resolved.iter().any(|address| addresses.contains(address)) {
+            mismatches.push(Mismatch {
+                hostname: hostname.to_string(),
+                expected: addresses.clone(),
+                resolved,
+            });
+        }
+    }
+
+    Ok(Report {
+        path: target.path().display().to_string(),
+        checked: sample.len(),
+        mismatches,
+    })
+}
@@ -0,0 +1,163 @@
+use crate::error::ErrorKind;
+use crate::CURRENT_VERSION;
+use serde::Deserialize;
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt;
+
+/// The last release versioned by its build time, every later one has a semantic version
+pub const LAST_TIMESTAMP_RELEASE: u64 = 202011120913;
+
+/// Either the legacy build timestamp (e.g. 202011120913) or a semantic version
+#[derive(Deserialize, Clone, PartialEq, Eq)]
+#[serde(try_from = "RawVersion")]
+pub enum Version {
+    Timestamp(u64),
+    Semantic(semver::Version),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawVersion {
+    Number(u64),
+    Text(String),
+}
+
+impl TryFrom<RawVersion> for Version {
+    type Error = String;
+
+    fn try_from(raw: RawVersion) -> Result<Self, Self::Error> {
+        match raw {
+            RawVersion::Number(timestamp) => Ok(Version::Timestamp(timestamp)),
+            RawVersion::Text(text) => Version::parse(&text).map_err(|err| err.to_string()),
+        }
+    }
+}
+
+impl Version {
+    pub fn parse(text: &str) -> Result<Version, ErrorKind> {
+        let text = text.trim();
+        if !text.is_empty() && text.chars().all(|c| c.is_ascii_digit()) {
+            return match text.parse::<u64>() {
+                Ok(timestamp) => Ok(Version::Timestamp(timestamp)),
+                Err(_) => Err(ErrorKind::String(format!("Invalid version {}", text))),
+            };
+        }
+
+        match semver::Version::parse(text.trim_start_matches('v')) {
+            Ok(version) => Ok(Version::Semantic(version)),
+            Err(err) => Err(ErrorKind::String(format!(
+                "Invalid version {}: {}",
+                text, err
+            ))),
+        }
+    }
+
+    /// The version of this binary
+    pub fn current() -> Version {
+        // The constant comes from Cargo.toml, which only accepts semantic versions
+        Version::parse(CURRENT_VERSION).unwrap_or(Version::Timestamp(0))
+    }
+
+    /// Whether this is one of the releases versioned by their build time
+    pub fn is_legacy(&self) -> bool {
+        match self {
+            Version::Timestamp(timestamp) => *timestamp <= LAST_TIMESTAMP_RELEASE,
+            Version::Semantic(_) => false,
+        }
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Version::Timestamp(a), Version::Timestamp(b)) => a.cmp(b),
+            (Version::Semantic(a), Version::Semantic(b)) => a.cmp(b),
+            // Semantic versions were introduced after the last timestamp release
+            (Version::Timestamp(_), Version::Semantic(_)) => Ordering::Less,
+            (Version::Semantic(_), Version::Timestamp(_)) => Ordering::Greater,
+        }
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Version::Timestamp(timestamp) => write!(f, "{}", timestamp),
+            Version::Semantic(version) => write!(f, "{}", version),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(text: &str) -> Version {
+        Version::parse(text).unwrap()
+    }
+
+    #[test]
+    fn parses_both_kinds() {
+        assert!(version("202011120913") == Version::Timestamp(202011120913));
+        assert!(version(" v1.2.3 ") == Version::Semantic(semver::Version::new(1, 2, 3)));
+        assert_eq!(version("1.2.3-beta.1").to_string(), "1.2.3-beta.1");
+        for text in &["", "v", "1.2", "latest", "99999999999999999999999"] {
+            match Version::parse(text) {
+                Err(ErrorKind::String(_)) => {}
+                result => panic!("unexpected result {:?}", result.map(|_| ())),
+            }
+        }
+
+        // latest.json names the legacy releases with a number, the others with a string
+        let versions: Vec<Version> =
+            serde_json::from_str(r#"[202011120913, "202011120913", "1.0.0"]"#).unwrap();
+        assert!(versions[0] == Version::Timestamp(202011120913));
+        assert!(versions[1] == versions[0]);
+        assert!(versions[2] == version("1.0.0"));
+        assert!(serde_json::from_str::<Version>(r#""1.0""#).is_err());
+    }
+
+    #[test]
+    fn orders_the_versions() {
+        let ordered = [
+            "201901010000",
+            "202011120913",
+            "0.1.0",
+            "1.0.0-alpha",
+            "1.0.0-alpha.2",
+            "1.0.0-alpha.10",
+            "1.0.0-beta",
+            "1.0.0-rc.1",
+            "1.0.0",
+            "1.0.1",
+            "1.10.0",
+        ];
+        for pair in ordered.windows(2) {
+            assert!(
+                version(pair[0]) < version(pair[1]),
+                "{} < {}",
+                pair[0],
+                pair[1]
+            );
+        }
+        // Whatever its number, a timestamp comes before the semantic versions
+        assert!(Version::Timestamp(u64::MAX) < version("0.0.1"));
+        assert!(version("v1.0.0") == version("1.0.0"));
+    }
+
+    #[test]
+    fn only_timestamp_releases_are_legacy() {
+        assert!(version("201901010000").is_legacy());
+        assert!(Version::Timestamp(LAST_TIMESTAMP_RELEASE).is_legacy());
+        assert!(!Version::Timestamp(LAST_TIMESTAMP_RELEASE + 1).is_legacy());
+        assert!(!version("0.1.0").is_legacy());
+        assert!(!Version::current().is_legacy());
+    }
+}